@@ -1,140 +1,2546 @@
-use anyhow::anyhow;
-use std::str::FromStr;
+use bytes::Bytes;
+use std::time::Duration;
 
+mod args;
 mod command_handler;
+mod error;
 mod response;
 
-pub use command_handler::CommandHandler;
+use args::{parse_float, parse_int, to_string, Args};
+pub use command_handler::{CommandHandler, Reply};
+pub use error::CommandError;
 
-use crate::resp::{Parser, Resp};
+use crate::pubsub::SubscriptionKind;
+use crate::resp::Resp;
+use crate::slot::key_hash_slot;
+use crate::store::{
+    now_millis, parse_f64, Aggregate, Claim, ClaimOptions, ExpireCondition, LexBound, LexRange,
+    ListEnd, NewStreamId, PendingRange, ScoreBound, ScoreRange, SetCondition, SetOp, StreamId,
+    StreamReadOp, StreamTrim, ZAddFlags, ZRangeBy,
+};
 
 pub enum Command {
     Ping,
-    Echo(String),
+    Echo(Bytes),
     Set {
-        key: String,
-        value: String,
-        expiry: Option<u64>,
+        key: Bytes,
+        value: Bytes,
+        expiry: Option<Expiry>,
+        condition: Option<SetCondition>,
+        keep_ttl: bool,
+        get: bool,
+    },
+    Get(Bytes),
+    Expire {
+        key: Bytes,
+        expiry: Expiry,
+        condition: ExpireCondition,
+    },
+    Ttl {
+        key: Bytes,
+        millis: bool,
+        absolute: bool,
+    },
+    Persist(Bytes),
+    Del(Vec<Bytes>),
+    Exists(Vec<Bytes>),
+    Touch(Vec<Bytes>),
+    Type(Bytes),
+    Rename {
+        key: Bytes,
+        new_key: Bytes,
+        nx: bool,
+    },
+    Append {
+        key: Bytes,
+        value: Bytes,
+    },
+    Strlen(Bytes),
+    GetRange {
+        key: Bytes,
+        start: i64,
+        end: i64,
+    },
+    SetRange {
+        key: Bytes,
+        offset: i64,
+        value: Bytes,
+    },
+    GetDel(Bytes),
+    GetEx {
+        key: Bytes,
+        expiry: Option<Expiry>,
+        persist: bool,
+    },
+    MGet(Vec<Bytes>),
+    MSet {
+        pairs: Vec<(Bytes, Bytes)>,
+        nx: bool,
+    },
+    Push {
+        key: Bytes,
+        values: Vec<Bytes>,
+        end: ListEnd,
+        only_existing: bool,
+    },
+    Pop {
+        key: Bytes,
+        end: ListEnd,
+        count: Option<usize>,
+    },
+    LRange {
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    LLen(Bytes),
+    LIndex {
+        key: Bytes,
+        index: i64,
+    },
+    LSet {
+        key: Bytes,
+        index: i64,
+        value: Bytes,
+    },
+    LRem {
+        key: Bytes,
+        count: i64,
+        element: Bytes,
+    },
+    LTrim {
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    LInsert {
+        key: Bytes,
+        before: bool,
+        pivot: Bytes,
+        element: Bytes,
+    },
+    LPos {
+        key: Bytes,
+        element: Bytes,
+        rank: i64,
+        count: Option<usize>,
+        maxlen: usize,
+    },
+    LMove {
+        source: Bytes,
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    },
+    BPop {
+        keys: Vec<Bytes>,
+        end: ListEnd,
+        timeout: Option<Duration>,
+    },
+    MPop {
+        keys: Vec<Bytes>,
+        end: ListEnd,
+        count: usize,
+        block: bool,
+        timeout: Option<Duration>,
+    },
+    BLMove {
+        source: Bytes,
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+        timeout: Option<Duration>,
+    },
+    HSet {
+        key: Bytes,
+        pairs: Vec<(Bytes, Bytes)>,
+        multi: bool,
+    },
+    HSetNx {
+        key: Bytes,
+        field: Bytes,
+        value: Bytes,
+    },
+    HGet {
+        key: Bytes,
+        field: Bytes,
+    },
+    HMGet {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    HDel {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    HGetAll(Bytes),
+    HKeys(Bytes),
+    HVals(Bytes),
+    HLen(Bytes),
+    HExists {
+        key: Bytes,
+        field: Bytes,
+    },
+    HStrlen {
+        key: Bytes,
+        field: Bytes,
+    },
+    HIncrBy {
+        key: Bytes,
+        field: Bytes,
+        increment: i64,
+    },
+    HIncrByFloat {
+        key: Bytes,
+        field: Bytes,
+        increment: f64,
+    },
+    HRandField {
+        key: Bytes,
+        count: Option<i64>,
+        with_values: bool,
+    },
+    HScan {
+        key: Bytes,
+        cursor: u64,
+        pattern: Option<Bytes>,
+        count: usize,
+        no_values: bool,
+    },
+    HExpire {
+        key: Bytes,
+        expiry: Expiry,
+        condition: ExpireCondition,
+        fields: Vec<Bytes>,
+    },
+    HTtl {
+        key: Bytes,
+        fields: Vec<Bytes>,
+        millis: bool,
+        absolute: bool,
+    },
+    HPersist {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    SAdd {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SRem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SMembers(Bytes),
+    SIsMember {
+        key: Bytes,
+        member: Bytes,
+    },
+    SMIsMember {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SCard(Bytes),
+    SPop {
+        key: Bytes,
+        count: Option<usize>,
+    },
+    SRandMember {
+        key: Bytes,
+        count: Option<i64>,
+    },
+    SetOp {
+        op: SetOp,
+        keys: Vec<Bytes>,
+    },
+    SetOpStore {
+        op: SetOp,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    SInterCard {
+        keys: Vec<Bytes>,
+        limit: usize,
+    },
+    SMove {
+        source: Bytes,
+        destination: Bytes,
+        member: Bytes,
+    },
+    SScan {
+        key: Bytes,
+        cursor: u64,
+        pattern: Option<Bytes>,
+        count: usize,
+    },
+    ZAdd {
+        key: Bytes,
+        pairs: Vec<(f64, Bytes)>,
+        flags: ZAddFlags,
+    },
+    ZRem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    ZScore {
+        key: Bytes,
+        member: Bytes,
+    },
+    ZMScore {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    ZCard(Bytes),
+    ZRank {
+        key: Bytes,
+        member: Bytes,
+        rev: bool,
+        with_score: bool,
+    },
+    ZRange {
+        key: Bytes,
+        by: ZRangeBy,
+        rev: bool,
+        limit: Option<(i64, i64)>,
+        with_scores: bool,
+        destination: Option<Bytes>,
+    },
+    ZCount {
+        key: Bytes,
+        range: ScoreRange,
+    },
+    ZLexCount {
+        key: Bytes,
+        range: LexRange,
+    },
+    ZPop {
+        key: Bytes,
+        count: Option<usize>,
+        max: bool,
+    },
+    ZCombineStore {
+        destination: Bytes,
+        keys: Vec<Bytes>,
+        weights: Vec<f64>,
+        aggregate: Aggregate,
+        inter: bool,
+    },
+    ZRandMember {
+        key: Bytes,
+        count: Option<i64>,
+        with_scores: bool,
+    },
+    XAdd {
+        key: Bytes,
+        id: NewStreamId,
+        fields: Vec<(Bytes, Bytes)>,
+        no_create: bool,
+        trim: Option<(StreamTrim, Option<usize>)>,
+    },
+    XRange {
+        key: Bytes,
+        start: StreamId,
+        end: StreamId,
+        count: usize,
+        rev: bool,
+    },
+    XRead {
+        keys: Vec<Bytes>,
+        op: StreamReadOp,
+        count: usize,
+        /// How long to block for, if at all, where `Some(None)` is forever
+        block: Option<Option<Duration>>,
+    },
+    XLen(Bytes),
+    XDel {
+        key: Bytes,
+        ids: Vec<StreamId>,
+    },
+    XTrim {
+        key: Bytes,
+        trim: StreamTrim,
+        limit: Option<usize>,
+    },
+    XGroup(XGroup),
+    XAck {
+        key: Bytes,
+        group: Bytes,
+        ids: Vec<StreamId>,
+    },
+    XPending {
+        key: Bytes,
+        group: Bytes,
+        /// The entries to list, or `None` for a summary
+        range: Option<PendingRange>,
+    },
+    XClaim {
+        key: Bytes,
+        claim: Claim,
+        ids: Vec<StreamId>,
+        options: ClaimOptions,
+    },
+    XAutoClaim {
+        key: Bytes,
+        claim: Claim,
+        start: StreamId,
+        count: usize,
+    },
+    XInfo(XInfo),
+    Subscribe {
+        kind: SubscriptionKind,
+        channels: Vec<Bytes>,
+    },
+    /// Unsubscribes from `channels`, or from every channel of the kind if
+    /// there are none
+    Unsubscribe {
+        kind: SubscriptionKind,
+        channels: Vec<Bytes>,
+    },
+    Publish {
+        channel: Bytes,
+        message: Bytes,
+        sharded: bool,
+    },
+    PubSub(PubSub),
+    Keys(Bytes),
+    IncrBy {
+        key: Bytes,
+        increment: i64,
+    },
+    IncrByFloat {
+        key: Bytes,
+        increment: f64,
+    },
+    Scan {
+        cursor: u64,
+        pattern: Option<Bytes>,
+        count: usize,
+        key_type: Option<String>,
     },
-    Get(String),
     Info(Option<String>),
     ReplConf(ReplConf),
     Psync {
         replica_id: String,
         offset: i32,
     },
+    Hello {
+        protover: Option<u32>,
+        auth: Option<(Bytes, Bytes)>,
+        setname: Option<Bytes>,
+    },
+}
+
+/// An expiry time given as a relative or absolute time in seconds or
+/// milliseconds, as in `SET`'s `EX`/`PX`/`EXAT`/`PXAT` options or the
+/// `EXPIRE` family of commands
+#[derive(Clone, Copy)]
+pub enum Expiry {
+    Ex(i64),
+    Px(i64),
+    ExAt(i64),
+    PxAt(i64),
+}
+
+impl Expiry {
+    /// Creates an expiry from the time following one of the
+    /// `EX`/`PX`/`EXAT`/`PXAT` options
+    fn new(option: &str, time: i64) -> Result<Self, CommandError> {
+        match option {
+            "ex" => Ok(Expiry::Ex(time)),
+            "px" => Ok(Expiry::Px(time)),
+            "exat" => Ok(Expiry::ExAt(time)),
+            "pxat" => Ok(Expiry::PxAt(time)),
+            _ => Err(CommandError::Syntax),
+        }
+    }
+
+    /// The name of the expire command taking this kind of time
+    pub fn command_name(&self) -> &'static str {
+        match self {
+            Expiry::Ex(_) => "expire",
+            Expiry::Px(_) => "pexpire",
+            Expiry::ExAt(_) => "expireat",
+            Expiry::PxAt(_) => "pexpireat",
+        }
+    }
+
+    /// The absolute expiry time in milliseconds since the UNIX epoch. Times
+    /// in the past are clamped to the epoch, and `None` means the time
+    /// overflowed.
+    pub fn deadline(&self) -> Option<u128> {
+        let now = now_millis() as i64;
+
+        let deadline = match *self {
+            Expiry::Ex(secs) => secs.checked_mul(1000)?.checked_add(now)?,
+            Expiry::Px(millis) => millis.checked_add(now)?,
+            Expiry::ExAt(secs) => secs.checked_mul(1000)?,
+            Expiry::PxAt(millis) => millis,
+        };
+
+        Some(deadline.max(0) as u128)
+    }
+}
+
+/// Parses the `LEFT`/`RIGHT` argument of `LMOVE` and friends
+fn parse_list_end(token: &[u8]) -> Result<ListEnd, CommandError> {
+    match to_string(token).to_lowercase().as_str() {
+        "left" => Ok(ListEnd::Left),
+        "right" => Ok(ListEnd::Right),
+        _ => Err(CommandError::Syntax),
+    }
+}
+
+/// Parses the timeout of a blocking command, in seconds. A timeout of 0
+/// blocks forever, which is `None`.
+fn parse_timeout(token: &[u8]) -> Result<Option<Duration>, CommandError> {
+    let timeout = parse_float(token)
+        .map_err(|_| CommandError::Other("timeout is not a float or out of range".to_owned()))?;
+
+    if timeout < 0.0 {
+        return Err(CommandError::Other("timeout is negative".to_owned()));
+    }
+
+    if timeout == 0.0 {
+        return Ok(None);
+    }
+
+    Duration::try_from_secs_f64(timeout)
+        .map(Some)
+        .map_err(|_| CommandError::Other("timeout is out of range".to_owned()))
+}
+
+/// Parses a `SCAN` family cursor
+fn parse_cursor(token: &[u8]) -> Result<u64, CommandError> {
+    to_string(token)
+        .parse::<u64>()
+        .map_err(|_| CommandError::Other("invalid cursor".to_owned()))
+}
+
+/// Parses the count of `HRANDFIELD` and friends. Like Redis, negative
+/// counts are capped, since that many elements are replied with.
+fn parse_random_count(token: &[u8]) -> Result<i64, CommandError> {
+    let count = parse_int::<i64>(token)?;
+
+    if count < -(i64::MAX / 2) {
+        return Err(CommandError::Other("value is out of range".to_owned()));
+    }
+
+    Ok(count)
+}
+
+/// Parses the value of `option` if it's one of the `MATCH` and `COUNT`
+/// options shared by the `SCAN` family. Returns whether it was.
+fn parse_scan_option(
+    option: &str,
+    args: &mut Args,
+    pattern: &mut Option<Bytes>,
+    count: &mut usize,
+) -> Result<bool, CommandError> {
+    match option {
+        "match" => *pattern = Some(args.option_value()?),
+        "count" => {
+            *count = parse_int::<usize>(&args.option_value()?)?;
+
+            if *count < 1 {
+                return Err(CommandError::Syntax);
+            }
+        }
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+/// Splits `tokens` into field-value pairs, failing if one is missing its
+/// value
+fn to_pairs(tokens: Vec<Bytes>, args: &Args) -> Result<Vec<(Bytes, Bytes)>, CommandError> {
+    if !tokens.len().is_multiple_of(2) {
+        return Err(args.wrong_arity());
+    }
+
+    Ok(tokens
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
+}
+
+/// Parses the `FIELDS numfields field [field ...]` arguments of the hash
+/// field expiry commands
+fn parse_fields(args: &mut Args) -> Result<Vec<Bytes>, CommandError> {
+    if args.next_keyword().as_deref() != Some("fields") {
+        return Err(CommandError::Other(
+            "Mandatory argument FIELDS is missing or not at the right position".to_owned(),
+        ));
+    }
+
+    let numfields = args.next_int::<i64>()?;
+
+    if numfields <= 0 {
+        return Err(CommandError::Other(
+            "Parameter `numFields` should be greater than 0".to_owned(),
+        ));
+    }
+
+    let fields = args.rest()?;
+
+    if fields.len() as i64 != numfields {
+        return Err(CommandError::Other(
+            "The `numfields` parameter must match the number of arguments".to_owned(),
+        ));
+    }
+
+    Ok(fields)
+}
+
+/// Parses one end of a score range, e.g. `1.5`, `(1.5` or `-inf`
+fn parse_score_bound(token: &[u8]) -> Result<ScoreBound, CommandError> {
+    let (score, exclusive) = match token.strip_prefix(b"(") {
+        Some(score) => (score, true),
+        None => (token, false),
+    };
+
+    let score = parse_f64(score)
+        .ok_or_else(|| CommandError::Other("min or max is not a float".to_owned()))?;
+
+    Ok(ScoreBound { score, exclusive })
+}
+
+/// Parses one end of a lexicographical range, e.g. `[a`, `(a`, `-` or `+`
+fn parse_lex_bound(token: &Bytes) -> Result<LexBound, CommandError> {
+    match token.first() {
+        Some(b'-') if token.len() == 1 => Ok(LexBound::NegInf),
+        Some(b'+') if token.len() == 1 => Ok(LexBound::PosInf),
+        Some(b'[') => Ok(LexBound::Inclusive(token.slice(1..))),
+        Some(b'(') => Ok(LexBound::Exclusive(token.slice(1..))),
+        _ => Err(CommandError::Other(
+            "min or max not valid string range item".to_owned(),
+        )),
+    }
+}
+
+/// Parses `ZRANGE`, `ZRANGESTORE` and the older range commands they replace
+fn parse_zrange(cmd_name: &str, args: &mut Args) -> Result<Command, CommandError> {
+    let destination = match cmd_name {
+        "zrangestore" => Some(args.next()?),
+        _ => None,
+    };
+
+    let key = args.next()?;
+    let start = args.next()?;
+    let stop = args.next()?;
+
+    // The older commands imply what ZRANGE takes as options
+    let unified = matches!(cmd_name, "zrange" | "zrangestore");
+    let mut by_score = cmd_name.ends_with("byscore");
+    let mut by_lex = cmd_name.ends_with("bylex");
+    let mut rev = cmd_name.starts_with("zrev");
+    let mut limit = None;
+    let mut with_scores = false;
+
+    while let Some(option) = args.next_keyword() {
+        match option.as_str() {
+            "byscore" if unified => by_score = true,
+            "bylex" if unified => by_lex = true,
+            "rev" if unified => rev = true,
+            "withscores" if cmd_name != "zrangestore" => with_scores = true,
+            "limit" => {
+                let offset = parse_int::<i64>(&args.option_value()?)?;
+                let count = parse_int::<i64>(&args.option_value()?)?;
+                limit = Some((offset, count));
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+
+    if by_score && by_lex {
+        return Err(CommandError::Syntax);
+    }
+
+    if limit.is_some() && !by_score && !by_lex {
+        return Err(CommandError::Other(
+            "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                .to_owned(),
+        ));
+    }
+
+    if with_scores && by_lex {
+        return Err(CommandError::Other(
+            "syntax error, WITHSCORES not supported in combination with BYLEX".to_owned(),
+        ));
+    }
+
+    // Reversed score and lex ranges are given from the top down
+    let (min, max) = if rev {
+        (&stop, &start)
+    } else {
+        (&start, &stop)
+    };
+
+    let by = if by_score {
+        ZRangeBy::Score(ScoreRange {
+            min: parse_score_bound(min)?,
+            max: parse_score_bound(max)?,
+        })
+    } else if by_lex {
+        ZRangeBy::Lex(LexRange {
+            min: parse_lex_bound(min)?,
+            max: parse_lex_bound(max)?,
+        })
+    } else {
+        ZRangeBy::Rank(parse_int(&start)?, parse_int(&stop)?)
+    };
+
+    Ok(Command::ZRange {
+        key,
+        by,
+        rev,
+        limit,
+        with_scores,
+        destination,
+    })
+}
+
+fn invalid_stream_id() -> CommandError {
+    CommandError::Other("Invalid stream ID specified as stream command argument".to_owned())
+}
+
+/// Parses a stream ID, where a missing sequence number is `default_seq`
+fn parse_stream_id(token: &[u8], default_seq: u64) -> Result<StreamId, CommandError> {
+    StreamId::parse(token, default_seq).ok_or_else(invalid_stream_id)
+}
+
+/// Parses the ID given to `XADD`, e.g. `*`, `1-*` or `1-1`
+fn parse_new_stream_id(token: &[u8]) -> Result<NewStreamId, CommandError> {
+    if token == b"*" {
+        return Ok(NewStreamId::Auto);
+    }
+
+    match token.strip_suffix(b"-*") {
+        Some(ms) => Ok(NewStreamId::AutoSeq(
+            parse_int(ms).map_err(|_| invalid_stream_id())?,
+        )),
+        None => Ok(NewStreamId::Explicit(parse_stream_id(token, 0)?)),
+    }
+}
+
+/// Parses one end of a stream range, e.g. `-`, `1`, `1-1` or `(1-1`. A
+/// missing sequence number covers the whole millisecond.
+fn parse_stream_bound(token: &[u8], start: bool) -> Result<StreamId, CommandError> {
+    match token {
+        b"-" => return Ok(StreamId::MIN),
+        b"+" => return Ok(StreamId::MAX),
+        _ => {}
+    }
+
+    let default_seq = if start { 0 } else { u64::MAX };
+
+    let Some(token) = token.strip_prefix(b"(") else {
+        return parse_stream_id(token, default_seq);
+    };
+
+    let id = parse_stream_id(token, default_seq)?;
+    let (id, end_name) = if start {
+        (id.next(), "start")
+    } else {
+        (id.prev(), "end")
+    };
+
+    id.ok_or_else(|| CommandError::Other(format!("invalid {} ID for the interval", end_name)))
+}
+
+/// Parses the ID `XGROUP CREATE` and `XGROUP SETID` set, where `$` is
+/// `None`, along with the `ENTRIESREAD` option, where `-1` is `None`
+fn parse_group_id(
+    args: &mut Args,
+    mut make_stream: Option<&mut bool>,
+) -> Result<(Option<StreamId>, Option<u64>), CommandError> {
+    let id = match &args.next()?[..] {
+        b"$" => None,
+        id => Some(parse_stream_id(id, 0)?),
+    };
+
+    let mut entries_read = None;
+
+    while let Some(option) = args.next_keyword() {
+        match (option.as_str(), make_stream.as_deref_mut()) {
+            ("mkstream", Some(make_stream)) => *make_stream = true,
+            ("entriesread", _) => {
+                let value = parse_int::<i64>(&args.option_value()?)?;
+
+                if value < -1 {
+                    return Err(CommandError::Other(
+                        "value for ENTRIESREAD must be positive or -1".to_owned(),
+                    ));
+                }
+
+                entries_read = u64::try_from(value).ok();
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+
+    Ok((id, entries_read))
+}
+
+/// Parses the `min-idle-time` of `XCLAIM` and `XAUTOCLAIM`
+fn parse_min_idle(token: &[u8], cmd_name: &str) -> Result<u128, CommandError> {
+    let min_idle = parse_int::<i64>(token).map_err(|_| {
+        CommandError::Other(format!(
+            "Invalid min-idle-time argument for {}",
+            cmd_name.to_uppercase()
+        ))
+    })?;
+
+    Ok(min_idle.max(0) as u128)
+}
+
+/// Parses the `MAXLEN|MINID [=|~] threshold [LIMIT count]` arguments of
+/// `XADD` and `XTRIM`, following the `MAXLEN` or `MINID` in `strategy`
+fn parse_stream_trim(
+    strategy: &str,
+    args: &mut Args,
+) -> Result<(StreamTrim, Option<usize>), CommandError> {
+    let mut threshold = args.option_value()?;
+    let mut approximate = false;
+
+    if matches!(&threshold[..], b"=" | b"~") {
+        approximate = &threshold[..] == b"~";
+        threshold = args.option_value()?;
+    }
+
+    let trim = match strategy {
+        "maxlen" => {
+            let max_len = parse_int::<i64>(&threshold)?;
+
+            if max_len < 0 {
+                return Err(CommandError::Other(
+                    "The MAXLEN argument must be >= 0.".to_owned(),
+                ));
+            }
+
+            StreamTrim::MaxLen(max_len as usize)
+        }
+        _ => StreamTrim::MinId(parse_stream_id(&threshold, 0)?),
+    };
+
+    let limit = match args.peek_keyword().as_deref() {
+        Some("limit") => {
+            args.next()?;
+            let limit = parse_int::<i64>(&args.option_value()?)?;
+
+            if limit < 0 {
+                return Err(CommandError::Other(
+                    "The LIMIT argument must be >= 0.".to_owned(),
+                ));
+            }
+
+            if !approximate {
+                return Err(CommandError::Other(
+                    "syntax error, LIMIT cannot be used without the special ~ option".to_owned(),
+                ));
+            }
+
+            Some(limit as usize)
+        }
+        _ => None,
+    };
+
+    Ok((trim, limit))
+}
+
+/// Fails unless all of `channels` hash to the same slot, as the shard
+/// channels a sharded pub/sub command names have to live on one shard
+fn check_same_slot(channels: &[Bytes]) -> Result<(), CommandError> {
+    let mut slots = channels.iter().map(|channel| key_hash_slot(channel));
+
+    match slots.next() {
+        Some(slot) if slots.any(|x| x != slot) => Err(CommandError::CrossSlot),
+        _ => Ok(()),
+    }
+}
+
+/// The `XGROUP` subcommands, where an ID of `None` is `$`
+pub enum XGroup {
+    Create {
+        key: Bytes,
+        group: Bytes,
+        id: Option<StreamId>,
+        make_stream: bool,
+        entries_read: Option<u64>,
+    },
+    SetId {
+        key: Bytes,
+        group: Bytes,
+        id: Option<StreamId>,
+        entries_read: Option<u64>,
+    },
+    Destroy {
+        key: Bytes,
+        group: Bytes,
+    },
+    CreateConsumer {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+    },
+    DelConsumer {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+    },
 }
 
-pub enum ReplConf {
-    ListeningPort(u32),
-    Capa(Vec<String>),
-}
+pub enum XInfo {
+    Stream(Bytes),
+    Groups(Bytes),
+    Consumers { key: Bytes, group: Bytes },
+}
+
+pub enum PubSub {
+    Channels(Option<Bytes>),
+    NumSub(Vec<Bytes>),
+    NumPat,
+    ShardChannels(Option<Bytes>),
+    ShardNumSub(Vec<Bytes>),
+}
+
+pub enum ReplConf {
+    ListeningPort(u32),
+    Capa(Vec<String>),
+}
+
+impl TryFrom<Resp> for Command {
+    type Error = CommandError;
+
+    fn try_from(resp: Resp) -> Result<Self, Self::Error> {
+        let Resp::Array(array) = resp else {
+            return Err(CommandError::Protocol("expected an array".to_owned()));
+        };
+
+        let tokens = array
+            .into_iter()
+            .map(|token| match token {
+                Resp::BulkString(s) => Ok(s),
+                Resp::SimpleString(s) => Ok(s.into()),
+                _ => Err(CommandError::Protocol("expected a bulk string".to_owned())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (cmd_name, tokens) = tokens
+            .split_first()
+            .ok_or(CommandError::Protocol("no command specified".to_owned()))?;
+
+        let original_name = to_string(cmd_name);
+        let cmd_name = original_name.to_lowercase();
+        let mut args = Args::new(&cmd_name, tokens.to_vec());
+
+        let command = match cmd_name.as_str() {
+            "ping" => Command::Ping,
+            "echo" => {
+                let arg = args.next()?;
+                Command::Echo(arg)
+            }
+            "set" => {
+                let key = args.next()?;
+                let value = args.next()?;
+
+                let mut expiry = None;
+                let mut condition = None;
+                let mut keep_ttl = false;
+                let mut get = false;
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "nx" if condition != Some(SetCondition::IfExists) => {
+                            condition = Some(SetCondition::IfNotExists)
+                        }
+                        "xx" if condition != Some(SetCondition::IfNotExists) => {
+                            condition = Some(SetCondition::IfExists)
+                        }
+                        "keepttl" if expiry.is_none() => keep_ttl = true,
+                        "get" => get = true,
+                        "ex" | "px" | "exat" | "pxat" if !keep_ttl && expiry.is_none() => {
+                            let time = parse_int::<i64>(&args.option_value()?)?;
+
+                            // Redis only accepts positive times here
+                            if time <= 0 {
+                                return Err(CommandError::InvalidExpireTime(cmd_name));
+                            }
+
+                            expiry = Some(Expiry::new(&option, time)?);
+                        }
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
+
+                Command::Set {
+                    key,
+                    value,
+                    expiry,
+                    condition,
+                    keep_ttl,
+                    get,
+                }
+            }
+            "get" => {
+                let key = args.next()?;
+                Command::Get(key)
+            }
+            "append" => {
+                let key = args.next()?;
+                let value = args.next()?;
+                Command::Append { key, value }
+            }
+            "strlen" => {
+                let key = args.next()?;
+                Command::Strlen(key)
+            }
+            "getrange" | "substr" => {
+                let key = args.next()?;
+                let start = args.next_int::<i64>()?;
+                let end = args.next_int::<i64>()?;
+                Command::GetRange { key, start, end }
+            }
+            "setrange" => {
+                let key = args.next()?;
+                let offset = args.next_int::<i64>()?;
+                let value = args.next()?;
+                Command::SetRange { key, offset, value }
+            }
+            "getdel" => {
+                let key = args.next()?;
+                Command::GetDel(key)
+            }
+            "getex" => {
+                let key = args.next()?;
+
+                let mut expiry = None;
+                let mut persist = false;
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "persist" if expiry.is_none() => persist = true,
+                        "ex" | "px" | "exat" | "pxat" if !persist && expiry.is_none() => {
+                            let time = parse_int::<i64>(&args.option_value()?)?;
+
+                            if time <= 0 {
+                                return Err(CommandError::InvalidExpireTime(cmd_name));
+                            }
+
+                            expiry = Some(Expiry::new(&option, time)?);
+                        }
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
+
+                Command::GetEx {
+                    key,
+                    expiry,
+                    persist,
+                }
+            }
+            "mget" => Command::MGet(args.rest()?),
+            "mset" | "msetnx" => {
+                let pairs = to_pairs(args.rest()?, &args)?;
+
+                Command::MSet {
+                    pairs,
+                    nx: cmd_name == "msetnx",
+                }
+            }
+            "expire" | "pexpire" | "expireat" | "pexpireat" => {
+                let key = args.next()?;
+                let time = args.next_int::<i64>()?;
+
+                let option = match cmd_name.as_str() {
+                    "expire" => "ex",
+                    "pexpire" => "px",
+                    "expireat" => "exat",
+                    _ => "pxat",
+                };
+
+                let expiry = Expiry::new(option, time)?;
+
+                let mut condition = ExpireCondition::default();
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "nx" => condition.nx = true,
+                        "xx" => condition.xx = true,
+                        "gt" => condition.gt = true,
+                        "lt" => condition.lt = true,
+                        _ => {
+                            return Err(CommandError::Other(format!(
+                                "Unsupported option {}",
+                                option
+                            )))
+                        }
+                    }
+                }
+
+                if condition.nx && (condition.xx || condition.gt || condition.lt) {
+                    return Err(CommandError::Other(
+                        "NX and XX, GT or LT options at the same time are not compatible"
+                            .to_owned(),
+                    ));
+                }
+
+                if condition.gt && condition.lt {
+                    return Err(CommandError::Other(
+                        "GT and LT options at the same time are not compatible".to_owned(),
+                    ));
+                }
+
+                Command::Expire {
+                    key,
+                    expiry,
+                    condition,
+                }
+            }
+            "ttl" | "pttl" | "expiretime" | "pexpiretime" => {
+                let key = args.next()?;
+
+                Command::Ttl {
+                    key,
+                    millis: cmd_name.starts_with('p'),
+                    absolute: cmd_name.ends_with("expiretime"),
+                }
+            }
+            "persist" => {
+                let key = args.next()?;
+                Command::Persist(key)
+            }
+            // Values are dropped right away either way, so UNLINK is DEL
+            "del" | "unlink" => Command::Del(args.rest()?),
+            "exists" => Command::Exists(args.rest()?),
+            "touch" => Command::Touch(args.rest()?),
+            "type" => {
+                let key = args.next()?;
+                Command::Type(key)
+            }
+            "rename" | "renamenx" => {
+                let key = args.next()?;
+                let new_key = args.next()?;
+
+                Command::Rename {
+                    key,
+                    new_key,
+                    nx: cmd_name == "renamenx",
+                }
+            }
+            "incr" | "decr" => {
+                let key = args.next()?;
+                let increment = if cmd_name == "incr" { 1 } else { -1 };
+
+                Command::IncrBy { key, increment }
+            }
+            "incrby" => {
+                let key = args.next()?;
+                let increment = args.next_int::<i64>()?;
+
+                Command::IncrBy { key, increment }
+            }
+            "decrby" => {
+                let key = args.next()?;
+                let increment = args
+                    .next_int::<i64>()?
+                    .checked_neg()
+                    .ok_or(CommandError::Other("decrement would overflow".to_owned()))?;
+
+                Command::IncrBy { key, increment }
+            }
+            "incrbyfloat" => {
+                let key = args.next()?;
+                let increment = args.next_float()?;
+
+                Command::IncrByFloat { key, increment }
+            }
+            "lpush" | "rpush" | "lpushx" | "rpushx" => {
+                let key = args.next()?;
+                let values = args.rest()?;
+
+                Command::Push {
+                    key,
+                    values,
+                    end: if cmd_name.starts_with('l') {
+                        ListEnd::Left
+                    } else {
+                        ListEnd::Right
+                    },
+                    only_existing: cmd_name.ends_with('x'),
+                }
+            }
+            "lpop" | "rpop" => {
+                let key = args.next()?;
+                let count = args
+                    .optional()
+                    .map(|count| {
+                        parse_int::<usize>(&count).map_err(|_| {
+                            CommandError::Other(
+                                "value is out of range, must be positive".to_owned(),
+                            )
+                        })
+                    })
+                    .transpose()?;
+
+                Command::Pop {
+                    key,
+                    end: if cmd_name == "lpop" {
+                        ListEnd::Left
+                    } else {
+                        ListEnd::Right
+                    },
+                    count,
+                }
+            }
+            "lrange" | "ltrim" => {
+                let key = args.next()?;
+                let start = args.next_int::<i64>()?;
+                let stop = args.next_int::<i64>()?;
+
+                if cmd_name == "lrange" {
+                    Command::LRange { key, start, stop }
+                } else {
+                    Command::LTrim { key, start, stop }
+                }
+            }
+            "llen" => {
+                let key = args.next()?;
+                Command::LLen(key)
+            }
+            "lindex" => {
+                let key = args.next()?;
+                let index = args.next_int::<i64>()?;
+                Command::LIndex { key, index }
+            }
+            "lset" => {
+                let key = args.next()?;
+                let index = args.next_int::<i64>()?;
+                let value = args.next()?;
+                Command::LSet { key, index, value }
+            }
+            "lrem" => {
+                let key = args.next()?;
+                let count = args.next_int::<i64>()?;
+                let element = args.next()?;
+                Command::LRem {
+                    key,
+                    count,
+                    element,
+                }
+            }
+            "linsert" => {
+                let key = args.next()?;
+                let before = match args.next_string()?.to_lowercase().as_str() {
+                    "before" => true,
+                    "after" => false,
+                    _ => return Err(CommandError::Syntax),
+                };
+                let pivot = args.next()?;
+                let element = args.next()?;
+
+                Command::LInsert {
+                    key,
+                    before,
+                    pivot,
+                    element,
+                }
+            }
+            "lpos" => {
+                let key = args.next()?;
+                let element = args.next()?;
+
+                let mut rank = 1;
+                let mut count = None;
+                let mut maxlen = 0;
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "rank" => {
+                            rank = parse_int::<i64>(&args.option_value()?)?;
+
+                            if rank == 0 {
+                                return Err(CommandError::Other(
+                                    "RANK can't be zero: use 1 to start from the first match, \
+                                     2 from the second ... or use negative to start from the end \
+                                     of the list"
+                                        .to_owned(),
+                                ));
+                            }
+                        }
+                        "count" => {
+                            let value = parse_int::<i64>(&args.option_value()?)?;
+                            count = Some(usize::try_from(value).map_err(|_| {
+                                CommandError::Other("COUNT can't be negative".to_owned())
+                            })?);
+                        }
+                        "maxlen" => {
+                            let value = parse_int::<i64>(&args.option_value()?)?;
+                            maxlen = usize::try_from(value).map_err(|_| {
+                                CommandError::Other("MAXLEN can't be negative".to_owned())
+                            })?;
+                        }
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
+
+                Command::LPos {
+                    key,
+                    element,
+                    rank,
+                    count,
+                    maxlen,
+                }
+            }
+            "blpop" | "brpop" => {
+                let mut keys = args.rest()?;
+                let timeout = keys.pop().expect("There's at least one argument");
+
+                if keys.is_empty() {
+                    return Err(args.wrong_arity());
+                }
+
+                Command::BPop {
+                    keys,
+                    end: if cmd_name == "blpop" {
+                        ListEnd::Left
+                    } else {
+                        ListEnd::Right
+                    },
+                    timeout: parse_timeout(&timeout)?,
+                }
+            }
+            "lmpop" | "blmpop" => {
+                let block = cmd_name == "blmpop";
+                let timeout = if block {
+                    parse_timeout(&args.next()?)?
+                } else {
+                    None
+                };
+
+                let numkeys = args.next_int::<i64>()?;
+
+                if numkeys <= 0 {
+                    return Err(CommandError::Other(
+                        "numkeys should be greater than 0".to_owned(),
+                    ));
+                }
+
+                let keys = (0..numkeys)
+                    .map(|_| args.next())
+                    .collect::<Result<_, _>>()?;
+                let end = parse_list_end(&args.next()?)?;
+
+                let mut count = 1;
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "count" => {
+                            count = parse_int::<i64>(&args.option_value()?)?
+                                .try_into()
+                                .ok()
+                                .filter(|&count| count > 0)
+                                .ok_or_else(|| {
+                                    CommandError::Other("count should be greater than 0".to_owned())
+                                })?;
+                        }
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
+
+                Command::MPop {
+                    keys,
+                    end,
+                    count,
+                    block,
+                    timeout,
+                }
+            }
+            "blmove" => {
+                let source = args.next()?;
+                let destination = args.next()?;
+                let from = parse_list_end(&args.next()?)?;
+                let to = parse_list_end(&args.next()?)?;
+                let timeout = parse_timeout(&args.next()?)?;
+
+                Command::BLMove {
+                    source,
+                    destination,
+                    from,
+                    to,
+                    timeout,
+                }
+            }
+            "brpoplpush" => {
+                let source = args.next()?;
+                let destination = args.next()?;
+                let timeout = parse_timeout(&args.next()?)?;
+
+                Command::BLMove {
+                    source,
+                    destination,
+                    from: ListEnd::Right,
+                    to: ListEnd::Left,
+                    timeout,
+                }
+            }
+            "lmove" => {
+                let source = args.next()?;
+                let destination = args.next()?;
+                let from = parse_list_end(&args.next()?)?;
+                let to = parse_list_end(&args.next()?)?;
+
+                Command::LMove {
+                    source,
+                    destination,
+                    from,
+                    to,
+                }
+            }
+            "rpoplpush" => {
+                let source = args.next()?;
+                let destination = args.next()?;
+
+                Command::LMove {
+                    source,
+                    destination,
+                    from: ListEnd::Right,
+                    to: ListEnd::Left,
+                }
+            }
+            "hexpire" | "hpexpire" | "hexpireat" | "hpexpireat" => {
+                let key = args.next()?;
+                let time = args.next_int::<i64>()?;
+
+                let option = match cmd_name.as_str() {
+                    "hexpire" => "ex",
+                    "hpexpire" => "px",
+                    "hexpireat" => "exat",
+                    _ => "pxat",
+                };
+
+                let expiry = Expiry::new(option, time)?;
+
+                // At most one condition may come before FIELDS
+                let mut condition = ExpireCondition::default();
+
+                if let Some(flag) = args.peek_keyword().filter(|flag| flag != "fields") {
+                    match flag.as_str() {
+                        "nx" => condition.nx = true,
+                        "xx" => condition.xx = true,
+                        "gt" => condition.gt = true,
+                        "lt" => condition.lt = true,
+                        _ => return Err(CommandError::Syntax),
+                    }
+
+                    args.next()?;
+                }
+
+                let fields = parse_fields(&mut args)?;
+
+                Command::HExpire {
+                    key,
+                    expiry,
+                    condition,
+                    fields,
+                }
+            }
+            "httl" | "hpttl" | "hexpiretime" | "hpexpiretime" => {
+                let key = args.next()?;
+                let fields = parse_fields(&mut args)?;
+
+                Command::HTtl {
+                    key,
+                    fields,
+                    millis: cmd_name.starts_with("hp"),
+                    absolute: cmd_name.ends_with("expiretime"),
+                }
+            }
+            "hpersist" => {
+                let key = args.next()?;
+                let fields = parse_fields(&mut args)?;
+                Command::HPersist { key, fields }
+            }
+            "sadd" | "srem" | "smismember" => {
+                let key = args.next()?;
+                let members = args.rest()?;
+
+                match cmd_name.as_str() {
+                    "sadd" => Command::SAdd { key, members },
+                    "srem" => Command::SRem { key, members },
+                    _ => Command::SMIsMember { key, members },
+                }
+            }
+            "smembers" => {
+                let key = args.next()?;
+                Command::SMembers(key)
+            }
+            "sismember" => {
+                let key = args.next()?;
+                let member = args.next()?;
+                Command::SIsMember { key, member }
+            }
+            "scard" => {
+                let key = args.next()?;
+                Command::SCard(key)
+            }
+            "spop" => {
+                let key = args.next()?;
+                let count = args
+                    .optional()
+                    .map(|count| {
+                        parse_int::<usize>(&count).map_err(|_| {
+                            CommandError::Other(
+                                "value is out of range, must be positive".to_owned(),
+                            )
+                        })
+                    })
+                    .transpose()?;
+
+                Command::SPop { key, count }
+            }
+            "srandmember" => {
+                let key = args.next()?;
+                let count = args
+                    .optional()
+                    .map(|count| parse_random_count(&count))
+                    .transpose()?;
+
+                Command::SRandMember { key, count }
+            }
+            "sinter" | "sunion" | "sdiff" => {
+                let op = match cmd_name.as_str() {
+                    "sinter" => SetOp::Inter,
+                    "sunion" => SetOp::Union,
+                    _ => SetOp::Diff,
+                };
+
+                Command::SetOp {
+                    op,
+                    keys: args.rest()?,
+                }
+            }
+            "sinterstore" | "sunionstore" | "sdiffstore" => {
+                let op = match cmd_name.as_str() {
+                    "sinterstore" => SetOp::Inter,
+                    "sunionstore" => SetOp::Union,
+                    _ => SetOp::Diff,
+                };
+                let destination = args.next()?;
+
+                Command::SetOpStore {
+                    op,
+                    destination,
+                    keys: args.rest()?,
+                }
+            }
+            "sintercard" => {
+                let numkeys = args.next_int::<i64>()?;
+
+                if numkeys <= 0 {
+                    return Err(CommandError::Other(
+                        "numkeys should be greater than 0".to_owned(),
+                    ));
+                }
+
+                let keys = (0..numkeys)
+                    .map(|_| args.next())
+                    .collect::<Result<_, _>>()?;
+
+                let mut limit = 0;
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "limit" => {
+                            let value = parse_int::<i64>(&args.option_value()?)?;
+                            limit = usize::try_from(value).map_err(|_| {
+                                CommandError::Other("LIMIT can't be negative".to_owned())
+                            })?;
+                        }
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
 
-impl FromStr for Command {
-    type Err = anyhow::Error;
+                Command::SInterCard { keys, limit }
+            }
+            "smove" => {
+                let source = args.next()?;
+                let destination = args.next()?;
+                let member = args.next()?;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parser = Parser::new(s);
+                Command::SMove {
+                    source,
+                    destination,
+                    member,
+                }
+            }
+            "sscan" => {
+                let key = args.next()?;
+                let cursor = parse_cursor(&args.next()?)?;
 
-        if let Resp::Array(array) = parser.parse()? {
-            let mut cmd_tokens = array.into_iter().map(|x| x.into_string());
+                let mut pattern = None;
+                let mut count = 10;
 
-            let cmd_name = cmd_tokens
-                .next()
-                .ok_or(anyhow!("No command specified"))?
-                .to_lowercase();
+                while let Some(option) = args.next_keyword() {
+                    if !parse_scan_option(&option, &mut args, &mut pattern, &mut count)? {
+                        return Err(CommandError::Syntax);
+                    }
+                }
 
-            let command = match cmd_name.as_str() {
-                "ping" => Command::Ping,
-                "echo" => {
-                    let arg = cmd_tokens.next().ok_or(anyhow!("No argument to echo"))?;
-                    Command::Echo(arg)
+                Command::SScan {
+                    key,
+                    cursor,
+                    pattern,
+                    count,
                 }
-                "set" => {
-                    let key = cmd_tokens.next().ok_or(anyhow!("No key specified"))?;
-                    let value = cmd_tokens.next().ok_or(anyhow!("No value specified"))?;
+            }
+            "zadd" => {
+                let key = args.next()?;
 
-                    let expiry = cmd_tokens
-                        .next()
-                        .and_then(|px| {
-                            if px.to_lowercase() == "px" {
-                                cmd_tokens.next()
-                            } else {
-                                None
-                            }
+                let mut flags = ZAddFlags::default();
+
+                while let Some(flag) = args.peek_keyword() {
+                    match flag.as_str() {
+                        "nx" => flags.nx = true,
+                        "xx" => flags.xx = true,
+                        "gt" => flags.gt = true,
+                        "lt" => flags.lt = true,
+                        "ch" => flags.ch = true,
+                        "incr" => flags.incr = true,
+                        _ => break,
+                    }
+
+                    args.next()?;
+                }
+
+                let tokens = args.rest()?;
+
+                if !tokens.len().is_multiple_of(2) {
+                    return Err(CommandError::Syntax);
+                }
+
+                if flags.nx && flags.xx {
+                    return Err(CommandError::Other(
+                        "XX and NX options at the same time are not compatible".to_owned(),
+                    ));
+                }
+
+                if (flags.gt && flags.lt) || ((flags.gt || flags.lt) && flags.nx) {
+                    return Err(CommandError::Other(
+                        "GT, LT, and/or NX options at the same time are not compatible".to_owned(),
+                    ));
+                }
+
+                if flags.incr && tokens.len() > 2 {
+                    return Err(CommandError::Other(
+                        "INCR option supports a single increment-element pair".to_owned(),
+                    ));
+                }
+
+                let pairs = tokens
+                    .chunks(2)
+                    .map(|pair| Ok((parse_float(&pair[0])?, pair[1].clone())))
+                    .collect::<Result<_, CommandError>>()?;
+
+                Command::ZAdd { key, pairs, flags }
+            }
+            "zincrby" => {
+                let key = args.next()?;
+                let increment = args.next_float()?;
+                let member = args.next()?;
+
+                Command::ZAdd {
+                    key,
+                    pairs: vec![(increment, member)],
+                    flags: ZAddFlags {
+                        incr: true,
+                        ..Default::default()
+                    },
+                }
+            }
+            "zrem" | "zmscore" => {
+                let key = args.next()?;
+                let members = args.rest()?;
+
+                if cmd_name == "zrem" {
+                    Command::ZRem { key, members }
+                } else {
+                    Command::ZMScore { key, members }
+                }
+            }
+            "zscore" => {
+                let key = args.next()?;
+                let member = args.next()?;
+                Command::ZScore { key, member }
+            }
+            "zcard" => {
+                let key = args.next()?;
+                Command::ZCard(key)
+            }
+            "zrank" | "zrevrank" => {
+                let key = args.next()?;
+                let member = args.next()?;
+
+                let with_score = match args.next_keyword() {
+                    Some(option) if option == "withscore" => true,
+                    Some(_) => return Err(CommandError::Syntax),
+                    None => false,
+                };
+
+                Command::ZRank {
+                    key,
+                    member,
+                    rev: cmd_name == "zrevrank",
+                    with_score,
+                }
+            }
+            "zrange" | "zrangestore" | "zrevrange" | "zrangebyscore" | "zrevrangebyscore"
+            | "zrangebylex" | "zrevrangebylex" => parse_zrange(&cmd_name, &mut args)?,
+            "zcount" => {
+                let key = args.next()?;
+                let min = parse_score_bound(&args.next()?)?;
+                let max = parse_score_bound(&args.next()?)?;
+
+                Command::ZCount {
+                    key,
+                    range: ScoreRange { min, max },
+                }
+            }
+            "zlexcount" => {
+                let key = args.next()?;
+                let min = parse_lex_bound(&args.next()?)?;
+                let max = parse_lex_bound(&args.next()?)?;
+
+                Command::ZLexCount {
+                    key,
+                    range: LexRange { min, max },
+                }
+            }
+            "zpopmin" | "zpopmax" => {
+                let key = args.next()?;
+                let count = args
+                    .optional()
+                    .map(|count| {
+                        parse_int::<usize>(&count).map_err(|_| {
+                            CommandError::Other(
+                                "value is out of range, must be positive".to_owned(),
+                            )
                         })
-                        .and_then(|expiry| expiry.as_str().parse::<u64>().ok());
+                    })
+                    .transpose()?;
 
-                    Command::Set { key, value, expiry }
+                Command::ZPop {
+                    key,
+                    count,
+                    max: cmd_name == "zpopmax",
                 }
-                "get" => {
-                    let key = cmd_tokens.next().ok_or(anyhow!("No key specified"))?;
-                    Command::Get(key)
+            }
+            "zunionstore" | "zinterstore" => {
+                let destination = args.next()?;
+                let numkeys = args.next_int::<i64>()?;
+
+                if numkeys <= 0 {
+                    return Err(CommandError::Other(format!(
+                        "at least 1 input key is needed for '{}' command",
+                        cmd_name
+                    )));
+                }
+
+                let keys: Vec<_> = (0..numkeys)
+                    .map(|_| args.next().map_err(|_| CommandError::Syntax))
+                    .collect::<Result<_, _>>()?;
+
+                let mut weights = vec![];
+                let mut aggregate = Aggregate::default();
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "weights" => {
+                            weights = (0..keys.len())
+                                .map(|_| {
+                                    parse_f64(&args.option_value()?).ok_or_else(|| {
+                                        CommandError::Other(
+                                            "weight value is not a float".to_owned(),
+                                        )
+                                    })
+                                })
+                                .collect::<Result<_, _>>()?;
+                        }
+                        "aggregate" => {
+                            aggregate =
+                                match to_string(&args.option_value()?).to_lowercase().as_str() {
+                                    "sum" => Aggregate::Sum,
+                                    "min" => Aggregate::Min,
+                                    "max" => Aggregate::Max,
+                                    _ => return Err(CommandError::Syntax),
+                                }
+                        }
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
+
+                Command::ZCombineStore {
+                    destination,
+                    keys,
+                    weights,
+                    aggregate,
+                    inter: cmd_name == "zinterstore",
                 }
-                "info" => {
-                    let role = cmd_tokens.next();
-                    Command::Info(role)
+            }
+            "zrandmember" => {
+                let key = args.next()?;
+                let count = args
+                    .optional()
+                    .map(|count| parse_random_count(&count))
+                    .transpose()?;
+
+                let with_scores = match args.next_keyword() {
+                    Some(option) if option == "withscores" && count.is_some() => true,
+                    Some(_) => return Err(CommandError::Syntax),
+                    None => false,
+                };
+
+                Command::ZRandMember {
+                    key,
+                    count,
+                    with_scores,
                 }
-                "replconf" => {
-                    let subcmd = cmd_tokens
-                        .next()
-                        .ok_or(anyhow!("No subcommand specified"))?
-                        .to_lowercase();
+            }
+            "xadd" => {
+                let key = args.next()?;
 
-                    let conf = match subcmd.as_str() {
-                        "listening-port" => {
-                            let port = cmd_tokens
-                                .next()
-                                .ok_or(anyhow!("No listening port specified"))?
-                                .as_str()
-                                .parse::<u32>()?;
+                let mut no_create = false;
+                let mut trim = None;
 
-                            ReplConf::ListeningPort(port)
+                while let Some(option) = args.peek_keyword() {
+                    match option.as_str() {
+                        "nomkstream" => {
+                            args.next()?;
+                            no_create = true;
+                        }
+                        "maxlen" | "minid" => {
+                            args.next()?;
+                            trim = Some(parse_stream_trim(&option, &mut args)?);
                         }
-                        "capa" => {
-                            let capa = cmd_tokens.next().ok_or(anyhow!("Missing capability"))?;
-                            let mut capas = vec![capa];
+                        _ => break,
+                    }
+                }
 
-                            while let Some(capa_cmd) = cmd_tokens.next() {
-                                let capa_cmd = capa_cmd.to_lowercase();
+                let id = parse_new_stream_id(&args.next()?)?;
+                let fields = to_pairs(args.rest()?, &args)?;
 
-                                if capa_cmd != "capa" {
-                                    return Err(anyhow!("Expected `capa', found {}", capa_cmd));
-                                }
+                Command::XAdd {
+                    key,
+                    id,
+                    fields,
+                    no_create,
+                    trim,
+                }
+            }
+            "xrange" | "xrevrange" => {
+                let key = args.next()?;
+                let rev = cmd_name == "xrevrange";
+
+                // XREVRANGE takes the end of the range first
+                let (first, second) = (args.next()?, args.next()?);
+                let (start, end) = if rev {
+                    (second, first)
+                } else {
+                    (first, second)
+                };
+
+                let start = parse_stream_bound(&start, true)?;
+                let end = parse_stream_bound(&end, false)?;
+
+                let count = match args.next_keyword() {
+                    Some(option) if option == "count" => {
+                        parse_int::<i64>(&args.option_value()?)?.max(0) as usize
+                    }
+                    Some(_) => return Err(CommandError::Syntax),
+                    None => usize::MAX,
+                };
+
+                args.finish().map_err(|_| CommandError::Syntax)?;
 
-                                let capa =
-                                    cmd_tokens.next().ok_or(anyhow!("Missing capability"))?;
-                                capas.push(capa);
+                Command::XRange {
+                    key,
+                    start,
+                    end,
+                    count,
+                    rev,
+                }
+            }
+            "xread" | "xreadgroup" => {
+                let mut group = None;
+                let mut count = usize::MAX;
+                let mut block = None;
+                let mut no_ack = false;
+
+                loop {
+                    match args.next_keyword().as_deref() {
+                        Some("count") => {
+                            // A count of 0 or less reads everything there is
+                            count = match parse_int::<i64>(&args.option_value()?)? {
+                                count if count > 0 => count as usize,
+                                _ => usize::MAX,
+                            };
+                        }
+                        Some("block") => {
+                            let timeout =
+                                parse_int::<i64>(&args.option_value()?).map_err(|_| {
+                                    CommandError::Other(
+                                        "timeout is not an integer or out of range".to_owned(),
+                                    )
+                                })?;
+
+                            if timeout < 0 {
+                                return Err(CommandError::Other("timeout is negative".to_owned()));
                             }
 
-                            ReplConf::Capa(capas)
+                            block =
+                                Some((timeout > 0).then(|| Duration::from_millis(timeout as u64)));
+                        }
+                        Some("group") if cmd_name == "xreadgroup" => {
+                            group = Some((args.option_value()?, args.option_value()?));
                         }
-                        _ => todo!(),
-                    };
+                        Some("noack") if cmd_name == "xreadgroup" => no_ack = true,
+                        Some("group") => {
+                            return Err(CommandError::Other(
+                                "The GROUP option is only supported by XREADGROUP. You called XREAD instead."
+                                    .to_owned(),
+                            ))
+                        }
+                        Some("streams") => break,
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
 
-                    Command::ReplConf(conf)
+                let mut keys = args.rest()?;
+
+                if !keys.len().is_multiple_of(2) {
+                    return Err(CommandError::Other(format!(
+                        "Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+                        cmd_name
+                    )));
                 }
-                "psync" => {
-                    let replica_id = cmd_tokens.next().ok_or(anyhow!("Missing replication ID"))?;
-                    let offset = cmd_tokens
-                        .next()
-                        .ok_or(anyhow!("Missing replication offset"))?
-                        .as_str()
-                        .parse::<i32>()?;
-                    Command::Psync { replica_id, offset }
+
+                // `$` and `>` are both `None`, as each is only valid for one
+                // of the commands
+                let new_id: &[u8] = if group.is_some() { b">" } else { b"$" };
+
+                let after = keys
+                    .split_off(keys.len() / 2)
+                    .iter()
+                    .map(|id| match &id[..] {
+                        id if id == new_id => Ok(None),
+                        b">" => Err(CommandError::Other(
+                            "The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option."
+                                .to_owned(),
+                        )),
+                        b"$" => Err(CommandError::Other(
+                            "The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set."
+                                .to_owned(),
+                        )),
+                        id => parse_stream_id(id, 0).map(Some),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                let op = match group {
+                    Some((group, consumer)) => StreamReadOp::Group {
+                        group,
+                        consumer,
+                        after,
+                        no_ack,
+                    },
+                    None if cmd_name == "xreadgroup" => {
+                        return Err(CommandError::Other(
+                            "Missing GROUP option for XREADGROUP".to_owned(),
+                        ))
+                    }
+                    None => StreamReadOp::After(after),
+                };
+
+                Command::XRead {
+                    keys,
+                    op,
+                    count,
+                    block,
                 }
+            }
+            "xgroup" => {
+                let subcmd = args.next_string()?;
 
-                _ => unimplemented!(),
-            };
+                let xgroup = match subcmd.to_lowercase().as_str() {
+                    "create" => {
+                        let key = args.next()?;
+                        let group = args.next()?;
+                        let mut make_stream = false;
+                        let (id, entries_read) = parse_group_id(&mut args, Some(&mut make_stream))?;
 
-            Ok(command)
-        } else {
-            Err(anyhow!("Expected an array"))
-        }
+                        XGroup::Create {
+                            key,
+                            group,
+                            id,
+                            make_stream,
+                            entries_read,
+                        }
+                    }
+                    "setid" => {
+                        let key = args.next()?;
+                        let group = args.next()?;
+                        let (id, entries_read) = parse_group_id(&mut args, None)?;
+
+                        XGroup::SetId {
+                            key,
+                            group,
+                            id,
+                            entries_read,
+                        }
+                    }
+                    "destroy" => {
+                        let key = args.next()?;
+                        let group = args.next()?;
+                        XGroup::Destroy { key, group }
+                    }
+                    "createconsumer" | "delconsumer" => {
+                        let key = args.next()?;
+                        let group = args.next()?;
+                        let consumer = args.next()?;
+
+                        if subcmd.eq_ignore_ascii_case("createconsumer") {
+                            XGroup::CreateConsumer {
+                                key,
+                                group,
+                                consumer,
+                            }
+                        } else {
+                            XGroup::DelConsumer {
+                                key,
+                                group,
+                                consumer,
+                            }
+                        }
+                    }
+                    _ => return Err(CommandError::UnknownSubcommand("XGROUP".to_owned(), subcmd)),
+                };
+
+                args.finish()?;
+
+                Command::XGroup(xgroup)
+            }
+            "xack" => {
+                let key = args.next()?;
+                let group = args.next()?;
+                let ids = args
+                    .rest()?
+                    .iter()
+                    .map(|id| parse_stream_id(id, 0))
+                    .collect::<Result<_, _>>()?;
+
+                Command::XAck { key, group, ids }
+            }
+            "xpending" => {
+                let key = args.next()?;
+                let group = args.next()?;
+
+                let range = if args.is_empty() {
+                    None
+                } else {
+                    let mut min_idle = 0;
+
+                    if args.peek_keyword().as_deref() == Some("idle") {
+                        args.next()?;
+                        min_idle = parse_int::<i64>(&args.option_value()?)?.max(0) as u128;
+                    }
+
+                    let start = parse_stream_bound(&args.option_value()?, true)?;
+                    let end = parse_stream_bound(&args.option_value()?, false)?;
+                    let count = parse_int::<i64>(&args.option_value()?)?.max(0) as usize;
+                    let consumer = args.optional();
+
+                    Some(PendingRange {
+                        range: start..=end,
+                        count,
+                        consumer,
+                        min_idle,
+                    })
+                };
+
+                args.finish().map_err(|_| CommandError::Syntax)?;
+
+                Command::XPending { key, group, range }
+            }
+            "xclaim" => {
+                let key = args.next()?;
+                let group = args.next()?;
+                let consumer = args.next()?;
+                let min_idle = parse_min_idle(&args.next()?, &cmd_name)?;
+
+                let mut ids = vec![parse_stream_id(&args.next()?, 0)?];
+
+                // The IDs go on until the first option
+                while let Some(id) = args
+                    .peek_keyword()
+                    .and_then(|id| StreamId::parse(id.as_bytes(), 0))
+                {
+                    args.next()?;
+                    ids.push(id);
+                }
+
+                let mut just_id = false;
+                let mut options = ClaimOptions::default();
+                let now = now_millis();
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "idle" => {
+                            let idle = parse_int::<i64>(&args.option_value()?)?.max(0) as u128;
+                            options.delivery_time = Some(now.saturating_sub(idle));
+                        }
+                        "time" => {
+                            let time = parse_int::<i64>(&args.option_value()?)?.max(0) as u128;
+                            options.delivery_time = Some(time.min(now));
+                        }
+                        "retrycount" => {
+                            options.retry_count =
+                                Some(parse_int::<i64>(&args.option_value()?)?.max(0) as u64);
+                        }
+                        "force" => options.force = true,
+                        "justid" => just_id = true,
+                        "lastid" => {
+                            options.last_id = Some(parse_stream_id(&args.option_value()?, 0)?)
+                        }
+                        _ => {
+                            return Err(CommandError::Other(format!(
+                                "Unrecognized XCLAIM option '{}'",
+                                option
+                            )))
+                        }
+                    }
+                }
+
+                Command::XClaim {
+                    key,
+                    claim: Claim {
+                        group,
+                        consumer,
+                        min_idle,
+                        just_id,
+                    },
+                    ids,
+                    options,
+                }
+            }
+            "xautoclaim" => {
+                let key = args.next()?;
+                let group = args.next()?;
+                let consumer = args.next()?;
+                let min_idle = parse_min_idle(&args.next()?, &cmd_name)?;
+                let start = parse_stream_bound(&args.next()?, true)?;
+
+                let mut count = 100;
+                let mut just_id = false;
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "count" => {
+                            let value = parse_int::<i64>(&args.option_value()?)?;
+
+                            // At most ten times the count are looked at
+                            if !(1..=i64::MAX / 10).contains(&value) {
+                                return Err(CommandError::Other("COUNT must be > 0".to_owned()));
+                            }
+
+                            count = value as usize;
+                        }
+                        "justid" => just_id = true,
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
+
+                Command::XAutoClaim {
+                    key,
+                    claim: Claim {
+                        group,
+                        consumer,
+                        min_idle,
+                        just_id,
+                    },
+                    start,
+                    count,
+                }
+            }
+            "xinfo" => {
+                let subcmd = args.next_string()?;
+
+                let xinfo = match subcmd.to_lowercase().as_str() {
+                    "stream" => XInfo::Stream(args.next()?),
+                    "groups" => XInfo::Groups(args.next()?),
+                    "consumers" => {
+                        let key = args.next()?;
+                        let group = args.next()?;
+                        XInfo::Consumers { key, group }
+                    }
+                    _ => return Err(CommandError::UnknownSubcommand("XINFO".to_owned(), subcmd)),
+                };
+
+                args.finish().map_err(|_| CommandError::Syntax)?;
+
+                Command::XInfo(xinfo)
+            }
+            "xlen" => {
+                let key = args.next()?;
+                Command::XLen(key)
+            }
+            "xdel" => {
+                let key = args.next()?;
+                let ids = args
+                    .rest()?
+                    .iter()
+                    .map(|id| parse_stream_id(id, 0))
+                    .collect::<Result<_, _>>()?;
+
+                Command::XDel { key, ids }
+            }
+            "xtrim" => {
+                let key = args.next()?;
+
+                let (trim, limit) = match args.next_keyword() {
+                    Some(strategy) if matches!(strategy.as_str(), "maxlen" | "minid") => {
+                        parse_stream_trim(&strategy, &mut args)?
+                    }
+                    Some(_) => return Err(CommandError::Syntax),
+                    None => return Err(args.wrong_arity()),
+                };
+
+                args.finish().map_err(|_| CommandError::Syntax)?;
+
+                Command::XTrim { key, trim, limit }
+            }
+            "subscribe" | "psubscribe" | "ssubscribe" => {
+                let kind = match cmd_name.as_str() {
+                    "subscribe" => SubscriptionKind::Channel,
+                    "psubscribe" => SubscriptionKind::Pattern,
+                    _ => SubscriptionKind::Shard,
+                };
+
+                let channels = args.rest()?;
+
+                if kind == SubscriptionKind::Shard {
+                    check_same_slot(&channels)?;
+                }
+
+                Command::Subscribe { kind, channels }
+            }
+            "unsubscribe" | "punsubscribe" | "sunsubscribe" => {
+                let kind = match cmd_name.as_str() {
+                    "unsubscribe" => SubscriptionKind::Channel,
+                    "punsubscribe" => SubscriptionKind::Pattern,
+                    _ => SubscriptionKind::Shard,
+                };
+
+                let channels = if args.is_empty() {
+                    vec![]
+                } else {
+                    args.rest()?
+                };
+
+                if kind == SubscriptionKind::Shard {
+                    check_same_slot(&channels)?;
+                }
+
+                Command::Unsubscribe { kind, channels }
+            }
+            "publish" | "spublish" => {
+                let channel = args.next()?;
+                let message = args.next()?;
+                Command::Publish {
+                    channel,
+                    message,
+                    sharded: cmd_name == "spublish",
+                }
+            }
+            "pubsub" => {
+                let subcmd = args.next_string()?;
+
+                let pubsub = match subcmd.to_lowercase().as_str() {
+                    "channels" => PubSub::Channels(args.optional()),
+                    "numsub" if args.is_empty() => PubSub::NumSub(vec![]),
+                    "numsub" => PubSub::NumSub(args.rest()?),
+                    "numpat" => PubSub::NumPat,
+                    "shardchannels" => PubSub::ShardChannels(args.optional()),
+                    "shardnumsub" if args.is_empty() => PubSub::ShardNumSub(vec![]),
+                    "shardnumsub" => PubSub::ShardNumSub(args.rest()?),
+                    _ => return Err(CommandError::UnknownSubcommand("PUBSUB".to_owned(), subcmd)),
+                };
+
+                args.finish().map_err(|_| {
+                    CommandError::WrongArity(format!("pubsub|{}", subcmd.to_lowercase()))
+                })?;
+
+                Command::PubSub(pubsub)
+            }
+            "keys" => {
+                let pattern = args.next()?;
+                Command::Keys(pattern)
+            }
+            "scan" => {
+                let cursor = parse_cursor(&args.next()?)?;
+
+                let mut pattern = None;
+                let mut count = 10;
+                let mut key_type = None;
+
+                while let Some(option) = args.next_keyword() {
+                    if parse_scan_option(&option, &mut args, &mut pattern, &mut count)? {
+                        continue;
+                    }
+
+                    match option.as_str() {
+                        "type" => key_type = Some(to_string(&args.option_value()?).to_lowercase()),
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
+
+                Command::Scan {
+                    cursor,
+                    pattern,
+                    count,
+                    key_type,
+                }
+            }
+            "hset" | "hmset" => {
+                let key = args.next()?;
+                let pairs = to_pairs(args.rest()?, &args)?;
+
+                Command::HSet {
+                    key,
+                    pairs,
+                    multi: cmd_name == "hmset",
+                }
+            }
+            "hsetnx" => {
+                let key = args.next()?;
+                let field = args.next()?;
+                let value = args.next()?;
+                Command::HSetNx { key, field, value }
+            }
+            "hget" | "hexists" | "hstrlen" => {
+                let key = args.next()?;
+                let field = args.next()?;
+
+                match cmd_name.as_str() {
+                    "hget" => Command::HGet { key, field },
+                    "hexists" => Command::HExists { key, field },
+                    _ => Command::HStrlen { key, field },
+                }
+            }
+            "hmget" | "hdel" => {
+                let key = args.next()?;
+                let fields = args.rest()?;
+
+                if cmd_name == "hmget" {
+                    Command::HMGet { key, fields }
+                } else {
+                    Command::HDel { key, fields }
+                }
+            }
+            "hgetall" | "hkeys" | "hvals" | "hlen" => {
+                let key = args.next()?;
+
+                match cmd_name.as_str() {
+                    "hgetall" => Command::HGetAll(key),
+                    "hkeys" => Command::HKeys(key),
+                    "hvals" => Command::HVals(key),
+                    _ => Command::HLen(key),
+                }
+            }
+            "hincrby" => {
+                let key = args.next()?;
+                let field = args.next()?;
+                let increment = args.next_int::<i64>()?;
+                Command::HIncrBy {
+                    key,
+                    field,
+                    increment,
+                }
+            }
+            "hincrbyfloat" => {
+                let key = args.next()?;
+                let field = args.next()?;
+                let increment = args.next_float()?;
+                Command::HIncrByFloat {
+                    key,
+                    field,
+                    increment,
+                }
+            }
+            "hrandfield" => {
+                let key = args.next()?;
+                let count = args
+                    .optional()
+                    .map(|count| parse_random_count(&count))
+                    .transpose()?;
+
+                let with_values = match args.next_keyword() {
+                    Some(option) if option == "withvalues" && count.is_some() => true,
+                    Some(_) => return Err(CommandError::Syntax),
+                    None => false,
+                };
+
+                Command::HRandField {
+                    key,
+                    count,
+                    with_values,
+                }
+            }
+            "hscan" => {
+                let key = args.next()?;
+                let cursor = parse_cursor(&args.next()?)?;
+
+                let mut pattern = None;
+                let mut count = 10;
+                let mut no_values = false;
+
+                while let Some(option) = args.next_keyword() {
+                    if parse_scan_option(&option, &mut args, &mut pattern, &mut count)? {
+                        continue;
+                    }
+
+                    match option.as_str() {
+                        "novalues" => no_values = true,
+                        _ => return Err(CommandError::Syntax),
+                    }
+                }
+
+                Command::HScan {
+                    key,
+                    cursor,
+                    pattern,
+                    count,
+                    no_values,
+                }
+            }
+            "info" => {
+                let role = args.optional().map(|x| to_string(&x));
+                Command::Info(role)
+            }
+            "replconf" => {
+                let subcmd = args.next_string()?.to_lowercase();
+
+                let conf = match subcmd.as_str() {
+                    "listening-port" => {
+                        let port = args.next_int::<u32>()?;
+
+                        ReplConf::ListeningPort(port)
+                    }
+                    "capa" => {
+                        let capa = args.next_string()?;
+                        let mut capas = vec![capa];
+
+                        while let Some(capa_cmd) = args.next_keyword() {
+                            if capa_cmd != "capa" {
+                                return Err(CommandError::Syntax);
+                            }
+
+                            let capa = args.next_string()?;
+                            capas.push(capa);
+                        }
+
+                        ReplConf::Capa(capas)
+                    }
+                    _ => {
+                        return Err(CommandError::Other(format!(
+                            "Unrecognized REPLCONF option: {}",
+                            subcmd
+                        )))
+                    }
+                };
+
+                Command::ReplConf(conf)
+            }
+            "psync" => {
+                let replica_id = args.next_string()?;
+                let offset = args.next_int::<i32>()?;
+                Command::Psync { replica_id, offset }
+            }
+            "hello" => {
+                let protover = args
+                    .optional()
+                    .map(|protover| to_string(&protover).parse::<u32>())
+                    .transpose()
+                    .map_err(|_| {
+                        CommandError::Other(
+                            "Protocol version is not an integer or out of range".to_owned(),
+                        )
+                    })?;
+
+                let mut auth = None;
+                let mut setname = None;
+
+                while let Some(option) = args.next_keyword() {
+                    match option.as_str() {
+                        "auth" => {
+                            let username = args.next()?;
+                            let password = args.next()?;
+                            auth = Some((username, password));
+                        }
+                        "setname" => {
+                            let name = args.next()?;
+                            setname = Some(name);
+                        }
+                        _ => {
+                            return Err(CommandError::Other(format!(
+                                "Syntax error in HELLO option '{}'",
+                                option
+                            )))
+                        }
+                    }
+                }
+
+                Command::Hello {
+                    protover,
+                    auth,
+                    setname,
+                }
+            }
+
+            _ => {
+                // Like Redis, the arguments are quoted until 128 characters
+                // of them have been
+                let mut args_beginning = String::new();
+
+                for token in tokens {
+                    let room = 128_usize.saturating_sub(args_beginning.chars().count());
+
+                    if room == 0 {
+                        break;
+                    }
+
+                    let token: String = to_string(token).chars().take(room).collect();
+                    args_beginning += &format!("'{token}' ");
+                }
+
+                return Err(CommandError::UnknownCommand(original_name, args_beginning));
+            }
+        };
+
+        args.finish()?;
+
+        Ok(command)
     }
 }
 
 impl Command {
+    /// Whether a RESP2 client may run the command while subscribed, where
+    /// its replies could be mistaken for messages
+    pub fn allowed_in_subscribe_mode(&self) -> bool {
+        matches!(
+            self,
+            Self::Ping | Self::Subscribe { .. } | Self::Unsubscribe { .. }
+        )
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut result: Vec<String> = vec![];
 
@@ -169,3 +2575,122 @@ impl Command {
         resp.serialize()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, CommandError> {
+        Command::try_from(Resp::from(args.to_vec()))
+    }
+
+    /// The error parsing the command made of `args`
+    fn parse_error(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("The command should be rejected"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn set_options() {
+        let command = parse(&["SET", "k", "v", "nx", "GET", "Ex", "10"]);
+
+        let Ok(Command::Set {
+            expiry: Some(Expiry::Ex(10)),
+            condition: Some(SetCondition::IfNotExists),
+            keep_ttl: false,
+            get: true,
+            ..
+        }) = command
+        else {
+            panic!("The options should be parsed");
+        };
+
+        let conflicting: &[&[&str]] = &[
+            &["NX", "XX"],
+            &["XX", "NX"],
+            &["EX", "10", "PX", "10"],
+            &["EX", "10", "EX", "10"],
+            &["KEEPTTL", "EX", "10"],
+            &["PXAT", "10", "KEEPTTL"],
+            &["EX"],
+            &["FOO"],
+        ];
+
+        for options in conflicting {
+            let args = [&["SET", "k", "v"], *options].concat();
+            assert_eq!(parse_error(&args), "ERR syntax error");
+        }
+    }
+
+    #[test]
+    fn set_expire_times() {
+        for time in ["0", "-1"] {
+            assert_eq!(
+                parse_error(&["SET", "k", "v", "PX", time]),
+                "ERR invalid expire time in 'set' command"
+            );
+        }
+
+        for time in ["1.5", "9223372036854775808"] {
+            assert_eq!(
+                parse_error(&["SET", "k", "v", "EX", time]),
+                "ERR value is not an integer or out of range"
+            );
+        }
+    }
+
+    #[test]
+    fn zadd_flag_conflicts() {
+        let conflicting: &[(&[&str], &str)] = &[
+            (&["NX", "XX"], "XX and NX options"),
+            (&["GT", "LT"], "GT, LT, and/or NX options"),
+            (&["NX", "GT"], "GT, LT, and/or NX options"),
+            (&["LT", "NX"], "GT, LT, and/or NX options"),
+        ];
+
+        for (flags, error) in conflicting {
+            let args = [&["ZADD", "z"], *flags, &["1", "a"]].concat();
+            assert!(parse_error(&args).starts_with(&format!("ERR {error}")));
+        }
+
+        assert_eq!(
+            parse_error(&["ZADD", "z", "INCR", "1", "a", "2", "b"]),
+            "ERR INCR option supports a single increment-element pair"
+        );
+        assert_eq!(
+            parse_error(&["ZADD", "z", "1", "a", "2"]),
+            "ERR syntax error"
+        );
+
+        let command = parse(&["ZADD", "z", "xx", "GT", "ch", "incr", "1", "a"]);
+        let Ok(Command::ZAdd { flags, .. }) = command else {
+            panic!("The flags should be parsed");
+        };
+        assert!(flags.xx && flags.gt && flags.ch && flags.incr && !flags.nx && !flags.lt);
+    }
+
+    #[test]
+    fn random_counts() {
+        for command in ["HRANDFIELD", "SRANDMEMBER", "ZRANDMEMBER"] {
+            assert_eq!(
+                parse_error(&[command, "k", "-9223372036854775807"]),
+                "ERR value is out of range"
+            );
+        }
+
+        assert_eq!(
+            parse_error(&["HRANDFIELD", "h", "-9223372036854775808"]),
+            "ERR value is out of range"
+        );
+
+        let Ok(Command::HRandField {
+            count: Some(-4611686018427387903),
+            ..
+        }) = parse(&["HRANDFIELD", "h", "-4611686018427387903"])
+        else {
+            panic!("The count should be parsed");
+        };
+    }
+}
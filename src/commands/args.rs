@@ -0,0 +1,140 @@
+use bytes::Bytes;
+use std::str::FromStr;
+
+use super::error::CommandError;
+use crate::store::parse_f64;
+
+/// The arguments of a command, with helpers that turn missing or malformed
+/// arguments into the errors Redis replies with
+pub struct Args {
+    name: String,
+    tokens: std::vec::IntoIter<Bytes>,
+}
+
+/// Lossily converts a binary token into a `String`, for arguments that are
+/// only ever meaningful as text (command names, options, numbers).
+pub fn to_string(token: &[u8]) -> String {
+    String::from_utf8_lossy(token).into_owned()
+}
+
+pub fn parse_int<T: FromStr>(token: &[u8]) -> Result<T, CommandError> {
+    to_string(token)
+        .parse::<T>()
+        .map_err(|_| CommandError::NotInteger)
+}
+
+pub fn parse_float(token: &[u8]) -> Result<f64, CommandError> {
+    parse_f64(token).ok_or(CommandError::NotFloat)
+}
+
+impl Args {
+    pub fn new(name: &str, tokens: Vec<Bytes>) -> Self {
+        Self {
+            name: name.to_owned(),
+            tokens: tokens.into_iter(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.len() == 0
+    }
+
+    pub fn wrong_arity(&self) -> CommandError {
+        CommandError::WrongArity(self.name.to_owned())
+    }
+
+    pub fn next(&mut self) -> Result<Bytes, CommandError> {
+        self.tokens.next().ok_or_else(|| self.wrong_arity())
+    }
+
+    pub fn next_string(&mut self) -> Result<String, CommandError> {
+        Ok(to_string(&self.next()?))
+    }
+
+    /// The next argument lowercased, for matching against option names
+    pub fn next_keyword(&mut self) -> Option<String> {
+        self.tokens.next().map(|x| to_string(&x).to_lowercase())
+    }
+
+    /// Like `next_keyword`, but leaves the argument to be consumed later
+    pub fn peek_keyword(&self) -> Option<String> {
+        self.tokens
+            .as_slice()
+            .first()
+            .map(|x| to_string(x).to_lowercase())
+    }
+
+    pub fn next_int<T: FromStr>(&mut self) -> Result<T, CommandError> {
+        parse_int(&self.next()?)
+    }
+
+    pub fn next_float(&mut self) -> Result<f64, CommandError> {
+        parse_float(&self.next()?)
+    }
+
+    /// The value following an option, e.g. the `10` in `EX 10`. A missing
+    /// value is a syntax error rather than an arity error.
+    pub fn option_value(&mut self) -> Result<Bytes, CommandError> {
+        self.tokens.next().ok_or(CommandError::Syntax)
+    }
+
+    /// The remaining arguments, of which there has to be at least one
+    pub fn rest(&mut self) -> Result<Vec<Bytes>, CommandError> {
+        if self.is_empty() {
+            return Err(self.wrong_arity());
+        }
+
+        Ok(self.tokens.by_ref().collect())
+    }
+
+    pub fn optional(&mut self) -> Option<Bytes> {
+        self.tokens.next()
+    }
+
+    /// Fails unless every argument has been consumed
+    pub fn finish(&self) -> Result<(), CommandError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self.wrong_arity())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(tokens: &[&'static str]) -> Args {
+        Args::new("get", tokens.iter().map(|&x| Bytes::from(x)).collect())
+    }
+
+    #[test]
+    fn missing_and_trailing_arguments() {
+        let arity = "ERR wrong number of arguments for 'get' command";
+
+        assert_eq!(args(&[]).next().unwrap_err().to_string(), arity);
+        assert_eq!(args(&[]).rest().unwrap_err().to_string(), arity);
+        assert_eq!(args(&["a", "b"]).finish().unwrap_err().to_string(), arity);
+
+        // A missing option value is a syntax error instead
+        let err = args(&[]).option_value().unwrap_err();
+        assert_eq!(err.to_string(), "ERR syntax error");
+
+        let mut args = args(&["a"]);
+        assert_eq!(args.next().unwrap(), "a");
+        assert!(args.finish().is_ok());
+    }
+
+    #[test]
+    fn malformed_numbers() {
+        let err = args(&["1.5"]).next_int::<i64>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR value is not an integer or out of range"
+        );
+
+        let err = args(&["nan"]).next_float().unwrap_err();
+        assert_eq!(err.to_string(), "ERR value is not a valid float");
+    }
+}
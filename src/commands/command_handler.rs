@@ -1,7 +1,20 @@
-use super::response::Response;
-use crate::store::Store;
+use super::{error::CommandError, response::Response, Expiry, PubSub, XGroup, XInfo};
+use crate::pubsub::{Broker, Message, MessageSender, Subscriber, SubscriptionKind};
+use crate::resp::{Protocol, Resp};
+use crate::store::{
+    now_millis, Aggregate, Blocking, BlockingOp, Claim, ClaimOptions, ExpireCondition, ListEnd,
+    NewStreamId, PendingRange, Served, SetCondition, SetOp, Store, StoreError, StreamBlocking,
+    StreamEntry, StreamId, StreamReadOp, StreamTrim, StreamsRead, ZAddFlags, ZRange, ZRangeBy,
+};
 use crate::{Command, CONFIG};
 use anyhow::anyhow;
+use bytes::Bytes;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 pub const EMPTY_RDB: &[u8] = &[
     0x52, 0x45, 0x44, 0x49, 0x53, 0x30, 0x30, 0x31, 0x31, 0xfa, 0x09, 0x72, 0x65, 0x64, 0x69, 0x73,
@@ -12,89 +25,2208 @@ pub const EMPTY_RDB: &[u8] = &[
     0xf0, 0x6e, 0x3b, 0xfe, 0xc0, 0xff, 0x5a, 0xa2,
 ];
 
+/// The server version reported by `HELLO`, matching the RDB file we send
+const REDIS_VERSION: &str = "7.2.0";
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The serialized reply to a command. A client running a blocking command
+/// gets its reply once the command is unblocked.
+pub enum Reply {
+    Ready(Vec<u8>),
+    Blocked(Pin<Box<dyn Future<Output = Vec<u8>> + Send>>),
+}
+
+/// Turns an error into an error reply for the client
+fn to_response(result: anyhow::Result<Response>) -> Response {
+    result.unwrap_or_else(|err| {
+        // Our own errors already start with the error code
+        if err.is::<CommandError>() || err.is::<StoreError>() {
+            Response::Error(err.to_string())
+        } else {
+            Response::Error(format!("ERR {}", err))
+        }
+    })
+}
+
+/// The reply of the `TTL` family for `expiry`, which is `None` if the key
+/// doesn't exist
+fn to_ttl(expiry: Option<Option<u128>>, millis: bool, absolute: bool) -> i64 {
+    match expiry {
+        None => -2,
+        Some(None) => -1,
+        Some(Some(deadline)) => {
+            let ttl = if absolute {
+                deadline
+            } else {
+                deadline.saturating_sub(now_millis())
+            };
+
+            if millis {
+                ttl as i64
+            } else {
+                // Round to the nearest second, like Redis does
+                ((ttl + 500) / 1000) as i64
+            }
+        }
+    }
+}
+
+/// A stream entry as Redis replies with it, an array of the ID and a flat
+/// array of the fields and values. A deleted entry has no fields.
+fn stream_entry(id: StreamId, fields: Option<Vec<(Bytes, Bytes)>>) -> Response {
+    let fields = fields.map_or(Response::NullArray, |fields| {
+        Response::Array(
+            fields
+                .into_iter()
+                .flat_map(|(field, value)| {
+                    [Response::BulkString(field), Response::BulkString(value)]
+                })
+                .collect(),
+        )
+    });
+
+    Response::Array(vec![stream_id(id), fields])
+}
+
+fn stream_entries(entries: Vec<StreamEntry>) -> Response {
+    Response::Array(
+        entries
+            .into_iter()
+            .map(|(id, fields)| stream_entry(id, Some(fields)))
+            .collect(),
+    )
+}
+
+/// The entries claimed by `XCLAIM` or `XAUTOCLAIM`, or just their IDs
+fn claimed_entries(claimed: Vec<StreamEntry>, just_id: bool) -> Response {
+    if just_id {
+        Response::Array(claimed.into_iter().map(|(id, _)| stream_id(id)).collect())
+    } else {
+        stream_entries(claimed)
+    }
+}
+
+fn stream_id(id: StreamId) -> Response {
+    Response::BulkString(id.to_string().into())
+}
+
+/// The reply of `XREAD` and `XREADGROUP`, which maps each stream key to its
+/// entries. In RESP2, that's an array of key and entries pairs.
+fn streams_read(read: StreamsRead, protocol: Protocol) -> Response {
+    let read = read.into_iter().map(|(key, entries)| {
+        let entries = entries
+            .into_iter()
+            .map(|(id, fields)| stream_entry(id, fields))
+            .collect();
+
+        (Response::BulkString(key), Response::Array(entries))
+    });
+
+    match protocol {
+        Protocol::Resp3 => Response::Map(read.collect()),
+        Protocol::Resp2 => Response::Array(
+            read.map(|(key, entries)| Response::Array(vec![key, entries]))
+                .collect(),
+        ),
+    }
+}
+
+/// The lowercase name of the command in `frame`, for error messages
+fn command_name(frame: &Resp) -> String {
+    match frame {
+        Resp::Array(array) => match array.first() {
+            Some(Resp::BulkString(name)) => String::from_utf8_lossy(name).to_lowercase(),
+            Some(Resp::SimpleString(name)) => name.to_lowercase(),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/// The reply to subscribing to or unsubscribing from `channel`, with the
+/// number of subscriptions left
+fn subscription_reply(action: &str, channel: Response, count: usize) -> Response {
+    Response::Push(vec![
+        Response::BulkString(Bytes::copy_from_slice(action.as_bytes())),
+        channel,
+        Response::Int(count as i64),
+    ])
+}
+
+/// A published message as pushed to a subscriber
+fn message_response(message: Message) -> Response {
+    let items = match message {
+        Message::Channel { channel, payload } => vec![
+            Response::BulkString("message".into()),
+            Response::BulkString(channel),
+            Response::BulkString(payload),
+        ],
+        Message::Pattern {
+            pattern,
+            channel,
+            payload,
+        } => vec![
+            Response::BulkString("pmessage".into()),
+            Response::BulkString(pattern),
+            Response::BulkString(channel),
+            Response::BulkString(payload),
+        ],
+        Message::Shard { channel, payload } => vec![
+            Response::BulkString("smessage".into()),
+            Response::BulkString(channel),
+            Response::BulkString(payload),
+        ],
+    };
+
+    Response::Push(items)
+}
+
 pub struct CommandHandler {
     store: Store,
+    broker: Broker,
+    subscriber: Subscriber,
+    client_id: u64,
+    client_name: Option<Bytes>,
+    protocol: Protocol,
 }
 
 impl CommandHandler {
-    pub fn new(store: Store) -> Self {
-        Self { store }
+    /// Creates the handler for a new client, which is sent the messages
+    /// published to its subscriptions through `messages`
+    pub fn new(store: Store, broker: Broker, messages: MessageSender) -> Self {
+        let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+
+        Self {
+            store,
+            subscriber: broker.subscriber(client_id, messages),
+            broker,
+            client_id,
+            client_name: None,
+            protocol: Protocol::default(),
+        }
+    }
+
+    /// Serializes a message published to one of the client's subscriptions
+    pub fn serialize_message(&self, message: Message) -> Vec<u8> {
+        message_response(message).serialize(self.protocol)
+    }
+
+    /// Whether the client is subscribed while speaking RESP2, which only
+    /// lets it run the commands managing its subscriptions
+    fn in_subscribe_mode(&self) -> bool {
+        self.protocol == Protocol::Resp2 && self.subscriber.is_subscribed()
+    }
+
+    /// Executes the command in `frame` and returns the serialized reply. Any
+    /// error is turned into an error reply for the client.
+    pub fn handle_frame(&mut self, frame: Resp) -> Reply {
+        let name = command_name(&frame);
+
+        let result = Command::try_from(frame)
+            .map_err(anyhow::Error::from)
+            .and_then(|cmd| {
+                if self.in_subscribe_mode() && !cmd.allowed_in_subscribe_mode() {
+                    return Err(CommandError::SubscribeMode(name).into());
+                }
+
+                self.handle_command(cmd)
+            });
+
+        let protocol = self.protocol;
+
+        match result {
+            Ok(Response::Blocked(reply)) => Reply::Blocked(Box::pin(async move {
+                to_response(reply.await).serialize(protocol)
+            })),
+            result => Reply::Ready(to_response(result).serialize(protocol)),
+        }
     }
 
-    pub fn handle_command(&mut self, cmd: Command) -> anyhow::Result<Vec<u8>> {
+    fn handle_command(&mut self, cmd: Command) -> anyhow::Result<Response> {
         let response = match cmd {
             Command::Ping => self.handle_ping(),
-            Command::Echo(arg) => self.handle_echo(&arg),
-            Command::Set { key, value, expiry } => self.handle_set(&key, &value, expiry),
+            Command::Echo(arg) => self.handle_echo(arg),
+            Command::Set {
+                key,
+                value,
+                expiry,
+                condition,
+                keep_ttl,
+                get,
+            } => self.handle_set(key, value, expiry, condition, keep_ttl, get),
             Command::Get(key) => self.handle_get(&key),
+            Command::Expire {
+                key,
+                expiry,
+                condition,
+            } => self.handle_expire(&key, expiry, condition),
+            Command::Ttl {
+                key,
+                millis,
+                absolute,
+            } => self.handle_ttl(&key, millis, absolute),
+            Command::Persist(key) => self.handle_persist(&key),
+            Command::Del(keys) => self.handle_del(&keys),
+            Command::Exists(keys) | Command::Touch(keys) => self.handle_exists(&keys),
+            Command::Type(key) => self.handle_type(&key),
+            Command::Rename { key, new_key, nx } => self.handle_rename(&key, new_key, nx),
+            Command::Append { key, value } => self.handle_append(key, &value),
+            Command::Strlen(key) => self.handle_strlen(&key),
+            Command::GetRange { key, start, end } => self.handle_get_range(&key, start, end),
+            Command::SetRange { key, offset, value } => self.handle_set_range(key, offset, &value),
+            Command::GetDel(key) => self.handle_get_del(&key),
+            Command::GetEx {
+                key,
+                expiry,
+                persist,
+            } => self.handle_get_ex(&key, expiry, persist),
+            Command::MGet(keys) => self.handle_mget(&keys),
+            Command::MSet { pairs, nx } => self.handle_mset(pairs, nx),
+            Command::Push {
+                key,
+                values,
+                end,
+                only_existing,
+            } => self.handle_push(key, values, end, only_existing),
+            Command::Pop { key, end, count } => self.handle_pop(&key, end, count),
+            Command::LRange { key, start, stop } => self.handle_lrange(&key, start, stop),
+            Command::LLen(key) => self.handle_llen(&key),
+            Command::LIndex { key, index } => self.handle_lindex(&key, index),
+            Command::LSet { key, index, value } => self.handle_lset(&key, index, value),
+            Command::LRem {
+                key,
+                count,
+                element,
+            } => self.handle_lrem(&key, count, &element),
+            Command::LTrim { key, start, stop } => self.handle_ltrim(&key, start, stop),
+            Command::LInsert {
+                key,
+                before,
+                pivot,
+                element,
+            } => self.handle_linsert(&key, before, &pivot, element),
+            Command::LPos {
+                key,
+                element,
+                rank,
+                count,
+                maxlen,
+            } => self.handle_lpos(&key, &element, rank, count, maxlen),
+            Command::LMove {
+                source,
+                destination,
+                from,
+                to,
+            } => self.handle_lmove(&source, destination, from, to),
+            Command::BPop { keys, end, timeout } => self.handle_bpop(keys, end, timeout),
+            Command::MPop {
+                keys,
+                end,
+                count,
+                block,
+                timeout,
+            } => self.handle_mpop(keys, end, count, block, timeout),
+            Command::BLMove {
+                source,
+                destination,
+                from,
+                to,
+                timeout,
+            } => self.handle_blmove(source, destination, from, to, timeout),
+            Command::HSet { key, pairs, multi } => self.handle_hset(key, pairs, multi),
+            Command::HSetNx { key, field, value } => self.handle_hsetnx(key, field, value),
+            Command::HGet { key, field } => self.handle_hget(&key, &field),
+            Command::HMGet { key, fields } => self.handle_hmget(&key, &fields),
+            Command::HDel { key, fields } => self.handle_hdel(&key, &fields),
+            Command::HGetAll(key) => self.handle_hgetall(&key),
+            Command::HKeys(key) => self.handle_hkeys(&key),
+            Command::HVals(key) => self.handle_hvals(&key),
+            Command::HLen(key) => self.handle_hlen(&key),
+            Command::HExists { key, field } => self.handle_hexists(&key, &field),
+            Command::HStrlen { key, field } => self.handle_hstrlen(&key, &field),
+            Command::HIncrBy {
+                key,
+                field,
+                increment,
+            } => self.handle_hincr_by(key, field, increment),
+            Command::HIncrByFloat {
+                key,
+                field,
+                increment,
+            } => self.handle_hincr_by_float(key, field, increment),
+            Command::HRandField {
+                key,
+                count,
+                with_values,
+            } => self.handle_hrandfield(&key, count, with_values),
+            Command::HScan {
+                key,
+                cursor,
+                pattern,
+                count,
+                no_values,
+            } => self.handle_hscan(&key, cursor, pattern.as_deref(), count, no_values),
+            Command::HExpire {
+                key,
+                expiry,
+                condition,
+                fields,
+            } => self.handle_hexpire(&key, expiry, condition, &fields),
+            Command::HTtl {
+                key,
+                fields,
+                millis,
+                absolute,
+            } => self.handle_httl(&key, &fields, millis, absolute),
+            Command::HPersist { key, fields } => self.handle_hpersist(&key, &fields),
+            Command::SAdd { key, members } => self.handle_sadd(key, members),
+            Command::SRem { key, members } => self.handle_srem(&key, &members),
+            Command::SMembers(key) => self.handle_smembers(&key),
+            Command::SIsMember { key, member } => self.handle_sismember(&key, member),
+            Command::SMIsMember { key, members } => self.handle_smismember(&key, &members),
+            Command::SCard(key) => self.handle_scard(&key),
+            Command::SPop { key, count } => self.handle_spop(&key, count),
+            Command::SRandMember { key, count } => self.handle_srandmember(&key, count),
+            Command::SetOp { op, keys } => self.handle_set_op(op, &keys),
+            Command::SetOpStore {
+                op,
+                destination,
+                keys,
+            } => self.handle_set_op_store(op, destination, &keys),
+            Command::SInterCard { keys, limit } => self.handle_sintercard(&keys, limit),
+            Command::SMove {
+                source,
+                destination,
+                member,
+            } => self.handle_smove(&source, destination, member),
+            Command::SScan {
+                key,
+                cursor,
+                pattern,
+                count,
+            } => self.handle_sscan(&key, cursor, pattern.as_deref(), count),
+            Command::ZAdd { key, pairs, flags } => self.handle_zadd(key, pairs, flags),
+            Command::ZRem { key, members } => self.handle_zrem(&key, &members),
+            Command::ZScore { key, member } => self.handle_zscore(&key, member),
+            Command::ZMScore { key, members } => self.handle_zmscore(&key, &members),
+            Command::ZCard(key) => self.handle_zcard(&key),
+            Command::ZRank {
+                key,
+                member,
+                rev,
+                with_score,
+            } => self.handle_zrank(&key, &member, rev, with_score),
+            Command::ZRange {
+                key,
+                by,
+                rev,
+                limit,
+                with_scores,
+                destination,
+            } => self.handle_zrange(&key, &by, rev, limit, with_scores, destination),
+            Command::ZCount { key, range } => self.handle_zcount(&key, &range),
+            Command::ZLexCount { key, range } => self.handle_zcount(&key, &range),
+            Command::ZPop { key, count, max } => self.handle_zpop(&key, count, max),
+            Command::ZCombineStore {
+                destination,
+                keys,
+                weights,
+                aggregate,
+                inter,
+            } => self.handle_zcombine_store(destination, &keys, &weights, aggregate, inter),
+            Command::ZRandMember {
+                key,
+                count,
+                with_scores,
+            } => self.handle_zrandmember(&key, count, with_scores),
+            Command::XAdd {
+                key,
+                id,
+                fields,
+                no_create,
+                trim,
+            } => self.handle_xadd(key, id, fields, no_create, trim),
+            Command::XRange {
+                key,
+                start,
+                end,
+                count,
+                rev,
+            } => self.handle_xrange(&key, start, end, count, rev),
+            Command::XRead {
+                keys,
+                op,
+                count,
+                block,
+            } => self.handle_xread(keys, op, count, block),
+            Command::XLen(key) => self.handle_xlen(&key),
+            Command::XDel { key, ids } => self.handle_xdel(&key, &ids),
+            Command::XTrim { key, trim, limit } => self.handle_xtrim(&key, trim, limit),
+            Command::XGroup(xgroup) => self.handle_xgroup(xgroup),
+            Command::XAck { key, group, ids } => self.handle_xack(&key, &group, &ids),
+            Command::XPending { key, group, range } => self.handle_xpending(&key, &group, range),
+            Command::XClaim {
+                key,
+                claim,
+                ids,
+                options,
+            } => self.handle_xclaim(&key, &claim, &ids, &options),
+            Command::XAutoClaim {
+                key,
+                claim,
+                start,
+                count,
+            } => self.handle_xautoclaim(&key, &claim, start, count),
+            Command::XInfo(xinfo) => self.handle_xinfo(xinfo),
+            Command::Subscribe { kind, channels } => self.handle_subscribe(kind, channels),
+            Command::Unsubscribe { kind, channels } => self.handle_unsubscribe(kind, channels),
+            Command::Publish {
+                channel,
+                message,
+                sharded,
+            } => self.handle_publish(channel, message, sharded),
+            Command::PubSub(pubsub) => self.handle_pubsub(pubsub),
+            Command::Keys(pattern) => self.handle_keys(&pattern),
+            Command::IncrBy { key, increment } => self.handle_incr_by(key, increment),
+            Command::IncrByFloat { key, increment } => self.handle_incr_by_float(key, increment),
+            Command::Scan {
+                cursor,
+                pattern,
+                count,
+                key_type,
+            } => self.handle_scan(cursor, pattern.as_deref(), count, key_type.as_deref()),
             Command::Info(key) => self.handle_info(key.as_deref()),
             Command::ReplConf(conf) => self.handle_replconf(conf),
             Command::Psync { replica_id, offset } => self.handle_psync(replica_id, offset),
+            Command::Hello {
+                protover,
+                auth,
+                setname,
+            } => self.handle_hello(protover, auth, setname),
         }?;
 
-        Ok(response.serialize())
+        Ok(response)
     }
 
     fn handle_ping(&self) -> anyhow::Result<Response> {
+        // A subscribed RESP2 client can only tell replies from messages by
+        // their shape, so it gets an array
+        if self.in_subscribe_mode() {
+            return Ok(Response::Array(vec![
+                Response::BulkString("pong".into()),
+                Response::BulkString(Bytes::new()),
+            ]));
+        }
+
         Ok(Response::Pong)
     }
 
-    fn handle_echo(&self, arg: &str) -> anyhow::Result<Response> {
-        Ok(Response::BulkString(arg.to_owned()))
+    fn handle_echo(&self, arg: Bytes) -> anyhow::Result<Response> {
+        Ok(Response::BulkString(arg))
     }
 
     fn handle_set(
         &mut self,
-        key: &str,
-        value: &str,
-        expiry: Option<u64>,
+        key: Bytes,
+        value: Bytes,
+        expiry: Option<Expiry>,
+        condition: Option<SetCondition>,
+        keep_ttl: bool,
+        get: bool,
     ) -> anyhow::Result<Response> {
-        self.store.insert(key, value, expiry)?;
+        let expiry = expiry
+            .map(|expiry| expiry.deadline())
+            .map(|deadline| deadline.ok_or(CommandError::InvalidExpireTime("set".to_owned())))
+            .transpose()?;
 
-        Ok(Response::OK)
+        let (set, old) = self
+            .store
+            .set(key, value, expiry, keep_ttl, condition, get)?;
+
+        if get {
+            Ok(old.map_or(Response::Null, Response::BulkString))
+        } else if set {
+            Ok(Response::OK)
+        } else {
+            Ok(Response::Null)
+        }
     }
 
-    fn handle_get(&self, key: &str) -> anyhow::Result<Response> {
-        let item = self.store.get(key);
+    fn handle_get(&self, key: &[u8]) -> anyhow::Result<Response> {
+        let item = self.store.get(key)?;
 
         Ok(item.map_or(Response::Null, Response::BulkString))
     }
 
-    fn handle_info(&self, _key: Option<&str>) -> anyhow::Result<Response> {
-        let config = CONFIG.get().ok_or(anyhow!("Unable to get config"))?;
-        let role = if config.master.is_none() {
-            "master"
+    fn handle_append(&mut self, key: Bytes, value: &[u8]) -> anyhow::Result<Response> {
+        let len = self.store.append(key, value)?;
+
+        Ok(Response::Int(len as i64))
+    }
+
+    fn handle_strlen(&self, key: &[u8]) -> anyhow::Result<Response> {
+        let len = self.store.get(key)?.map_or(0, |value| value.len());
+
+        Ok(Response::Int(len as i64))
+    }
+
+    fn handle_get_range(&self, key: &[u8], start: i64, end: i64) -> anyhow::Result<Response> {
+        let value = self.store.get(key)?.unwrap_or_default();
+        let len = value.len() as i64;
+
+        // Negative offsets count from the end of the string
+        let start = if start < 0 {
+            (len + start).max(0)
         } else {
-            "slave"
+            start
         };
+        let end = if end < 0 { (len + end).max(0) } else { end }.min(len - 1);
 
-        let master_replid = &config.master_replid;
-        let master_repl_offset = config.master_repl_offset;
+        if len == 0 || start > end {
+            return Ok(Response::BulkString(Bytes::new()));
+        }
 
-        let result = format!(
-            "# Replication\nrole:{}\nmaster_replid:{}\nmaster_repl_offset:{}",
-            role, master_replid, master_repl_offset
-        );
+        Ok(Response::BulkString(
+            value.slice(start as usize..=end as usize),
+        ))
+    }
+
+    fn handle_set_range(
+        &mut self,
+        key: Bytes,
+        offset: i64,
+        value: &[u8],
+    ) -> anyhow::Result<Response> {
+        let offset = usize::try_from(offset).map_err(|_| StoreError::OffsetOutOfRange)?;
 
-        Ok(Response::BulkString(result))
+        let len = self.store.set_range(key, offset, value)?;
+
+        Ok(Response::Int(len as i64))
     }
 
-    fn handle_replconf(&self, _conf: super::ReplConf) -> anyhow::Result<Response> {
+    fn handle_get_del(&mut self, key: &[u8]) -> anyhow::Result<Response> {
+        let value = self.store.get_del(key)?;
+
+        Ok(value.map_or(Response::Null, Response::BulkString))
+    }
+
+    fn handle_get_ex(
+        &mut self,
+        key: &[u8],
+        expiry: Option<Expiry>,
+        persist: bool,
+    ) -> anyhow::Result<Response> {
+        let expiry = match expiry {
+            Some(expiry) => {
+                Some(Some(expiry.deadline().ok_or(
+                    CommandError::InvalidExpireTime("getex".to_owned()),
+                )?))
+            }
+            None if persist => Some(None),
+            None => None,
+        };
+
+        let value = self.store.get_ex(key, expiry)?;
+
+        Ok(value.map_or(Response::Null, Response::BulkString))
+    }
+
+    fn handle_mget(&self, keys: &[Bytes]) -> anyhow::Result<Response> {
+        let values = self.store.mget(keys);
+
+        Ok(Response::Array(
+            values
+                .into_iter()
+                .map(|value| value.map_or(Response::Null, Response::BulkString))
+                .collect(),
+        ))
+    }
+
+    fn handle_mset(&mut self, pairs: Vec<(Bytes, Bytes)>, nx: bool) -> anyhow::Result<Response> {
+        let set = self.store.mset(pairs, nx);
+
+        if nx {
+            Ok(Response::Int(set as i64))
+        } else {
+            Ok(Response::OK)
+        }
+    }
+
+    fn handle_expire(
+        &mut self,
+        key: &[u8],
+        expiry: Expiry,
+        condition: ExpireCondition,
+    ) -> anyhow::Result<Response> {
+        let deadline = expiry.deadline().ok_or(CommandError::InvalidExpireTime(
+            expiry.command_name().to_owned(),
+        ))?;
+
+        let updated = self.store.expire(key, deadline, condition);
+
+        Ok(Response::Int(updated as i64))
+    }
+
+    fn handle_ttl(&self, key: &[u8], millis: bool, absolute: bool) -> anyhow::Result<Response> {
+        let ttl = to_ttl(self.store.expiry(key), millis, absolute);
+
+        Ok(Response::Int(ttl))
+    }
+
+    fn handle_persist(&mut self, key: &[u8]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.persist(key) as i64))
+    }
+
+    fn handle_del(&mut self, keys: &[Bytes]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.del(keys) as i64))
+    }
+
+    fn handle_exists(&self, keys: &[Bytes]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.exists(keys) as i64))
+    }
+
+    fn handle_type(&self, key: &[u8]) -> anyhow::Result<Response> {
+        let key_type = self.store.key_type(key).unwrap_or("none");
+
+        Ok(Response::SimpleString(key_type.to_owned()))
+    }
+
+    fn handle_rename(&mut self, key: &[u8], new_key: Bytes, nx: bool) -> anyhow::Result<Response> {
+        let renamed = self
+            .store
+            .rename(key, new_key, nx)
+            .ok_or(CommandError::Other("no such key".to_owned()))?;
+
+        if nx {
+            Ok(Response::Int(renamed as i64))
+        } else {
+            Ok(Response::OK)
+        }
+    }
+
+    fn handle_incr_by(&mut self, key: Bytes, increment: i64) -> anyhow::Result<Response> {
+        let value = self.store.incr_by(key, increment)?;
+
+        Ok(Response::Int(value))
+    }
+
+    fn handle_incr_by_float(&mut self, key: Bytes, increment: f64) -> anyhow::Result<Response> {
+        let value = self.store.incr_by_float(key, increment)?;
+
+        Ok(Response::BulkString(value))
+    }
+
+    fn handle_push(
+        &mut self,
+        key: Bytes,
+        values: Vec<Bytes>,
+        end: ListEnd,
+        only_existing: bool,
+    ) -> anyhow::Result<Response> {
+        let len = self.store.push(key, values, end, only_existing)?;
+
+        Ok(Response::Int(len as i64))
+    }
+
+    fn handle_pop(
+        &mut self,
+        key: &[u8],
+        end: ListEnd,
+        count: Option<usize>,
+    ) -> anyhow::Result<Response> {
+        let popped = self.store.pop(key, end, count.unwrap_or(1))?;
+
+        // Without a count, the reply is a single element rather than an array
+        match (popped, count) {
+            (None, None) => Ok(Response::Null),
+            (None, Some(_)) => Ok(Response::NullArray),
+            (Some(mut popped), None) => {
+                Ok(popped.pop().map_or(Response::Null, Response::BulkString))
+            }
+            (Some(popped), Some(_)) => Ok(Response::Array(
+                popped.into_iter().map(Response::BulkString).collect(),
+            )),
+        }
+    }
+
+    fn handle_lrange(&self, key: &[u8], start: i64, stop: i64) -> anyhow::Result<Response> {
+        let elements = self.store.lrange(key, start, stop)?;
+
+        Ok(Response::Array(
+            elements.into_iter().map(Response::BulkString).collect(),
+        ))
+    }
+
+    fn handle_llen(&self, key: &[u8]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.llen(key)? as i64))
+    }
+
+    fn handle_lindex(&self, key: &[u8], index: i64) -> anyhow::Result<Response> {
+        let element = self.store.lindex(key, index)?;
+
+        Ok(element.map_or(Response::Null, Response::BulkString))
+    }
+
+    fn handle_lset(&mut self, key: &[u8], index: i64, value: Bytes) -> anyhow::Result<Response> {
+        self.store.lset(key, index, value)?;
+
         Ok(Response::OK)
     }
 
-    fn handle_psync(&self, _replica_id: String, _offset: i32) -> anyhow::Result<Response> {
-        let config = CONFIG.get().ok_or(anyhow!("Unable to get config"))?;
-        let repl_id = &config.master_replid;
-        let offset = config.master_repl_offset;
+    fn handle_lrem(&mut self, key: &[u8], count: i64, element: &[u8]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.lrem(key, count, element)? as i64))
+    }
 
-        let rdb_file = Response::File(EMPTY_RDB.into());
+    fn handle_ltrim(&mut self, key: &[u8], start: i64, stop: i64) -> anyhow::Result<Response> {
+        self.store.ltrim(key, start, stop)?;
 
-        let response = vec![
-            Response::SimpleString(format!("FULLRESYNC {} {}", repl_id, offset)),
-            rdb_file,
-        ];
+        Ok(Response::OK)
+    }
 
-        Ok(Response::Seq(response))
+    fn handle_linsert(
+        &mut self,
+        key: &[u8],
+        before: bool,
+        pivot: &[u8],
+        element: Bytes,
+    ) -> anyhow::Result<Response> {
+        Ok(Response::Int(
+            self.store.linsert(key, before, pivot, element)?,
+        ))
+    }
+
+    fn handle_lpos(
+        &self,
+        key: &[u8],
+        element: &[u8],
+        rank: i64,
+        count: Option<usize>,
+        maxlen: usize,
+    ) -> anyhow::Result<Response> {
+        let positions = self
+            .store
+            .lpos(key, element, rank, count.unwrap_or(1), maxlen)?;
+
+        // Without a count, the reply is a single position rather than an array
+        match count {
+            None => Ok(positions
+                .first()
+                .map_or(Response::Null, |&pos| Response::Int(pos as i64))),
+            Some(_) => Ok(Response::Array(
+                positions
+                    .into_iter()
+                    .map(|pos| Response::Int(pos as i64))
+                    .collect(),
+            )),
+        }
+    }
+
+    fn handle_lmove(
+        &mut self,
+        source: &[u8],
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> anyhow::Result<Response> {
+        let element = self.store.lmove(source, destination, from, to)?;
+
+        Ok(element.map_or(Response::Null, Response::BulkString))
+    }
+
+    /// Runs `op` on the first of `keys` holding a list, blocking for up to
+    /// `timeout` if there's none. `reply` builds the response from what was
+    /// popped, which is `None` on a timeout.
+    fn block_on(
+        &mut self,
+        keys: Vec<Bytes>,
+        op: BlockingOp,
+        timeout: Option<Duration>,
+        reply: impl FnOnce(Option<Served>) -> Response + Send + 'static,
+    ) -> anyhow::Result<Response> {
+        match self.store.pop_or_block(keys, op)? {
+            Blocking::Ready(served) => Ok(reply(Some(served))),
+            Blocking::Blocked(client) => Ok(Response::Blocked(Box::pin(async move {
+                Ok(reply(client.wait(timeout).await?))
+            }))),
+        }
+    }
+
+    fn handle_bpop(
+        &mut self,
+        keys: Vec<Bytes>,
+        end: ListEnd,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Response> {
+        let op = BlockingOp::Pop { end, count: 1 };
+
+        self.block_on(keys, op, timeout, |served| match served {
+            Some((key, mut elements)) => Response::Array(vec![
+                Response::BulkString(key),
+                elements.pop().map_or(Response::Null, Response::BulkString),
+            ]),
+            None => Response::NullArray,
+        })
+    }
+
+    fn handle_mpop(
+        &mut self,
+        keys: Vec<Bytes>,
+        end: ListEnd,
+        count: usize,
+        block: bool,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Response> {
+        let op = BlockingOp::Pop { end, count };
+
+        let reply = |served: Option<Served>| match served {
+            Some((key, elements)) => Response::Array(vec![
+                Response::BulkString(key),
+                Response::Array(elements.into_iter().map(Response::BulkString).collect()),
+            ]),
+            None => Response::NullArray,
+        };
+
+        if block {
+            self.block_on(keys, op, timeout, reply)
+        } else {
+            Ok(reply(self.store.pop_first(&keys, op)?))
+        }
+    }
+
+    fn handle_blmove(
+        &mut self,
+        source: Bytes,
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Response> {
+        let op = BlockingOp::Move {
+            destination,
+            from,
+            to,
+        };
+
+        self.block_on(vec![source], op, timeout, |served| {
+            served
+                .and_then(|(_, mut elements)| elements.pop())
+                .map_or(Response::Null, Response::BulkString)
+        })
+    }
+
+    fn handle_hset(
+        &mut self,
+        key: Bytes,
+        pairs: Vec<(Bytes, Bytes)>,
+        multi: bool,
+    ) -> anyhow::Result<Response> {
+        let added = self.store.hset(key, pairs)?;
+
+        // HMSET is the deprecated form, which replies with OK
+        if multi {
+            Ok(Response::OK)
+        } else {
+            Ok(Response::Int(added as i64))
+        }
+    }
+
+    fn handle_hsetnx(
+        &mut self,
+        key: Bytes,
+        field: Bytes,
+        value: Bytes,
+    ) -> anyhow::Result<Response> {
+        let set = self.store.hsetnx(key, field, value)?;
+
+        Ok(Response::Int(set as i64))
+    }
+
+    fn handle_hget(&self, key: &[u8], field: &[u8]) -> anyhow::Result<Response> {
+        let value = self.store.hget(key, field)?;
+
+        Ok(value.map_or(Response::Null, Response::BulkString))
+    }
+
+    fn handle_hmget(&self, key: &[u8], fields: &[Bytes]) -> anyhow::Result<Response> {
+        let values = self.store.hmget(key, fields)?;
+
+        Ok(Response::Array(
+            values
+                .into_iter()
+                .map(|value| value.map_or(Response::Null, Response::BulkString))
+                .collect(),
+        ))
+    }
+
+    fn handle_hdel(&mut self, key: &[u8], fields: &[Bytes]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.hdel(key, fields)? as i64))
+    }
+
+    fn handle_hgetall(&self, key: &[u8]) -> anyhow::Result<Response> {
+        let pairs = self.store.hgetall(key)?;
+
+        Ok(Response::Map(
+            pairs
+                .into_iter()
+                .map(|(field, value)| (Response::BulkString(field), Response::BulkString(value)))
+                .collect(),
+        ))
+    }
+
+    fn handle_hkeys(&self, key: &[u8]) -> anyhow::Result<Response> {
+        let pairs = self.store.hgetall(key)?;
+
+        Ok(Response::Array(
+            pairs
+                .into_iter()
+                .map(|(field, _)| Response::BulkString(field))
+                .collect(),
+        ))
+    }
+
+    fn handle_hvals(&self, key: &[u8]) -> anyhow::Result<Response> {
+        let pairs = self.store.hgetall(key)?;
+
+        Ok(Response::Array(
+            pairs
+                .into_iter()
+                .map(|(_, value)| Response::BulkString(value))
+                .collect(),
+        ))
+    }
+
+    fn handle_hlen(&self, key: &[u8]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.hlen(key)? as i64))
+    }
+
+    fn handle_hexists(&self, key: &[u8], field: &[u8]) -> anyhow::Result<Response> {
+        let exists = self.store.hget(key, field)?.is_some();
+
+        Ok(Response::Int(exists as i64))
+    }
+
+    fn handle_hstrlen(&self, key: &[u8], field: &[u8]) -> anyhow::Result<Response> {
+        let len = self.store.hget(key, field)?.map_or(0, |value| value.len());
+
+        Ok(Response::Int(len as i64))
+    }
+
+    fn handle_hincr_by(
+        &mut self,
+        key: Bytes,
+        field: Bytes,
+        increment: i64,
+    ) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.hincr_by(key, field, increment)?))
+    }
+
+    fn handle_hincr_by_float(
+        &mut self,
+        key: Bytes,
+        field: Bytes,
+        increment: f64,
+    ) -> anyhow::Result<Response> {
+        let value = self.store.hincr_by_float(key, field, increment)?;
+
+        Ok(Response::BulkString(value))
+    }
+
+    fn handle_hexpire(
+        &mut self,
+        key: &[u8],
+        expiry: Expiry,
+        condition: ExpireCondition,
+        fields: &[Bytes],
+    ) -> anyhow::Result<Response> {
+        let cmd_name = format!("h{}", expiry.command_name());
+
+        let deadline = expiry
+            .deadline()
+            .ok_or(CommandError::InvalidExpireTime(cmd_name))?;
+
+        let codes = self.store.hexpire(key, fields, deadline, condition)?;
+
+        Ok(Response::Array(
+            codes.into_iter().map(Response::Int).collect(),
+        ))
+    }
+
+    fn handle_httl(
+        &self,
+        key: &[u8],
+        fields: &[Bytes],
+        millis: bool,
+        absolute: bool,
+    ) -> anyhow::Result<Response> {
+        let expiries = self.store.hexpiry(key, fields)?;
+
+        Ok(Response::Array(
+            expiries
+                .into_iter()
+                .map(|expiry| Response::Int(to_ttl(expiry, millis, absolute)))
+                .collect(),
+        ))
+    }
+
+    fn handle_hpersist(&mut self, key: &[u8], fields: &[Bytes]) -> anyhow::Result<Response> {
+        let codes = self.store.hpersist(key, fields)?;
+
+        Ok(Response::Array(
+            codes.into_iter().map(Response::Int).collect(),
+        ))
+    }
+
+    fn handle_hrandfield(
+        &mut self,
+        key: &[u8],
+        count: Option<i64>,
+        with_values: bool,
+    ) -> anyhow::Result<Response> {
+        let pairs = self.store.hrandfield(key, count.unwrap_or(1))?;
+
+        let Some(_) = count else {
+            return Ok(pairs
+                .into_iter()
+                .next()
+                .map_or(Response::Null, |(field, _)| Response::BulkString(field)));
+        };
+
+        let reply = if !with_values {
+            pairs
+                .into_iter()
+                .map(|(field, _)| Response::BulkString(field))
+                .collect()
+        } else if self.protocol == Protocol::Resp3 {
+            // RESP3 clients get each field paired with its value
+            pairs
+                .into_iter()
+                .map(|(field, value)| {
+                    Response::Array(vec![
+                        Response::BulkString(field),
+                        Response::BulkString(value),
+                    ])
+                })
+                .collect()
+        } else {
+            pairs
+                .into_iter()
+                .flat_map(|(field, value)| {
+                    [Response::BulkString(field), Response::BulkString(value)]
+                })
+                .collect()
+        };
+
+        Ok(Response::Array(reply))
+    }
+
+    fn handle_hscan(
+        &self,
+        key: &[u8],
+        cursor: u64,
+        pattern: Option<&[u8]>,
+        count: usize,
+        no_values: bool,
+    ) -> anyhow::Result<Response> {
+        let (cursor, pairs) = self.store.hscan(key, cursor, count, pattern)?;
+
+        let mut reply = vec![];
+
+        for (field, value) in pairs {
+            reply.push(Response::BulkString(field));
+
+            if !no_values {
+                reply.push(Response::BulkString(value));
+            }
+        }
+
+        Ok(Response::Array(vec![
+            Response::BulkString(cursor.to_string().into()),
+            Response::Array(reply),
+        ]))
+    }
+
+    fn handle_sadd(&mut self, key: Bytes, members: Vec<Bytes>) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.sadd(key, members)? as i64))
+    }
+
+    fn handle_srem(&mut self, key: &[u8], members: &[Bytes]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.srem(key, members)? as i64))
+    }
+
+    fn handle_smembers(&self, key: &[u8]) -> anyhow::Result<Response> {
+        let members = self.store.smembers(key)?;
+
+        Ok(Response::Set(
+            members.into_iter().map(Response::BulkString).collect(),
+        ))
+    }
+
+    fn handle_sismember(&self, key: &[u8], member: Bytes) -> anyhow::Result<Response> {
+        let found = self.store.smismember(key, &[member])?;
+
+        Ok(Response::Int(found[0] as i64))
+    }
+
+    fn handle_smismember(&self, key: &[u8], members: &[Bytes]) -> anyhow::Result<Response> {
+        let found = self.store.smismember(key, members)?;
+
+        Ok(Response::Array(
+            found
+                .into_iter()
+                .map(|found| Response::Int(found as i64))
+                .collect(),
+        ))
+    }
+
+    fn handle_scard(&self, key: &[u8]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.scard(key)? as i64))
+    }
+
+    fn handle_spop(&mut self, key: &[u8], count: Option<usize>) -> anyhow::Result<Response> {
+        let popped = self.store.spop(key, count.unwrap_or(1))?;
+
+        // Without a count, the reply is a single member rather than a set
+        match count {
+            None => Ok(popped
+                .into_iter()
+                .next()
+                .map_or(Response::Null, Response::BulkString)),
+            Some(_) => Ok(Response::Set(
+                popped.into_iter().map(Response::BulkString).collect(),
+            )),
+        }
+    }
+
+    fn handle_srandmember(&mut self, key: &[u8], count: Option<i64>) -> anyhow::Result<Response> {
+        let members = self.store.srandmember(key, count.unwrap_or(1))?;
+
+        match count {
+            None => Ok(members
+                .into_iter()
+                .next()
+                .map_or(Response::Null, Response::BulkString)),
+            Some(_) => Ok(Response::Array(
+                members.into_iter().map(Response::BulkString).collect(),
+            )),
+        }
+    }
+
+    fn handle_set_op(&self, op: SetOp, keys: &[Bytes]) -> anyhow::Result<Response> {
+        let members = self.store.combine_sets(op, keys)?;
+
+        Ok(Response::Set(
+            members.into_iter().map(Response::BulkString).collect(),
+        ))
+    }
+
+    fn handle_set_op_store(
+        &mut self,
+        op: SetOp,
+        destination: Bytes,
+        keys: &[Bytes],
+    ) -> anyhow::Result<Response> {
+        let len = self.store.combine_sets_store(op, destination, keys)?;
+
+        Ok(Response::Int(len as i64))
+    }
+
+    fn handle_sintercard(&self, keys: &[Bytes], limit: usize) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.sintercard(keys, limit)? as i64))
+    }
+
+    fn handle_smove(
+        &mut self,
+        source: &[u8],
+        destination: Bytes,
+        member: Bytes,
+    ) -> anyhow::Result<Response> {
+        let moved = self.store.smove(source, destination, member)?;
+
+        Ok(Response::Int(moved as i64))
+    }
+
+    fn handle_sscan(
+        &self,
+        key: &[u8],
+        cursor: u64,
+        pattern: Option<&[u8]>,
+        count: usize,
+    ) -> anyhow::Result<Response> {
+        let (cursor, members) = self.store.sscan(key, cursor, count, pattern)?;
+
+        Ok(Response::Array(vec![
+            Response::BulkString(cursor.to_string().into()),
+            Response::Array(members.into_iter().map(Response::BulkString).collect()),
+        ]))
+    }
+
+    /// The reply for sorted set elements. With scores, RESP3 clients get
+    /// each member paired with its score, while RESP2 clients get them
+    /// interleaved.
+    fn scored_elements(&self, elements: Vec<(Bytes, f64)>, with_scores: bool) -> Response {
+        let reply = if !with_scores {
+            elements
+                .into_iter()
+                .map(|(member, _)| Response::BulkString(member))
+                .collect()
+        } else if self.protocol == Protocol::Resp3 {
+            elements
+                .into_iter()
+                .map(|(member, score)| {
+                    Response::Array(vec![Response::BulkString(member), Response::Double(score)])
+                })
+                .collect()
+        } else {
+            elements
+                .into_iter()
+                .flat_map(|(member, score)| [Response::BulkString(member), Response::Double(score)])
+                .collect()
+        };
+
+        Response::Array(reply)
+    }
+
+    fn handle_zadd(
+        &mut self,
+        key: Bytes,
+        pairs: Vec<(f64, Bytes)>,
+        flags: ZAddFlags,
+    ) -> anyhow::Result<Response> {
+        let (count, score) = self.store.zadd(key, pairs, flags)?;
+
+        // With INCR, the reply is the new score like ZINCRBY's
+        if flags.incr {
+            Ok(score.map_or(Response::Null, Response::Double))
+        } else {
+            Ok(Response::Int(count as i64))
+        }
+    }
+
+    fn handle_zrem(&mut self, key: &[u8], members: &[Bytes]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.zrem(key, members)? as i64))
+    }
+
+    fn handle_zscore(&self, key: &[u8], member: Bytes) -> anyhow::Result<Response> {
+        let scores = self.store.zmscore(key, &[member])?;
+
+        Ok(scores[0].map_or(Response::Null, Response::Double))
+    }
+
+    fn handle_zmscore(&self, key: &[u8], members: &[Bytes]) -> anyhow::Result<Response> {
+        let scores = self.store.zmscore(key, members)?;
+
+        Ok(Response::Array(
+            scores
+                .into_iter()
+                .map(|score| score.map_or(Response::Null, Response::Double))
+                .collect(),
+        ))
+    }
+
+    fn handle_zcard(&self, key: &[u8]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.zcard(key)? as i64))
+    }
+
+    fn handle_zrank(
+        &self,
+        key: &[u8],
+        member: &[u8],
+        rev: bool,
+        with_score: bool,
+    ) -> anyhow::Result<Response> {
+        let rank = self.store.zrank(key, member, rev)?;
+
+        Ok(match rank {
+            Some((rank, score)) if with_score => {
+                Response::Array(vec![Response::Int(rank as i64), Response::Double(score)])
+            }
+            Some((rank, _)) => Response::Int(rank as i64),
+            None if with_score => Response::NullArray,
+            None => Response::Null,
+        })
+    }
+
+    fn handle_zrange(
+        &mut self,
+        key: &[u8],
+        by: &ZRangeBy,
+        rev: bool,
+        limit: Option<(i64, i64)>,
+        with_scores: bool,
+        destination: Option<Bytes>,
+    ) -> anyhow::Result<Response> {
+        if let Some(destination) = destination {
+            let len = self.store.zrangestore(destination, key, by, rev, limit)?;
+            return Ok(Response::Int(len as i64));
+        }
+
+        let elements = self.store.zrange(key, by, rev, limit)?;
+
+        Ok(self.scored_elements(elements, with_scores))
+    }
+
+    fn handle_zcount(&self, key: &[u8], range: &impl ZRange) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.zcount(key, range)? as i64))
+    }
+
+    fn handle_zpop(
+        &mut self,
+        key: &[u8],
+        count: Option<usize>,
+        max: bool,
+    ) -> anyhow::Result<Response> {
+        let popped = self.store.zpop(key, count.unwrap_or(1), max)?;
+
+        // Without a count, the reply is a single member and score pair
+        match count {
+            None => Ok(Response::Array(
+                popped
+                    .into_iter()
+                    .flat_map(|(member, score)| {
+                        [Response::BulkString(member), Response::Double(score)]
+                    })
+                    .collect(),
+            )),
+            Some(_) => Ok(self.scored_elements(popped, true)),
+        }
+    }
+
+    fn handle_zcombine_store(
+        &mut self,
+        destination: Bytes,
+        keys: &[Bytes],
+        weights: &[f64],
+        aggregate: Aggregate,
+        inter: bool,
+    ) -> anyhow::Result<Response> {
+        let len = self
+            .store
+            .zcombine_store(destination, keys, weights, aggregate, inter)?;
+
+        Ok(Response::Int(len as i64))
+    }
+
+    fn handle_zrandmember(
+        &mut self,
+        key: &[u8],
+        count: Option<i64>,
+        with_scores: bool,
+    ) -> anyhow::Result<Response> {
+        let elements = self.store.zrandmember(key, count.unwrap_or(1))?;
+
+        match count {
+            None => Ok(elements
+                .into_iter()
+                .next()
+                .map_or(Response::Null, |(member, _)| Response::BulkString(member))),
+            Some(_) => Ok(self.scored_elements(elements, with_scores)),
+        }
+    }
+
+    fn handle_xadd(
+        &mut self,
+        key: Bytes,
+        id: NewStreamId,
+        fields: Vec<(Bytes, Bytes)>,
+        no_create: bool,
+        trim: Option<(StreamTrim, Option<usize>)>,
+    ) -> anyhow::Result<Response> {
+        let id = self.store.xadd(key, id, fields, no_create, trim)?;
+
+        Ok(id.map_or(Response::Null, |id| {
+            Response::BulkString(id.to_string().into())
+        }))
+    }
+
+    fn handle_xrange(
+        &self,
+        key: &[u8],
+        start: StreamId,
+        end: StreamId,
+        count: usize,
+        rev: bool,
+    ) -> anyhow::Result<Response> {
+        let entries = self.store.xrange(key, start..=end, count, rev)?;
+
+        Ok(stream_entries(entries))
+    }
+
+    fn handle_xread(
+        &mut self,
+        keys: Vec<Bytes>,
+        op: StreamReadOp,
+        count: usize,
+        block: Option<Option<Duration>>,
+    ) -> anyhow::Result<Response> {
+        let protocol = self.protocol;
+
+        // Streams without new entries are left out, and there's a null
+        // reply if there are none at all
+        let reply = move |read: Option<StreamsRead>| match read {
+            Some(read) if !read.is_empty() => streams_read(read, protocol),
+            _ => Response::NullArray,
+        };
+
+        let timeout = block.flatten();
+
+        match self.store.xread(keys, op, count, block.is_some())? {
+            StreamBlocking::Ready(read) => Ok(reply(Some(read))),
+            StreamBlocking::Blocked(reader) => Ok(Response::Blocked(Box::pin(async move {
+                Ok(reply(reader.wait(timeout).await?))
+            }))),
+        }
+    }
+
+    fn handle_xgroup(&mut self, xgroup: XGroup) -> anyhow::Result<Response> {
+        match xgroup {
+            XGroup::Create {
+                key,
+                group,
+                id,
+                make_stream,
+                entries_read,
+            } => {
+                self.store
+                    .xgroup_create(key, group, id, make_stream, entries_read)?;
+                Ok(Response::OK)
+            }
+            XGroup::SetId {
+                key,
+                group,
+                id,
+                entries_read,
+            } => {
+                self.store.xgroup_setid(&key, &group, id, entries_read)?;
+                Ok(Response::OK)
+            }
+            XGroup::Destroy { key, group } => {
+                let destroyed = self.store.xgroup_destroy(&key, &group)?;
+                Ok(Response::Int(destroyed as i64))
+            }
+            XGroup::CreateConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let created = self.store.xgroup_create_consumer(&key, &group, &consumer)?;
+                Ok(Response::Int(created as i64))
+            }
+            XGroup::DelConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let pending = self.store.xgroup_del_consumer(&key, &group, &consumer)?;
+                Ok(Response::Int(pending as i64))
+            }
+        }
+    }
+
+    fn handle_xack(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        ids: &[StreamId],
+    ) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.xack(key, group, ids)? as i64))
+    }
+
+    fn handle_xpending(
+        &self,
+        key: &[u8],
+        group: &[u8],
+        range: Option<PendingRange>,
+    ) -> anyhow::Result<Response> {
+        let Some(range) = range else {
+            let summary = self.store.xpending_summary(key, group)?;
+
+            let (min, max) = summary
+                .bounds
+                .map_or((Response::Null, Response::Null), |(min, max)| {
+                    (stream_id(min), stream_id(max))
+                });
+
+            let consumers = if summary.consumers.is_empty() {
+                Response::NullArray
+            } else {
+                Response::Array(
+                    summary
+                        .consumers
+                        .into_iter()
+                        .map(|(name, count)| {
+                            Response::Array(vec![
+                                Response::BulkString(name),
+                                Response::BulkString(count.to_string().into()),
+                            ])
+                        })
+                        .collect(),
+                )
+            };
+
+            return Ok(Response::Array(vec![
+                Response::Int(summary.count as i64),
+                min,
+                max,
+                consumers,
+            ]));
+        };
+
+        let now = now_millis();
+        let pending = self.store.xpending(key, group, &range)?;
+
+        Ok(Response::Array(
+            pending
+                .into_iter()
+                .map(|(id, entry)| {
+                    Response::Array(vec![
+                        stream_id(id),
+                        Response::BulkString(entry.consumer),
+                        Response::Int(now.saturating_sub(entry.delivery_time) as i64),
+                        Response::Int(entry.delivery_count as i64),
+                    ])
+                })
+                .collect(),
+        ))
+    }
+
+    fn handle_xclaim(
+        &mut self,
+        key: &[u8],
+        claim: &Claim,
+        ids: &[StreamId],
+        options: &ClaimOptions,
+    ) -> anyhow::Result<Response> {
+        let claimed = self.store.xclaim(key, claim, ids, options)?;
+
+        Ok(claimed_entries(claimed, claim.just_id))
+    }
+
+    fn handle_xautoclaim(
+        &mut self,
+        key: &[u8],
+        claim: &Claim,
+        start: StreamId,
+        count: usize,
+    ) -> anyhow::Result<Response> {
+        let (cursor, claimed, deleted) = self.store.xautoclaim(key, claim, start, count)?;
+
+        Ok(Response::Array(vec![
+            stream_id(cursor),
+            claimed_entries(claimed, claim.just_id),
+            Response::Array(deleted.into_iter().map(stream_id).collect()),
+        ]))
+    }
+
+    fn handle_xinfo(&self, xinfo: XInfo) -> anyhow::Result<Response> {
+        let field = |name: &str| Response::BulkString(Bytes::copy_from_slice(name.as_bytes()));
+        let optional_int =
+            |value: Option<u64>| value.map_or(Response::Null, |x| Response::Int(x as i64));
+
+        match xinfo {
+            XInfo::Stream(key) => {
+                let info = self.store.xinfo_stream(&key)?;
+                let entry = |entry: Option<StreamEntry>| {
+                    entry.map_or(Response::Null, |(id, fields)| {
+                        stream_entry(id, Some(fields))
+                    })
+                };
+
+                Ok(Response::Map(vec![
+                    (field("length"), Response::Int(info.length as i64)),
+                    (field("last-generated-id"), stream_id(info.last_id)),
+                    (
+                        field("max-deleted-entry-id"),
+                        stream_id(info.max_deleted_id),
+                    ),
+                    (
+                        field("entries-added"),
+                        Response::Int(info.entries_added as i64),
+                    ),
+                    (field("recorded-first-entry-id"), stream_id(info.first_id)),
+                    (field("groups"), Response::Int(info.groups as i64)),
+                    (field("first-entry"), entry(info.first_entry)),
+                    (field("last-entry"), entry(info.last_entry)),
+                ]))
+            }
+            XInfo::Groups(key) => {
+                let groups = self.store.xinfo_groups(&key)?;
+
+                Ok(Response::Array(
+                    groups
+                        .into_iter()
+                        .map(|group| {
+                            Response::Map(vec![
+                                (field("name"), Response::BulkString(group.name)),
+                                (field("consumers"), Response::Int(group.consumers as i64)),
+                                (field("pending"), Response::Int(group.pending as i64)),
+                                (field("last-delivered-id"), stream_id(group.last_delivered)),
+                                (field("entries-read"), optional_int(group.entries_read)),
+                                (field("lag"), optional_int(group.lag)),
+                            ])
+                        })
+                        .collect(),
+                ))
+            }
+            XInfo::Consumers { key, group } => {
+                let consumers = self.store.xinfo_consumers(&key, &group)?;
+
+                Ok(Response::Array(
+                    consumers
+                        .into_iter()
+                        .map(|consumer| {
+                            let inactive = consumer.inactive.map_or(-1, |x| x as i64);
+
+                            Response::Map(vec![
+                                (field("name"), Response::BulkString(consumer.name)),
+                                (field("pending"), Response::Int(consumer.pending as i64)),
+                                (field("idle"), Response::Int(consumer.idle as i64)),
+                                (field("inactive"), Response::Int(inactive)),
+                            ])
+                        })
+                        .collect(),
+                ))
+            }
+        }
+    }
+
+    fn handle_xlen(&self, key: &[u8]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.xlen(key)? as i64))
+    }
+
+    fn handle_xdel(&mut self, key: &[u8], ids: &[StreamId]) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.xdel(key, ids)? as i64))
+    }
+
+    fn handle_xtrim(
+        &mut self,
+        key: &[u8],
+        trim: StreamTrim,
+        limit: Option<usize>,
+    ) -> anyhow::Result<Response> {
+        Ok(Response::Int(self.store.xtrim(key, trim, limit)? as i64))
+    }
+
+    fn handle_keys(&self, pattern: &[u8]) -> anyhow::Result<Response> {
+        let keys = self.store.keys(pattern);
+
+        Ok(Response::Array(
+            keys.into_iter().map(Response::BulkString).collect(),
+        ))
+    }
+
+    fn handle_scan(
+        &self,
+        cursor: u64,
+        pattern: Option<&[u8]>,
+        count: usize,
+        key_type: Option<&str>,
+    ) -> anyhow::Result<Response> {
+        let (cursor, keys) = self.store.scan(cursor, count, pattern, key_type);
+
+        Ok(Response::Array(vec![
+            Response::BulkString(cursor.to_string().into()),
+            Response::Array(keys.into_iter().map(Response::BulkString).collect()),
+        ]))
+    }
+
+    fn handle_info(&self, section: Option<&str>) -> anyhow::Result<Response> {
+        let config = CONFIG.get().ok_or(anyhow!("Unable to get config"))?;
+        let section = section.map_or("default".to_owned(), |x| x.to_lowercase());
+        let all = matches!(section.as_str(), "default" | "all" | "everything");
+
+        let mut sections = vec![];
+
+        if all || section == "replication" {
+            let role = if config.master.is_none() {
+                "master"
+            } else {
+                "slave"
+            };
+
+            let master_replid = &config.master_replid;
+            let master_repl_offset = config.master_repl_offset;
+
+            sections.push(format!(
+                "# Replication\nrole:{}\nmaster_replid:{}\nmaster_repl_offset:{}",
+                role, master_replid, master_repl_offset
+            ));
+        }
+
+        let info = self.store.info();
+
+        if all || section == "stats" {
+            sections.push(format!(
+                "# Stats\nexpired_keys:{}\nexpired_subkeys:{}\nexpired_time_cap_reached_count:{}",
+                info.expired_keys, info.expired_subkeys, info.expired_time_cap_reached_count
+            ));
+        }
+
+        if all || section == "keyspace" {
+            let mut keyspace = "# Keyspace".to_owned();
+
+            if info.keys > 0 {
+                keyspace += &format!("\ndb0:keys={},expires={}", info.keys, info.expires);
+            }
+
+            sections.push(keyspace);
+        }
+
+        Ok(Response::Verbatim {
+            format: "txt".to_owned(),
+            text: sections.join("\n\n").into(),
+        })
+    }
+
+    fn handle_subscribe(
+        &mut self,
+        kind: SubscriptionKind,
+        channels: Vec<Bytes>,
+    ) -> anyhow::Result<Response> {
+        let action = match kind {
+            SubscriptionKind::Channel => "subscribe",
+            SubscriptionKind::Pattern => "psubscribe",
+            SubscriptionKind::Shard => "ssubscribe",
+        };
+
+        let replies = channels
+            .into_iter()
+            .map(|channel| {
+                let count = self.subscriber.subscribe(kind, channel.clone());
+                subscription_reply(action, Response::BulkString(channel), count)
+            })
+            .collect();
+
+        Ok(Response::Seq(replies))
+    }
+
+    fn handle_unsubscribe(
+        &mut self,
+        kind: SubscriptionKind,
+        channels: Vec<Bytes>,
+    ) -> anyhow::Result<Response> {
+        let action = match kind {
+            SubscriptionKind::Channel => "unsubscribe",
+            SubscriptionKind::Pattern => "punsubscribe",
+            SubscriptionKind::Shard => "sunsubscribe",
+        };
+
+        let channels = if channels.is_empty() {
+            self.subscriber.subscriptions(kind)
+        } else {
+            channels
+        };
+
+        // Even with nothing to unsubscribe from, the client gets a reply
+        if channels.is_empty() {
+            let count = self.subscriber.count(kind);
+            return Ok(subscription_reply(action, Response::Null, count));
+        }
+
+        let replies = channels
+            .into_iter()
+            .map(|channel| {
+                let count = self.subscriber.unsubscribe(kind, &channel);
+                subscription_reply(action, Response::BulkString(channel), count)
+            })
+            .collect();
+
+        Ok(Response::Seq(replies))
+    }
+
+    fn handle_publish(
+        &self,
+        channel: Bytes,
+        message: Bytes,
+        sharded: bool,
+    ) -> anyhow::Result<Response> {
+        let receivers = if sharded {
+            self.broker.spublish(channel, message)
+        } else {
+            self.broker.publish(channel, message)
+        };
+
+        Ok(Response::Int(receivers as i64))
+    }
+
+    fn handle_pubsub(&self, pubsub: PubSub) -> anyhow::Result<Response> {
+        let channels = |channels: Vec<Bytes>| {
+            Response::Array(channels.into_iter().map(Response::BulkString).collect())
+        };
+        let numsub = |channels: Vec<Bytes>, counts: Vec<usize>| {
+            Response::Map(
+                channels
+                    .into_iter()
+                    .zip(counts)
+                    .map(|(channel, count)| {
+                        (Response::BulkString(channel), Response::Int(count as i64))
+                    })
+                    .collect(),
+            )
+        };
+
+        let response = match pubsub {
+            PubSub::Channels(pattern) => channels(self.broker.channels(pattern.as_deref())),
+            PubSub::NumSub(names) => {
+                let counts = self.broker.numsub(&names);
+                numsub(names, counts)
+            }
+            PubSub::NumPat => Response::Int(self.broker.numpat() as i64),
+            PubSub::ShardChannels(pattern) => {
+                channels(self.broker.shard_channels(pattern.as_deref()))
+            }
+            PubSub::ShardNumSub(names) => {
+                let counts = self.broker.shard_numsub(&names);
+                numsub(names, counts)
+            }
+        };
+
+        Ok(response)
+    }
+
+    fn handle_replconf(&self, _conf: super::ReplConf) -> anyhow::Result<Response> {
+        Ok(Response::OK)
+    }
+
+    fn handle_psync(&self, _replica_id: String, _offset: i32) -> anyhow::Result<Response> {
+        let config = CONFIG.get().ok_or(anyhow!("Unable to get config"))?;
+        let repl_id = &config.master_replid;
+        let offset = config.master_repl_offset;
+
+        let rdb_file = Response::File(EMPTY_RDB.into());
+
+        let response = vec![
+            Response::SimpleString(format!("FULLRESYNC {} {}", repl_id, offset)),
+            rdb_file,
+        ];
+
+        Ok(Response::Seq(response))
+    }
+
+    fn handle_hello(
+        &mut self,
+        protover: Option<u32>,
+        _auth: Option<(Bytes, Bytes)>,
+        setname: Option<Bytes>,
+    ) -> anyhow::Result<Response> {
+        // There are no users or passwords yet, so any credentials are accepted
+        if let Some(protover) = protover {
+            self.protocol = match protover {
+                2 => Protocol::Resp2,
+                3 => Protocol::Resp3,
+                _ => return Err(CommandError::NoProto.into()),
+            };
+        }
+
+        if setname.is_some() {
+            self.client_name = setname;
+        }
+
+        let config = CONFIG.get().ok_or(anyhow!("Unable to get config"))?;
+        let role = if config.master.is_none() {
+            "master"
+        } else {
+            "replica"
+        };
+
+        let proto = match self.protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+
+        let field = |name: &str| Response::BulkString(Bytes::copy_from_slice(name.as_bytes()));
+
+        let response = vec![
+            (field("server"), field("redis")),
+            (field("version"), field(REDIS_VERSION)),
+            (field("proto"), Response::Int(proto)),
+            (field("id"), Response::Int(self.client_id as i64)),
+            (field("mode"), field("standalone")),
+            (field("role"), field(role)),
+            (field("modules"), Response::Array(vec![])),
+        ];
+
+        Ok(Response::Map(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::{message_queue, MessageReceiver};
+
+    fn handler() -> CommandHandler {
+        subscriber().0
+    }
+
+    /// A handler along with the queue of the messages published to it
+    fn subscriber() -> (CommandHandler, MessageReceiver) {
+        let (sender, messages) = message_queue();
+        let handler = CommandHandler::new(Store::default(), Broker::default(), sender);
+
+        (handler, messages)
+    }
+
+    /// Runs the command made of `args`, which mustn't block
+    fn run(handler: &mut CommandHandler, args: &[&str]) -> Vec<u8> {
+        match handler.handle_frame(Resp::from(args.to_vec())) {
+            Reply::Ready(reply) => reply,
+            Reply::Blocked(_) => panic!("The command shouldn't block"),
+        }
+    }
+
+    #[test]
+    fn errors_leave_the_client_connected() {
+        let mut handler = handler();
+
+        assert_eq!(
+            run(&mut handler, &["GET", "a", "b"]),
+            b"-ERR wrong number of arguments for 'get' command\r\n"
+        );
+        assert_eq!(
+            run(&mut handler, &["foo"]),
+            b"-ERR unknown command 'foo', with args beginning with: \r\n"
+        );
+
+        run(&mut handler, &["SET", "a", "1"]);
+        assert_eq!(
+            run(&mut handler, &["LPUSH", "a", "x"]),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+
+        // The client carries on after the errors
+        assert_eq!(run(&mut handler, &["GET", "a"]), b"$1\r\n1\r\n");
+    }
+
+    #[test]
+    fn errors_stay_on_one_line() {
+        let mut handler = handler();
+
+        assert_eq!(
+            run(&mut handler, &["foo\r\n+INJECTED", "a\nb"]),
+            b"-ERR unknown command 'foo  +INJECTED', with args beginning with: 'a b' \r\n"
+        );
+    }
+
+    #[test]
+    fn subscribe_mode_only_allows_subscription_commands() {
+        let mut handler = handler();
+
+        run(&mut handler, &["SUBSCRIBE", "news"]);
+        assert_eq!(
+            run(&mut handler, &["GET", "a"]),
+            b"-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n"
+        );
+        assert_eq!(
+            run(&mut handler, &["PING"]),
+            b"*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+        );
+
+        // Unsubscribing from everything leaves subscribe mode
+        run(&mut handler, &["UNSUBSCRIBE"]);
+        assert_eq!(run(&mut handler, &["GET", "a"]), b"$-1\r\n");
+        assert_eq!(run(&mut handler, &["PING"]), b"+PONG\r\n");
+    }
+
+    #[tokio::test]
+    async fn resp3_messages_are_pushed() {
+        let (mut handler, mut messages) = subscriber();
+
+        run(&mut handler, &["HELLO", "3"]);
+        assert_eq!(
+            run(&mut handler, &["SUBSCRIBE", "news"]),
+            b">3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+
+        // RESP3 clients may run any command while subscribed
+        assert_eq!(run(&mut handler, &["GET", "a"]), b"_\r\n");
+
+        assert_eq!(run(&mut handler, &["PUBLISH", "news", "hello"]), b":1\r\n");
+
+        let message = messages.recv().await.unwrap();
+        assert_eq!(
+            handler.serialize_message(message),
+            b">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n"
+        );
+    }
+
+    #[test]
+    fn set_errors() {
+        let mut handler = handler();
+
+        // The deadline overflows, which is only known once it's computed
+        assert_eq!(
+            run(
+                &mut handler,
+                &["SET", "k", "v", "EX", "9223372036854775807"]
+            ),
+            b"-ERR invalid expire time in 'set' command\r\n"
+        );
+        assert_eq!(run(&mut handler, &["EXISTS", "k"]), b":0\r\n");
+
+        run(&mut handler, &["RPUSH", "list", "a"]);
+        assert_eq!(
+            run(&mut handler, &["SET", "list", "v", "GET"]),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(run(&mut handler, &["LLEN", "list"]), b":1\r\n");
+    }
+
+    #[test]
+    fn ttl_replies() {
+        let mut handler = handler();
+
+        for ttl in ["TTL", "PTTL", "EXPIRETIME", "PEXPIRETIME"] {
+            assert_eq!(run(&mut handler, &[ttl, "k"]), b":-2\r\n");
+        }
+
+        run(&mut handler, &["SET", "k", "v"]);
+
+        for ttl in ["TTL", "PTTL", "EXPIRETIME", "PEXPIRETIME"] {
+            assert_eq!(run(&mut handler, &[ttl, "k"]), b":-1\r\n");
+        }
+
+        run(&mut handler, &["EXPIREAT", "k", "4102444800"]);
+        assert_eq!(run(&mut handler, &["EXPIRETIME", "k"]), b":4102444800\r\n");
+        assert_eq!(
+            run(&mut handler, &["PEXPIRETIME", "k"]),
+            b":4102444800000\r\n"
+        );
+
+        run(&mut handler, &["EXPIRE", "k", "100"]);
+        assert_eq!(run(&mut handler, &["TTL", "k"]), b":100\r\n");
+
+        // A time in the past deletes the key
+        assert_eq!(run(&mut handler, &["EXPIRE", "k", "-1"]), b":1\r\n");
+        assert_eq!(run(&mut handler, &["TTL", "k"]), b":-2\r\n");
+    }
+
+    #[test]
+    fn get_range_offsets() {
+        let mut handler = handler();
+
+        assert_eq!(
+            run(&mut handler, &["GETRANGE", "k", "0", "-1"]),
+            b"$0\r\n\r\n"
+        );
+
+        run(&mut handler, &["SET", "k", "hello"]);
+
+        let ranges = [
+            ("0", "-1", "hello"),
+            ("-3", "-1", "llo"),
+            ("-100", "1", "he"),
+            ("1", "100", "ello"),
+            ("3", "1", ""),
+            ("5", "10", ""),
+            ("-1", "-2", ""),
+        ];
+
+        for (start, end, expected) in ranges {
+            let reply = run(&mut handler, &["GETRANGE", "k", start, end]);
+            let expected = format!("${}\r\n{}\r\n", expected.len(), expected);
+            assert_eq!(reply, expected.as_bytes(), "{start} {end}");
+        }
+
+        assert_eq!(
+            run(&mut handler, &["SETRANGE", "k", "-1", "x"]),
+            b"-ERR offset is out of range\r\n"
+        );
+        assert_eq!(
+            run(&mut handler, &["SETRANGE", "k", "536870912", "x"]),
+            b"-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+        );
+    }
+
+    #[test]
+    fn huge_random_counts_are_rejected() {
+        let mut handler = handler();
+        run(&mut handler, &["HSET", "h", "f", "v"]);
+        run(&mut handler, &["SADD", "s", "m"]);
+        run(&mut handler, &["ZADD", "z", "1", "m"]);
+
+        let commands = [
+            ("HRANDFIELD", "h"),
+            ("SRANDMEMBER", "s"),
+            ("ZRANDMEMBER", "z"),
+        ];
+
+        for (command, key) in commands {
+            assert_eq!(
+                run(&mut handler, &[command, key, "-9223372036854775807"]),
+                b"-ERR value is out of range\r\n"
+            );
+        }
+
+        assert_eq!(
+            run(&mut handler, &["ZRANDMEMBER", "z", "-9223372036854775808"]),
+            b"-ERR value is out of range\r\n"
+        );
+
+        // The store is still usable
+        assert_eq!(
+            run(&mut handler, &["HRANDFIELD", "h", "-2"]),
+            b"*2\r\n$1\r\nf\r\n$1\r\nf\r\n"
+        );
+    }
+
+    #[test]
+    fn spop_counts_past_i64() {
+        let mut handler = handler();
+
+        for count in ["9223372036854775808", "18446744073709551615"] {
+            run(&mut handler, &["SADD", "s", "a", "b", "c"]);
+            assert_eq!(run(&mut handler, &["SPOP", "s", count]).len(), 25);
+            assert_eq!(run(&mut handler, &["EXISTS", "s"]), b":0\r\n");
+        }
+    }
+
+    #[test]
+    fn xread_count_zero_reads_everything() {
+        let mut handler = handler();
+        run(&mut handler, &["XADD", "s", "1-1", "f", "a"]);
+        run(&mut handler, &["XADD", "s", "1-2", "f", "b"]);
+
+        let all = run(&mut handler, &["XREAD", "STREAMS", "s", "0"]);
+        assert!(all.starts_with(b"*1\r\n*2\r\n$1\r\ns\r\n*2\r\n"));
+
+        for count in ["0", "-1"] {
+            let read = run(
+                &mut handler,
+                &["XREAD", "COUNT", count, "STREAMS", "s", "0"],
+            );
+            assert_eq!(read, all);
+
+            // There's something to read, so the client doesn't block
+            let args = ["XREAD", "COUNT", count, "BLOCK", "0", "STREAMS", "s", "0"];
+            assert_eq!(run(&mut handler, &args), all);
+        }
+    }
+
+    #[test]
+    fn xreadgroup_count_zero_delivers_everything() {
+        let mut handler = handler();
+        run(&mut handler, &["XADD", "s", "1-1", "f", "a"]);
+        run(&mut handler, &["XADD", "s", "1-2", "f", "b"]);
+        run(&mut handler, &["XGROUP", "CREATE", "s", "g", "0"]);
+
+        let read = [
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "c",
+            "COUNT",
+            "0",
+            "STREAMS",
+            "s",
+            ">",
+        ];
+        let delivered = run(&mut handler, &read);
+        assert!(delivered.starts_with(b"*1\r\n*2\r\n$1\r\ns\r\n*2\r\n"));
+
+        // Both entries are pending and the group has moved past them
+        let pending = run(&mut handler, &["XPENDING", "s", "g"]);
+        assert!(pending.starts_with(b"*4\r\n:2\r\n$3\r\n1-1\r\n$3\r\n1-2\r\n"));
+        assert_eq!(run(&mut handler, &read), b"*-1\r\n");
     }
 }
@@ -0,0 +1,92 @@
+/// Errors that are reported back to the client as RESP errors, while the
+/// connection stays open. The message starts with the error code, e.g. `ERR`.
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("ERR unknown command '{0:.128}', with args beginning with: {1}")]
+    UnknownCommand(String, String),
+
+    #[error("ERR unknown subcommand '{1:.128}'. Try {0} HELP.")]
+    UnknownSubcommand(String, String),
+
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+
+    #[error("ERR syntax error")]
+    Syntax,
+
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+
+    #[error("ERR value is not a valid float")]
+    NotFloat,
+
+    #[error("ERR invalid expire time in '{0}' command")]
+    InvalidExpireTime(String),
+
+    #[error("ERR Protocol error: {0}")]
+    Protocol(String),
+
+    #[error("ERR Can't execute '{0}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context")]
+    SubscribeMode(String),
+
+    #[error("CROSSSLOT Keys in request don't hash to the same slot")]
+    CrossSlot,
+
+    #[error("NOPROTO unsupported protocol version")]
+    NoProto,
+
+    #[error("ERR {0}")]
+    Other(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resp::Resp;
+    use crate::Command;
+
+    /// The error parsing the command made of `args`
+    fn parse_error(args: &[&str]) -> String {
+        match Command::try_from(Resp::from(args.to_vec())) {
+            Ok(_) => panic!("The command should be rejected"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn command_errors() {
+        assert_eq!(
+            parse_error(&["foo", "bar", "baz"]),
+            "ERR unknown command 'foo', with args beginning with: 'bar' 'baz' "
+        );
+        assert_eq!(
+            parse_error(&["GET"]),
+            "ERR wrong number of arguments for 'get' command"
+        );
+        // Trailing arguments are caught for every command
+        assert_eq!(
+            parse_error(&["GET", "a", "b"]),
+            "ERR wrong number of arguments for 'get' command"
+        );
+        assert_eq!(
+            parse_error(&["REPLCONF", "foo"]),
+            "ERR Unrecognized REPLCONF option: foo"
+        );
+        assert_eq!(
+            parse_error(&["XINFO", "foo"]),
+            "ERR unknown subcommand 'foo'. Try XINFO HELP."
+        );
+
+        // Like Redis, no more than about 128 characters of input are echoed
+        let long = "x".repeat(200);
+        let error = parse_error(&[&long, &long, "y"]);
+        let echoed = format!("'{}'", &long[..128]);
+        assert_eq!(
+            error,
+            format!("ERR unknown command {echoed}, with args beginning with: {echoed} ")
+        );
+        assert_eq!(
+            parse_error(&["XINFO", &long]),
+            format!("ERR unknown subcommand {echoed}. Try XINFO HELP.")
+        );
+    }
+}
@@ -1,35 +1,109 @@
 use bytes::Bytes;
+use std::{future::Future, pin::Pin};
 
-use crate::resp::{Resp, ToResp};
+use crate::resp::{format_double, Protocol, Resp, ToResp};
 
+// Not every RESP3 type is produced by a command yet
+#[allow(dead_code)]
 pub enum Response {
     OK,
     Pong,
     Null,
+    /// A null in place of an array, which is `*-1` in RESP2
+    NullArray,
     SimpleString(String),
-    BulkString(String),
+    Error(String),
+    BulkString(Bytes),
     File(Bytes),
     Seq(Vec<Response>),
+    Int(i64),
+    Array(Vec<Response>),
+    Map(Vec<(Response, Response)>),
+    Set(Vec<Response>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    Verbatim {
+        format: String,
+        text: Bytes,
+    },
+    /// Out-of-band metadata about the reply that follows it
+    Attribute(Vec<(Response, Response)>),
+    Push(Vec<Response>),
+    /// The reply of a client blocked by a command, once it's unblocked
+    Blocked(Pin<Box<dyn Future<Output = anyhow::Result<Response>> + Send>>),
 }
 
 impl Response {
-    pub fn serialize(&self) -> Vec<u8> {
+    /// Serializes the response for a client speaking `protocol`. The RESP3
+    /// types are downgraded to their RESP2 counterparts the way Redis does it.
+    pub fn serialize(&self, protocol: Protocol) -> Vec<u8> {
         match self {
-            Response::OK => "OK".as_simple_string().serialize(),
-            Response::Pong => "PONG".as_simple_string().serialize(),
-            Response::Null => "$-1\r\n".as_bytes().to_vec(),
-            Response::SimpleString(s) => s.as_simple_string().serialize(),
-            Response::BulkString(s) => s.as_bulk_string().serialize(),
             Response::File(s) => Resp::File(s.to_owned()).serialize(),
             Response::Seq(seq) => {
                 let mut result = vec![];
 
                 for resp in seq {
-                    result.extend(resp.serialize())
+                    result.extend(resp.serialize(protocol))
                 }
 
                 result
             }
+            // Attributes don't exist in RESP2, so they are simply dropped
+            Response::Attribute(_) if protocol == Protocol::Resp2 => vec![],
+            _ => self.to_resp(protocol).serialize(),
+        }
+    }
+
+    fn to_resp(&self, protocol: Protocol) -> Resp {
+        let resp3 = protocol == Protocol::Resp3;
+
+        let to_resps = |vec: &[Response]| vec.iter().map(|x| x.to_resp(protocol)).collect();
+        let to_pairs = |pairs: &[(Response, Response)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_resp(protocol), v.to_resp(protocol)))
+                .collect()
+        };
+
+        match self {
+            Response::OK => "OK".as_simple_string(),
+            Response::Pong => "PONG".as_simple_string(),
+            Response::Null if resp3 => Resp::Null,
+            Response::Null => Resp::NullBulkString,
+            Response::NullArray if resp3 => Resp::Null,
+            Response::NullArray => Resp::NullArray,
+            Response::SimpleString(s) => s.as_simple_string(),
+            Response::Error(s) => Resp::Error(s.to_owned()),
+            Response::BulkString(s) => Resp::BulkString(s.to_owned()),
+            Response::File(s) => Resp::File(s.to_owned()),
+            Response::Seq(seq) => Resp::Array(to_resps(seq)),
+            Response::Int(n) => Resp::Int(*n),
+            Response::Array(vec) => Resp::Array(to_resps(vec)),
+            Response::Map(pairs) if resp3 => Resp::Map(to_pairs(pairs)),
+            Response::Map(pairs) => Resp::Array(
+                pairs
+                    .iter()
+                    .flat_map(|(k, v)| [k.to_resp(protocol), v.to_resp(protocol)])
+                    .collect(),
+            ),
+            Response::Set(vec) if resp3 => Resp::Set(to_resps(vec)),
+            Response::Set(vec) => Resp::Array(to_resps(vec)),
+            Response::Double(d) if resp3 => Resp::Double(*d),
+            Response::Double(d) => format_double(*d).as_bulk_string(),
+            Response::Boolean(b) if resp3 => Resp::Boolean(*b),
+            Response::Boolean(b) => Resp::Int(*b as i64),
+            Response::BigNumber(n) if resp3 => Resp::BigNumber(n.to_owned()),
+            Response::BigNumber(n) => n.as_bulk_string(),
+            Response::Verbatim { format, text } if resp3 => Resp::VerbatimString {
+                format: format.to_owned(),
+                text: text.to_owned(),
+            },
+            Response::Verbatim { text, .. } => Resp::BulkString(text.to_owned()),
+            Response::Attribute(pairs) => Resp::Attribute(to_pairs(pairs)),
+            Response::Push(vec) if resp3 => Resp::Push(to_resps(vec)),
+            Response::Push(vec) => Resp::Array(to_resps(vec)),
+            Response::Blocked(_) => unreachable!("Blocked replies are awaited before serializing"),
         }
     }
 }
@@ -1,19 +1,21 @@
 use anyhow::anyhow;
 
-use crate::{commands::ReplConf, Command, CONFIG};
-use std::{
-    io::{Read, Write},
+use crate::{
+    commands::ReplConf,
+    resp::{Decoder, Resp},
+    Command, CONFIG,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
 };
 
-pub fn do_handshake_with_master(stream: &mut TcpStream) -> anyhow::Result<()> {
-    let mut buf = [0u8; 512];
+pub async fn do_handshake_with_master(stream: &mut TcpStream) -> anyhow::Result<()> {
+    let mut decoder = Decoder::new();
 
     let ping: Command = Command::Ping;
 
-    stream.write_all(&ping.serialize())?;
-
-    let _ = stream.read(&mut buf)?;
+    send_command(stream, &mut decoder, ping).await?;
 
     let port = CONFIG
         .get()
@@ -22,24 +24,45 @@ pub fn do_handshake_with_master(stream: &mut TcpStream) -> anyhow::Result<()> {
 
     let replconf: Command = Command::ReplConf(ReplConf::ListeningPort(port));
 
-    stream.write_all(&replconf.serialize())?;
-
-    let _ = stream.read(&mut buf)?;
+    send_command(stream, &mut decoder, replconf).await?;
 
     let replconf: Command = Command::ReplConf(ReplConf::Capa(vec!["psync2".to_owned()]));
 
-    stream.write_all(&replconf.serialize())?;
-
-    let _ = stream.read(&mut buf)?;
+    send_command(stream, &mut decoder, replconf).await?;
 
     let psync: Command = Command::Psync {
         replica_id: "?".to_owned(),
         offset: -1,
     };
 
-    stream.write_all(&psync.serialize())?;
-
-    let _ = stream.read(&mut buf)?;
+    send_command(stream, &mut decoder, psync).await?;
 
     Ok(())
 }
+
+/// Sends `command` to the master and waits for its reply
+async fn send_command(
+    stream: &mut TcpStream,
+    decoder: &mut Decoder,
+    command: Command,
+) -> anyhow::Result<Resp> {
+    let mut buf = [0u8; 512];
+
+    stream.write_all(&command.serialize()).await?;
+
+    loop {
+        match decoder.next_frame()? {
+            Some(Resp::Error(err)) => return Err(anyhow!("Master replied with: {}", err)),
+            Some(reply) => return Ok(reply),
+            None => {
+                let bytes_read = stream.read(&mut buf).await?;
+
+                if bytes_read == 0 {
+                    return Err(anyhow!("Master closed the connection"));
+                }
+
+                decoder.extend(&buf[..bytes_read]);
+            }
+        }
+    }
+}
@@ -1,25 +1,29 @@
 mod commands;
 mod config;
 mod handshake;
+mod pubsub;
 mod resp;
+mod slot;
 mod store;
 
 pub use commands::Command;
-use commands::CommandHandler;
+use commands::{CommandError, CommandHandler, Reply};
 use config::Config;
 use handshake::do_handshake_with_master;
+use pubsub::{message_queue, Broker, MessageReceiver};
+use resp::{Decoder, Resp};
 
-use std::{
-    io::{Read, Write},
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
-    thread,
 };
 
 use std::sync::OnceLock;
 
 pub static CONFIG: OnceLock<Config> = OnceLock::new();
 
-fn main() -> anyhow::Result<()> {
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
     let config = Config::new()?;
 
     CONFIG.get_or_init(|| config);
@@ -28,46 +32,147 @@ fn main() -> anyhow::Result<()> {
 
     let address = format!("127.0.0.1:{}", config.port);
 
-    let listener = TcpListener::bind(address).unwrap();
+    let listener = TcpListener::bind(address).await?;
 
     let store = store::Store::default();
+    let broker = Broker::default();
 
-    // Send handshake if we are a replica
-    if config.master.is_some() {
-        let mut stream = TcpStream::connect(config.master_address().unwrap())?;
+    tokio::spawn(store.clone().run_active_expiry());
 
-        do_handshake_with_master(&mut stream)?;
+    // Send handshake if we are a replica
+    if let Some(master_address) = config.master_address() {
+        tokio::spawn(async move {
+            let result = async {
+                let mut stream = TcpStream::connect(master_address).await?;
+                do_handshake_with_master(&mut stream).await
+            };
+
+            if let Err(e) = result.await {
+                println!("error: handshake with master failed: {}", e);
+            }
+        });
     }
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
                 let store = store.clone();
-                thread::spawn(move || handle_client(stream, store));
+                let broker = broker.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_client(stream, store, broker).await {
+                        println!("error: {}", e);
+                    }
+                });
             }
             Err(e) => {
                 println!("error: {}", e);
             }
         }
     }
-    Ok(())
 }
 
-fn handle_client(mut stream: TcpStream, store: store::Store) -> anyhow::Result<()> {
-    let mut buf = [0u8; 512];
-    let mut command_handler = CommandHandler::new(store);
+async fn handle_client(
+    mut stream: TcpStream,
+    store: store::Store,
+    broker: Broker,
+) -> anyhow::Result<()> {
+    let mut buf = [0u8; 4096];
+    let mut decoder = Decoder::new();
+    let (sender, mut messages) = message_queue();
+    let mut command_handler = CommandHandler::new(store, broker, sender);
 
     loop {
-        let bytes_read = stream.read(&mut buf)?;
+        // Messages published to the client's subscriptions are written as
+        // they come, in between its commands
+        let bytes_read = tokio::select! {
+            bytes_read = stream.read(&mut buf) => bytes_read?,
+            message = messages.recv() => {
+                let Some(message) = message else {
+                    return Err(overflow_error());
+                };
+
+                let message = command_handler.serialize_message(message);
+                write(&mut stream, &message, &messages).await?;
+                continue;
+            }
+        };
 
         if bytes_read == 0 {
             return Ok(());
         }
 
-        let command = std::str::from_utf8(&buf)?.parse()?;
+        decoder.extend(&buf[..bytes_read]);
+
+        // Answer every complete command we have so far with a single write
+        let mut responses = vec![];
+
+        loop {
+            match decoder.next_command() {
+                Ok(Some(frame)) => match command_handler.handle_frame(frame) {
+                    Reply::Ready(reply) => responses.extend(reply),
+                    Reply::Blocked(mut reply) => {
+                        // Send the replies so far, as the client may block for a while
+                        write(&mut stream, &responses, &messages).await?;
+                        responses.clear();
+
+                        // Keep reading while blocked, so that a client hanging up
+                        // stops waiting and no longer takes elements
+                        loop {
+                            tokio::select! {
+                                reply = &mut reply => {
+                                    responses.extend(reply);
+                                    break;
+                                }
+                                message = messages.recv() => {
+                                    let Some(message) = message else {
+                                        return Err(overflow_error());
+                                    };
+
+                                    let message = command_handler.serialize_message(message);
+                                    write(&mut stream, &message, &messages).await?;
+                                }
+                                bytes_read = stream.read(&mut buf) => {
+                                    let bytes_read = bytes_read?;
+
+                                    if bytes_read == 0 {
+                                        return Ok(());
+                                    }
+
+                                    decoder.extend(&buf[..bytes_read]);
+                                }
+                            }
+                        }
+                    }
+                },
+                Ok(None) => break,
+                Err(err) => {
+                    // There's no telling where the next command starts after
+                    // a protocol error, so the connection is closed
+                    let err = CommandError::Protocol(err.to_string());
+                    responses.extend(Resp::Error(err.to_string()).serialize());
+                    write(&mut stream, &responses, &messages).await?;
+                    return Ok(());
+                }
+            }
+        }
 
-        let response = command_handler.handle_command(command)?;
+        write(&mut stream, &responses, &messages).await?;
+    }
+}
 
-        stream.write_all(&response)?;
+/// Writes `bytes` to the client, unless its message queue overflows first,
+/// as happens when it doesn't read what it's sent
+async fn write(
+    stream: &mut TcpStream,
+    bytes: &[u8],
+    messages: &MessageReceiver,
+) -> anyhow::Result<()> {
+    tokio::select! {
+        result = stream.write_all(bytes) => Ok(result?),
+        _ = messages.overflowed() => Err(overflow_error()),
     }
 }
+
+fn overflow_error() -> anyhow::Error {
+    anyhow::anyhow!("client closed for overcoming of output buffer limits")
+}
@@ -0,0 +1,541 @@
+use bytes::Bytes;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::{mpsc, Notify};
+
+use crate::slot::key_hash_slot;
+use crate::store::glob_match;
+
+/// A message published to a channel, as delivered to one subscriber
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Published to a channel the client subscribed to
+    Channel { channel: Bytes, payload: Bytes },
+    /// Published to a channel matching a pattern the client subscribed to
+    Pattern {
+        pattern: Bytes,
+        channel: Bytes,
+        payload: Bytes,
+    },
+    /// Published to a shard channel the client subscribed to
+    Shard { channel: Bytes, payload: Bytes },
+}
+
+/// What a client subscribes to: a channel by name, every channel matching
+/// a glob-style pattern, or a shard channel, which is scoped by the hash
+/// slot of its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionKind {
+    Channel,
+    Pattern,
+    Shard,
+}
+
+/// How many bytes of messages may wait for a client to read them before
+/// it's disconnected, like the hard limit of Redis'
+/// `client-output-buffer-limit pubsub`
+const MAX_QUEUED_BYTES: usize = 32 * 1024 * 1024;
+
+impl Message {
+    /// The number of bytes the message counts for while queued
+    fn size(&self) -> usize {
+        match self {
+            Message::Channel { channel, payload } | Message::Shard { channel, payload } => {
+                channel.len() + payload.len()
+            }
+            Message::Pattern {
+                pattern,
+                channel,
+                payload,
+            } => pattern.len() + channel.len() + payload.len(),
+        }
+    }
+}
+
+/// The state shared by both ends of a client's message queue
+#[derive(Default)]
+struct Queue {
+    /// The size of the messages sent but not received yet
+    bytes: AtomicUsize,
+    overflowed: AtomicBool,
+    overflow: Notify,
+}
+
+impl Queue {
+    async fn overflowed(&self) {
+        if !self.overflowed.load(Ordering::Relaxed) {
+            self.overflow.notified().await;
+        }
+    }
+}
+
+/// Creates the queue through which a client is sent the messages published
+/// to its subscriptions
+pub fn message_queue() -> (MessageSender, MessageReceiver) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let queue = Arc::new(Queue::default());
+
+    (
+        MessageSender {
+            sender,
+            queue: queue.clone(),
+        },
+        MessageReceiver { receiver, queue },
+    )
+}
+
+/// The sending end of a client's message queue. Once more than
+/// `MAX_QUEUED_BYTES` are waiting, nothing more is queued and the client
+/// has to be disconnected.
+#[derive(Clone)]
+pub struct MessageSender {
+    sender: mpsc::UnboundedSender<Message>,
+    queue: Arc<Queue>,
+}
+
+impl MessageSender {
+    /// Queues `message`, returning whether it was
+    fn send(&self, message: Message) -> bool {
+        let size = message.size();
+
+        if self.queue.overflowed.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        if self.queue.bytes.fetch_add(size, Ordering::Relaxed) + size > MAX_QUEUED_BYTES {
+            self.queue.bytes.fetch_sub(size, Ordering::Relaxed);
+            self.queue.overflowed.store(true, Ordering::Relaxed);
+            self.queue.overflow.notify_one();
+            return false;
+        }
+
+        self.sender.send(message).is_ok()
+    }
+}
+
+/// The receiving end of a client's message queue
+pub struct MessageReceiver {
+    receiver: mpsc::UnboundedReceiver<Message>,
+    queue: Arc<Queue>,
+}
+
+impl MessageReceiver {
+    /// The next message, or `None` once the queue has overflowed
+    pub async fn recv(&mut self) -> Option<Message> {
+        tokio::select! {
+            biased;
+            _ = self.queue.overflowed() => None,
+            message = self.receiver.recv() => {
+                let message = message?;
+                self.queue.bytes.fetch_sub(message.size(), Ordering::Relaxed);
+                Some(message)
+            }
+        }
+    }
+
+    /// Completes once the queue has overflowed, which may happen while the
+    /// client is too slow to take the messages already received
+    pub async fn overflowed(&self) {
+        self.queue.overflowed().await
+    }
+}
+
+/// The subscribers of each channel or pattern, by client ID
+type Subscribers = HashMap<Bytes, HashMap<u64, MessageSender>>;
+
+#[derive(Default)]
+struct Subscriptions {
+    channels: Subscribers,
+    patterns: Subscribers,
+    /// The subscribers of the shard channels, by hash slot
+    shard_channels: HashMap<u16, Subscribers>,
+}
+
+impl Subscriptions {
+    fn add(&mut self, kind: SubscriptionKind, name: Bytes, id: u64, sender: MessageSender) {
+        let subscribers = match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => self.shard_channels.entry(key_hash_slot(&name)).or_default(),
+        };
+
+        subscribers.entry(name).or_default().insert(id, sender);
+    }
+
+    fn remove(&mut self, kind: SubscriptionKind, name: &Bytes, id: u64) {
+        let slot = key_hash_slot(name);
+
+        let subscribers = match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => match self.shard_channels.get_mut(&slot) {
+                Some(subscribers) => subscribers,
+                None => return,
+            },
+        };
+
+        if let Some(clients) = subscribers.get_mut(name) {
+            clients.remove(&id);
+
+            if clients.is_empty() {
+                subscribers.remove(name);
+            }
+        }
+
+        if subscribers.is_empty() && kind == SubscriptionKind::Shard {
+            self.shard_channels.remove(&slot);
+        }
+    }
+
+    fn shard_subscribers(&self, channel: &[u8]) -> Option<&HashMap<u64, MessageSender>> {
+        self.shard_channels
+            .get(&key_hash_slot(channel))?
+            .get(channel)
+    }
+}
+
+/// The channels among `channels` matching `pattern`, if given
+fn matching<'a>(channels: impl Iterator<Item = &'a Bytes>, pattern: Option<&[u8]>) -> Vec<Bytes> {
+    channels
+        .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel, false)))
+        .cloned()
+        .collect()
+}
+
+/// Routes published messages to the clients subscribed to them. Every
+/// client has its own channel, which its connection drains into its socket.
+#[derive(Clone, Default)]
+pub struct Broker(Arc<Mutex<Subscriptions>>);
+
+impl Broker {
+    /// Creates the subscriber for the client `id`, whose messages are sent
+    /// to `sender`
+    pub fn subscriber(&self, id: u64, sender: MessageSender) -> Subscriber {
+        Subscriber {
+            broker: self.clone(),
+            id,
+            sender,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            shard_channels: BTreeSet::new(),
+        }
+    }
+
+    /// Sends `payload` to the subscribers of `channel` and of the patterns
+    /// matching it. Returns how many messages were sent.
+    pub fn publish(&self, channel: Bytes, payload: Bytes) -> usize {
+        let subscriptions = self.0.lock().unwrap();
+        let mut sent = 0;
+
+        if let Some(subscribers) = subscriptions.channels.get(&channel) {
+            for sender in subscribers.values() {
+                let message = Message::Channel {
+                    channel: channel.clone(),
+                    payload: payload.clone(),
+                };
+
+                sent += sender.send(message) as usize;
+            }
+        }
+
+        for (pattern, subscribers) in &subscriptions.patterns {
+            if !glob_match(pattern, &channel, false) {
+                continue;
+            }
+
+            for sender in subscribers.values() {
+                let message = Message::Pattern {
+                    pattern: pattern.clone(),
+                    channel: channel.clone(),
+                    payload: payload.clone(),
+                };
+
+                sent += sender.send(message) as usize;
+            }
+        }
+
+        sent
+    }
+
+    /// Sends `payload` to the subscribers of the shard channel `channel`.
+    /// Returns how many messages were sent.
+    pub fn spublish(&self, channel: Bytes, payload: Bytes) -> usize {
+        let subscriptions = self.0.lock().unwrap();
+
+        let Some(subscribers) = subscriptions.shard_subscribers(&channel) else {
+            return 0;
+        };
+
+        let mut sent = 0;
+
+        for sender in subscribers.values() {
+            let message = Message::Shard {
+                channel: channel.clone(),
+                payload: payload.clone(),
+            };
+
+            sent += sender.send(message) as usize;
+        }
+
+        sent
+    }
+
+    /// The channels with subscribers, optionally only those matching
+    /// `pattern`, for `PUBSUB CHANNELS`
+    pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        let subscriptions = self.0.lock().unwrap();
+
+        matching(subscriptions.channels.keys(), pattern)
+    }
+
+    /// The shard channels with subscribers, optionally only those matching
+    /// `pattern`, for `PUBSUB SHARDCHANNELS`
+    pub fn shard_channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        let subscriptions = self.0.lock().unwrap();
+        let channels = subscriptions.shard_channels.values().flat_map(|x| x.keys());
+
+        matching(channels, pattern)
+    }
+
+    /// The number of subscribers of each of `channels`, for `PUBSUB NUMSUB`
+    pub fn numsub(&self, channels: &[Bytes]) -> Vec<usize> {
+        let subscriptions = self.0.lock().unwrap();
+
+        channels
+            .iter()
+            .map(|channel| subscriptions.channels.get(channel).map_or(0, |x| x.len()))
+            .collect()
+    }
+
+    /// The number of subscribers of each of the shard channels `channels`,
+    /// for `PUBSUB SHARDNUMSUB`
+    pub fn shard_numsub(&self, channels: &[Bytes]) -> Vec<usize> {
+        let subscriptions = self.0.lock().unwrap();
+
+        channels
+            .iter()
+            .map(|channel| {
+                subscriptions
+                    .shard_subscribers(channel)
+                    .map_or(0, |x| x.len())
+            })
+            .collect()
+    }
+
+    /// The number of patterns with subscribers, for `PUBSUB NUMPAT`
+    pub fn numpat(&self) -> usize {
+        self.0.lock().unwrap().patterns.len()
+    }
+}
+
+/// The subscriptions of a single client. They are all dropped along with
+/// it, when the client disconnects.
+pub struct Subscriber {
+    broker: Broker,
+    id: u64,
+    sender: MessageSender,
+    channels: BTreeSet<Bytes>,
+    patterns: BTreeSet<Bytes>,
+    shard_channels: BTreeSet<Bytes>,
+}
+
+impl Subscriber {
+    fn names(&self, kind: SubscriptionKind) -> &BTreeSet<Bytes> {
+        match kind {
+            SubscriptionKind::Channel => &self.channels,
+            SubscriptionKind::Pattern => &self.patterns,
+            SubscriptionKind::Shard => &self.shard_channels,
+        }
+    }
+
+    fn names_mut(&mut self, kind: SubscriptionKind) -> &mut BTreeSet<Bytes> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// Whether the client is subscribed to anything at all
+    pub fn is_subscribed(&self) -> bool {
+        self.count(SubscriptionKind::Channel) + self.count(SubscriptionKind::Shard) > 0
+    }
+
+    /// The number of subscriptions reported when subscribing to `kind`.
+    /// Like in Redis, channels and patterns are counted together, while
+    /// shard channels are counted on their own.
+    pub fn count(&self, kind: SubscriptionKind) -> usize {
+        match kind {
+            SubscriptionKind::Channel | SubscriptionKind::Pattern => {
+                self.channels.len() + self.patterns.len()
+            }
+            SubscriptionKind::Shard => self.shard_channels.len(),
+        }
+    }
+
+    /// The channels, patterns or shard channels the client is subscribed to
+    pub fn subscriptions(&self, kind: SubscriptionKind) -> Vec<Bytes> {
+        self.names(kind).iter().cloned().collect()
+    }
+
+    /// Subscribes to `name`, unless already subscribed. Returns the number of
+    /// subscriptions afterwards.
+    pub fn subscribe(&mut self, kind: SubscriptionKind, name: Bytes) -> usize {
+        if self.names_mut(kind).insert(name.clone()) {
+            let mut subscriptions = self.broker.0.lock().unwrap();
+
+            subscriptions.add(kind, name, self.id, self.sender.clone());
+        }
+
+        self.count(kind)
+    }
+
+    /// Unsubscribes from `name`, if subscribed. Returns the number of
+    /// subscriptions afterwards.
+    pub fn unsubscribe(&mut self, kind: SubscriptionKind, name: &Bytes) -> usize {
+        if self.names_mut(kind).remove(name) {
+            let mut subscriptions = self.broker.0.lock().unwrap();
+
+            subscriptions.remove(kind, name, self.id);
+        }
+
+        self.count(kind)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        let kinds = [
+            SubscriptionKind::Channel,
+            SubscriptionKind::Pattern,
+            SubscriptionKind::Shard,
+        ];
+
+        for kind in kinds {
+            for name in self.subscriptions(kind) {
+                self.unsubscribe(kind, &name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_reach_channel_and_pattern_subscribers() {
+        let broker = Broker::default();
+
+        let (sender, mut receiver) = message_queue();
+        let mut subscriber = broker.subscriber(1, sender);
+        let (channel, pattern) = (SubscriptionKind::Channel, SubscriptionKind::Pattern);
+
+        assert_eq!(subscriber.subscribe(channel, "news".into()), 1);
+        assert_eq!(subscriber.subscribe(channel, "news".into()), 1);
+        assert_eq!(subscriber.subscribe(pattern, "n*".into()), 2);
+
+        assert_eq!(broker.publish("news".into(), "hello".into()), 2);
+        assert_eq!(broker.publish("sports".into(), "score".into()), 0);
+
+        assert_eq!(
+            receiver.receiver.try_recv().unwrap(),
+            Message::Channel {
+                channel: "news".into(),
+                payload: "hello".into()
+            }
+        );
+        assert_eq!(
+            receiver.receiver.try_recv().unwrap(),
+            Message::Pattern {
+                pattern: "n*".into(),
+                channel: "news".into(),
+                payload: "hello".into()
+            }
+        );
+        assert!(receiver.receiver.try_recv().is_err());
+
+        assert_eq!(broker.channels(None), vec![Bytes::from("news")]);
+        assert_eq!(broker.numsub(&["news".into(), "sports".into()]), vec![1, 0]);
+        assert_eq!(broker.numpat(), 1);
+
+        // Disconnecting drops all the client's subscriptions
+        drop(subscriber);
+        assert!(broker.channels(None).is_empty());
+        assert_eq!(broker.numpat(), 0);
+    }
+
+    #[test]
+    fn shard_channels_are_kept_apart() {
+        let broker = Broker::default();
+
+        let (sender, mut receiver) = message_queue();
+        let mut subscriber = broker.subscriber(1, sender);
+
+        assert_eq!(
+            subscriber.subscribe(SubscriptionKind::Channel, "news".into()),
+            1
+        );
+        assert_eq!(
+            subscriber.subscribe(SubscriptionKind::Shard, "news".into()),
+            1
+        );
+        assert_eq!(
+            subscriber.subscribe(SubscriptionKind::Shard, "{news}.eu".into()),
+            2
+        );
+
+        assert_eq!(broker.spublish("{news}.eu".into(), "hello".into()), 1);
+        assert_eq!(
+            receiver.receiver.try_recv().unwrap(),
+            Message::Shard {
+                channel: "{news}.eu".into(),
+                payload: "hello".into()
+            }
+        );
+
+        // Classic and shard channels of the same name don't see each other
+        assert_eq!(broker.publish("{news}.eu".into(), "hello".into()), 0);
+        assert!(receiver.receiver.try_recv().is_err());
+
+        assert_eq!(
+            broker.shard_channels(Some(b"{news}*")),
+            vec![Bytes::from("{news}.eu")]
+        );
+        assert_eq!(broker.shard_numsub(&["news".into()]), vec![1]);
+
+        assert_eq!(
+            subscriber.unsubscribe(SubscriptionKind::Shard, &"news".into()),
+            1
+        );
+        assert_eq!(
+            subscriber.unsubscribe(SubscriptionKind::Shard, &"{news}.eu".into()),
+            0
+        );
+        assert!(broker.0.lock().unwrap().shard_channels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_overflow() {
+        let broker = Broker::default();
+
+        let (sender, mut receiver) = message_queue();
+        let mut subscriber = broker.subscriber(1, sender);
+        subscriber.subscribe(SubscriptionKind::Channel, "news".into());
+
+        let payload = Bytes::from(vec![b'x'; MAX_QUEUED_BYTES / 2]);
+        assert_eq!(broker.publish("news".into(), payload.clone()), 1);
+        assert_eq!(broker.publish("news".into(), payload.clone()), 0);
+
+        // Nothing more is queued, and the client is to be disconnected
+        assert_eq!(broker.publish("news".into(), "hello".into()), 0);
+
+        receiver.overflowed().await;
+        assert_eq!(receiver.recv().await, None);
+    }
+}
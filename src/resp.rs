@@ -1,6 +1,10 @@
 mod data;
+mod decoder;
 mod parse;
 
+pub use data::format_double;
+pub use data::Protocol;
 pub use data::Resp;
 pub use data::ToResp;
+pub use decoder::Decoder;
 pub use parse::Parser;
@@ -1,18 +1,44 @@
 use bytes::Bytes;
 
+/// The protocol version negotiated with a client through `HELLO`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
 #[derive(Debug)]
 pub enum Resp {
     SimpleString(String),
-    BulkString(String),
+    Error(String),
+    BulkString(Bytes),
     File(Bytes),
     Int(i64),
     Array(Vec<Resp>),
+    /// The RESP2 null bulk string, `$-1`
+    NullBulkString,
+    /// The RESP2 null array, `*-1`
+    NullArray,
+    /// The RESP3 null, `_`
+    Null,
+    Boolean(bool),
+    Double(f64),
+    BigNumber(String),
+    VerbatimString {
+        format: String,
+        text: Bytes,
+    },
+    Map(Vec<(Resp, Resp)>),
+    Set(Vec<Resp>),
+    Attribute(Vec<(Resp, Resp)>),
+    Push(Vec<Resp>),
 }
 
 impl Resp {
-    pub fn into_string(self) -> String {
+    pub fn into_bytes(self) -> Bytes {
         match self {
-            Resp::SimpleString(s) => s,
+            Resp::SimpleString(s) => s.into(),
             Resp::BulkString(s) => s,
             _ => panic!("Should only be called on strings"),
         }
@@ -22,9 +48,18 @@ impl Resp {
         match self {
             Resp::SimpleString(s) => format!("+{}\r\n", s).as_bytes().to_vec(),
 
+            // Like Redis, newlines are replaced so that an error quoting the
+            // client's input stays on one line
+            Resp::Error(s) => format!("-{}\r\n", s.replace(['\r', '\n'], " "))
+                .as_bytes()
+                .to_vec(),
+
             Resp::BulkString(s) => {
                 let len = s.len();
-                format!("${}\r\n{}\r\n", len, s).as_bytes().to_vec()
+                let mut vec = format!("${}\r\n", len).as_bytes().to_vec();
+                vec.extend(s);
+                vec.extend(b"\r\n");
+                vec
             }
 
             Resp::File(s) => {
@@ -36,17 +71,65 @@ impl Resp {
 
             Resp::Int(n) => format!(":{}\r\n", n).as_bytes().to_vec(),
 
-            Resp::Array(vec) => {
-                let mut s = format!("*{}\r\n", vec.len()).as_bytes().to_vec();
-                for resp in vec {
-                    s.extend(&resp.serialize());
-                }
-                s
+            Resp::Array(vec) => serialize_aggregate('*', vec),
+
+            Resp::NullBulkString => b"$-1\r\n".to_vec(),
+
+            Resp::NullArray => b"*-1\r\n".to_vec(),
+
+            Resp::Null => b"_\r\n".to_vec(),
+
+            Resp::Boolean(b) => if *b { b"#t\r\n" } else { b"#f\r\n" }.to_vec(),
+
+            Resp::Double(d) => format!(",{}\r\n", format_double(*d)).as_bytes().to_vec(),
+
+            Resp::BigNumber(n) => format!("({}\r\n", n).as_bytes().to_vec(),
+
+            Resp::VerbatimString { format, text } => {
+                let len = format.len() + 1 + text.len();
+                let mut vec = format!("={}\r\n{}:", len, format).as_bytes().to_vec();
+                vec.extend(text);
+                vec.extend(b"\r\n");
+                vec
             }
+
+            Resp::Map(pairs) => serialize_pairs('%', pairs),
+
+            Resp::Set(vec) => serialize_aggregate('~', vec),
+
+            Resp::Attribute(pairs) => serialize_pairs('|', pairs),
+
+            Resp::Push(vec) => serialize_aggregate('>', vec),
         }
     }
 }
 
+fn serialize_aggregate(prefix: char, vec: &[Resp]) -> Vec<u8> {
+    let mut s = format!("{}{}\r\n", prefix, vec.len()).as_bytes().to_vec();
+    for resp in vec {
+        s.extend(&resp.serialize());
+    }
+    s
+}
+
+fn serialize_pairs(prefix: char, pairs: &[(Resp, Resp)]) -> Vec<u8> {
+    let mut s = format!("{}{}\r\n", prefix, pairs.len()).as_bytes().to_vec();
+    for (key, value) in pairs {
+        s.extend(&key.serialize());
+        s.extend(&value.serialize());
+    }
+    s
+}
+
+/// Formats a double the way Redis does, e.g. `1.5`, `3`, `inf` and `nan`
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        "nan".to_owned()
+    } else {
+        d.to_string()
+    }
+}
+
 impl From<Vec<Resp>> for Resp {
     fn from(value: Vec<Resp>) -> Self {
         Resp::Array(value)
@@ -63,7 +146,10 @@ impl From<Vec<&str>> for Resp {
 
 impl From<Vec<String>> for Resp {
     fn from(values: Vec<String>) -> Self {
-        let resp_vec: Vec<_> = values.into_iter().map(Resp::BulkString).collect();
+        let resp_vec: Vec<_> = values
+            .into_iter()
+            .map(|s| Resp::BulkString(s.into()))
+            .collect();
 
         Self::Array(resp_vec)
     }
@@ -80,6 +166,16 @@ impl ToResp for str {
     }
 
     fn as_bulk_string(&self) -> Resp {
-        Resp::BulkString(self.to_string())
+        Resp::BulkString(Bytes::copy_from_slice(self.as_bytes()))
+    }
+}
+
+impl ToResp for [u8] {
+    fn as_simple_string(&self) -> Resp {
+        Resp::SimpleString(String::from_utf8_lossy(self).into_owned())
+    }
+
+    fn as_bulk_string(&self) -> Resp {
+        Resp::BulkString(Bytes::copy_from_slice(self))
     }
 }
@@ -0,0 +1,217 @@
+use bytes::{Buf, BytesMut};
+
+use super::{parse::Incomplete, Parser, Resp};
+
+/// A stateful decoder that buffers the bytes read from a connection and
+/// yields the complete frames in them, one at a time and in order.
+///
+/// Bytes belonging to a frame that hasn't been fully received yet are kept
+/// around until the rest of the frame arrives. The elements of an array that
+/// have arrived are parsed only once, and a blob isn't parsed again until all
+/// of it has arrived, so a large frame read in small pieces is parsed in
+/// linear time.
+#[derive(Default)]
+pub struct Decoder {
+    buf: BytesMut,
+    /// The array whose elements are still arriving: its length and the
+    /// elements parsed so far
+    array: Option<(usize, Vec<Resp>)>,
+    /// How long the buffer has to be before parsing is worth retrying
+    needed: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns the next complete frame in the buffer, or `None` if more data
+    /// is needed.
+    pub fn next_frame(&mut self) -> anyhow::Result<Option<Resp>> {
+        self.next_with(|parser| parser.parse())
+    }
+
+    /// Like `next_frame`, but also accepts inline commands. Empty lines are
+    /// skipped, the way Redis ignores them.
+    pub fn next_command(&mut self) -> anyhow::Result<Option<Resp>> {
+        loop {
+            match self.next_with(|parser| parser.parse_command())? {
+                Some(Resp::Array(args)) if args.is_empty() => continue,
+                command => return Ok(command),
+            }
+        }
+    }
+
+    fn next_with(
+        &mut self,
+        parse: impl FnOnce(&mut Parser) -> anyhow::Result<Resp>,
+    ) -> anyhow::Result<Option<Resp>> {
+        if self.buf.is_empty() || self.buf.len() < self.needed {
+            return Ok(None);
+        }
+
+        if self.array.is_none() && self.buf[0] == b'*' {
+            match self.parse_with(|parser| parser.parse_array_len())? {
+                Some(Some(len)) => self.array = Some((len, vec![])),
+                Some(None) => return Ok(Some(Resp::NullArray)),
+                None => return Ok(None),
+            }
+        }
+
+        let Some((len, mut elements)) = self.array.take() else {
+            return self.parse_with(parse);
+        };
+
+        while elements.len() < len {
+            match self.parse_with(|parser| parser.parse()) {
+                Ok(Some(element)) => elements.push(element),
+                result => {
+                    self.array = Some((len, elements));
+                    return result.map(|_| None);
+                }
+            }
+        }
+
+        Ok(Some(Resp::Array(elements)))
+    }
+
+    /// Parses the start of the buffer with `parse`, consuming what was parsed.
+    /// Returns `None` if more data is needed.
+    fn parse_with<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Parser) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Option<T>> {
+        let mut parser = Parser::new(&self.buf);
+
+        match parse(&mut parser) {
+            Ok(parsed) => {
+                let consumed = parser.position();
+                self.buf.advance(consumed);
+                self.needed = 0;
+                Ok(Some(parsed))
+            }
+            Err(err) if err.is::<Incomplete>() => {
+                self.needed = parser.needed().max(self.buf.len() + 1);
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_partial_frame() -> anyhow::Result<()> {
+        let encoded = b"*2\r\n$4\r\necho\r\n$5\r\nhello\r\n";
+        let mut decoder = Decoder::new();
+
+        for (i, byte) in encoded.iter().enumerate() {
+            decoder.extend(&[*byte]);
+            let frame = decoder.next_frame()?;
+
+            if i + 1 < encoded.len() {
+                assert!(frame.is_none());
+            } else {
+                assert_eq!(frame.unwrap().serialize(), encoded);
+            }
+        }
+
+        assert!(decoder.next_frame()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_pipelined_frames() -> anyhow::Result<()> {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"*1\r\n$4\r\nping\r\n*2\r\n$3\r\nget\r\n$3\r\nfoo\r\n*1\r\n$4\r\nPI");
+
+        let first = decoder.next_frame()?.unwrap();
+        assert_eq!(first.serialize(), b"*1\r\n$4\r\nping\r\n");
+
+        let second = decoder.next_frame()?.unwrap();
+        assert_eq!(second.serialize(), b"*2\r\n$3\r\nget\r\n$3\r\nfoo\r\n");
+
+        assert!(decoder.next_frame()?.is_none());
+
+        decoder.extend(b"NG\r\n");
+        let third = decoder.next_frame()?.unwrap();
+        assert_eq!(third.serialize(), b"*1\r\n$4\r\nPING\r\n");
+
+        assert!(decoder.next_frame()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_malformed_frame() {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"*1\r\n$4\r\npingxx\r\n");
+
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[test]
+    fn decode_large_bulk_in_pieces() -> anyhow::Result<()> {
+        let value = vec![b'x'; 10_000];
+        let mut encoded = b"*2\r\n$3\r\nget\r\n$10000\r\n".to_vec();
+        encoded.extend(&value);
+        encoded.extend(b"\r\n");
+
+        let mut decoder = Decoder::new();
+        decoder.extend(&encoded[..100]);
+        assert!(decoder.next_command()?.is_none());
+
+        // The array header and the first element were consumed, and the
+        // second element isn't parsed again until all of it is there
+        assert_eq!(decoder.needed, 8 + 10_000 + 2);
+
+        for chunk in encoded[100..].chunks(4096) {
+            decoder.extend(chunk);
+        }
+
+        assert_eq!(decoder.next_command()?.unwrap().serialize(), encoded);
+        assert!(decoder.buf.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_invalid_bulk_length() {
+        let lengths = ["18446744073709551610", "536870913"];
+
+        for len in lengths {
+            let mut decoder = Decoder::new();
+            decoder.extend(format!("*1\r\n${len}\r\n").as_bytes());
+
+            let err = decoder.next_command().unwrap_err();
+            assert_eq!(err.to_string(), "invalid bulk length");
+        }
+    }
+
+    #[test]
+    fn decode_inline_commands() -> anyhow::Result<()> {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"PING\r\n\r\n*1\r\n$4\r\nping\r\nSET foo");
+
+        let first = decoder.next_command()?.unwrap();
+        assert_eq!(first.serialize(), b"*1\r\n$4\r\nPING\r\n");
+
+        let second = decoder.next_command()?.unwrap();
+        assert_eq!(second.serialize(), b"*1\r\n$4\r\nping\r\n");
+
+        assert!(decoder.next_command()?.is_none());
+
+        decoder.extend(b" bar\n");
+        let third = decoder.next_command()?.unwrap();
+        assert_eq!(
+            third.serialize(),
+            b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n"
+        );
+        Ok(())
+    }
+}
@@ -1,34 +1,126 @@
 use anyhow::anyhow;
+use bytes::Bytes;
 
 use super::Resp;
 
+/// Returned when the input ends in the middle of a frame, i.e. more data has
+/// to be read before the frame can be parsed.
+#[derive(Debug, thiserror::Error)]
+#[error("Incomplete input")]
+pub struct Incomplete;
+
+/// The longest bulk string a client may send, like Redis' default
+/// `proto-max-bulk-len`
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// The longest inline command, or length header, a client may send without
+/// ending the line, like Redis' `PROTO_INLINE_MAX_SIZE`
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
 pub struct Parser<'a> {
     input: &'a [u8],
     idx: usize,
+    needed: usize,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(input: &'a str) -> Self {
+    pub fn new(input: &'a [u8]) -> Self {
         Self {
-            input: input.as_bytes(),
+            input,
             idx: 0,
+            needed: 0,
         }
     }
 
+    /// The number of bytes consumed by the frames parsed so far
+    pub fn position(&self) -> usize {
+        self.idx
+    }
+
+    /// How long the input has to be before the frame that was cut short is
+    /// worth parsing again, if the parser could tell. That's the case for a
+    /// blob whose length is known.
+    pub fn needed(&self) -> usize {
+        self.needed
+    }
+
+    /// Parses a command sent by a client, which is either an array of bulk
+    /// strings or, for telnet-style clients, an inline command
+    pub fn parse_command(&mut self) -> anyhow::Result<Resp> {
+        match self.input.get(self.idx).ok_or(Incomplete)? {
+            b'*' => self.parse(),
+            _ => self.parse_inline(),
+        }
+    }
+
+    /// Parses an inline command: space-separated arguments ending with a
+    /// newline, where arguments may be quoted the way `redis-cli` quotes them.
+    /// The arguments are returned as an array of bulk strings.
+    pub fn parse_inline(&mut self) -> anyhow::Result<Resp> {
+        let rest = &self.input[self.idx..];
+
+        let Some(line_len) = rest.iter().position(|&x| x == b'\n') else {
+            if rest.len() > MAX_INLINE_LEN {
+                return Err(anyhow!("too big inline request"));
+            }
+
+            return Err(Incomplete.into());
+        };
+
+        let line_end = self.idx + line_len;
+
+        let line = &self.input[self.idx..line_end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        let args = split_args(line)?
+            .into_iter()
+            .map(|arg| Resp::BulkString(arg.into()))
+            .collect();
+
+        self.idx = line_end + 1;
+        Ok(Resp::Array(args))
+    }
+
+    /// Parses the header of an array, returning its length, or `None` for
+    /// the RESP2 null array
+    pub fn parse_array_len(&mut self) -> anyhow::Result<Option<usize>> {
+        self.parse_len('*')
+    }
+
     pub fn parse(&mut self) -> anyhow::Result<Resp> {
-        let first_char = self.input[self.idx] as char;
+        // For rolling back in case of any error
+        let last_idx = self.idx;
+
+        self.parse_frame().inspect_err(|_| {
+            // Rollback
+            self.idx = last_idx;
+        })
+    }
+
+    fn parse_frame(&mut self) -> anyhow::Result<Resp> {
+        let first_char = *self.input.get(self.idx).ok_or(Incomplete)? as char;
 
         match first_char {
             '+' => self.parse_simple_string(),
+            '-' => self.parse_error(),
             '$' => self.parse_bulk_string(),
             ':' => self.parse_int(),
             '*' => self.parse_array(),
+            '_' => self.parse_null(),
+            '#' => self.parse_boolean(),
+            ',' => self.parse_double(),
+            '(' => self.parse_big_number(),
+            '=' => self.parse_verbatim_string(),
+            '%' => self.parse_map(),
+            '~' => self.parse_set(),
+            '|' => self.parse_attribute(),
+            '>' => self.parse_push(),
             _ => Err(anyhow!("Unexpected character")),
         }
     }
 
     fn swallow_char(&mut self, char: char, idx: usize) -> anyhow::Result<usize> {
-        let next_char = self.input[idx] as char;
+        let next_char = *self.input.get(idx).ok_or(Incomplete)? as char;
 
         if next_char == char {
             Ok(idx + 1)
@@ -45,99 +137,258 @@ impl<'a> Parser<'a> {
     fn peek_til_crlf(&self) -> anyhow::Result<usize> {
         let char_idx = self.input[self.idx..]
             .windows(2)
-            .position(|x| String::from_utf8_lossy(x) == "\r\n")
-            .ok_or(anyhow!("Runaway input"))?;
+            .position(|x| x == b"\r\n")
+            .ok_or(Incomplete)?;
 
         // We need to add the initial offset
         Ok(char_idx + self.idx)
     }
 
-    fn parse_bulk_string(&mut self) -> anyhow::Result<Resp> {
-        let idx = self.swallow_char('$', self.idx)?;
+    /// Parses a `<prefix><line>\r\n` frame and returns the line
+    fn parse_line(&mut self, prefix: char) -> anyhow::Result<String> {
+        let line_start = self.swallow_char(prefix, self.idx)?;
+
+        let line_end = self.peek_til_crlf()?;
 
-        let last_idx = self.peek_til_crlf()?;
+        let line = String::from_utf8_lossy(&self.input[line_start..line_end]).into_owned();
 
-        let len = self.input[idx..last_idx]
-            .iter()
-            .map(|&x| x as char)
-            .collect::<String>()
-            .parse::<usize>()?;
+        self.idx = line_end + 2;
+        Ok(line)
+    }
 
-        let str_start = last_idx + 2;
-        let str_end = str_start + len;
+    /// Parses the length header of an aggregate or a blob. `None` stands for
+    /// the RESP2 null length of -1.
+    fn parse_len(&mut self, prefix: char) -> anyhow::Result<Option<usize>> {
+        // Like Redis, a header can't be longer than an inline command, so
+        // that an unterminated one is neither buffered nor scanned forever
+        let rest = &self.input[self.idx..];
+        let header = &rest[..rest.len().min(MAX_INLINE_LEN + 2)];
+
+        if rest.len() > MAX_INLINE_LEN && !header.windows(2).any(|x| x == b"\r\n") {
+            return Err(match prefix {
+                '$' | '=' => anyhow!("too big bulk count string"),
+                _ => anyhow!("too big mbulk count string"),
+            });
+        }
 
-        let str = self.input[str_start..str_end]
-            .iter()
-            .map(|&x| x as char)
-            .collect::<String>();
+        let len = self.parse_line(prefix)?;
 
-        if str.len() != len {
-            return Err(anyhow!("Malformed bulk string".to_string()));
+        if len == "-1" {
+            return Ok(None);
         }
 
+        Ok(Some(len.parse::<usize>()?))
+    }
+
+    /// Parses the payload of a blob of `len` bytes, including the trailing CRLF
+    fn parse_blob(&mut self, len: usize) -> anyhow::Result<Bytes> {
+        let str_start = self.idx;
+        let str_end = str_start
+            .checked_add(len)
+            .filter(|_| len <= MAX_BULK_LEN)
+            .ok_or(anyhow!("invalid bulk length"))?;
+
+        let Some(str) = self.input.get(str_start..str_end) else {
+            self.needed = str_end + 2;
+            return Err(Incomplete.into());
+        };
+
         let idx = self.swallow_crlf(str_end)?;
         self.idx = idx;
 
-        Ok(Resp::BulkString(str))
+        Ok(Bytes::copy_from_slice(str))
     }
 
-    fn parse_simple_string(&mut self) -> anyhow::Result<Resp> {
-        let str_start = self.swallow_char('+', self.idx)?;
-
-        let str_end = self.peek_til_crlf()?;
+    fn parse_elements(&mut self, len: usize) -> anyhow::Result<Vec<Resp>> {
+        (0..len).map(|_| self.parse_frame()).collect()
+    }
 
-        let str = self.input[str_start..str_end]
-            .iter()
-            .map(|&x| x as char)
-            .collect::<String>();
+    fn parse_pairs(&mut self, len: usize) -> anyhow::Result<Vec<(Resp, Resp)>> {
+        (0..len)
+            .map(|_| Ok((self.parse_frame()?, self.parse_frame()?)))
+            .collect()
+    }
 
-        self.idx = str_end + 2;
-        Ok(Resp::SimpleString(str))
+    fn parse_bulk_string(&mut self) -> anyhow::Result<Resp> {
+        match self.parse_len('$')? {
+            Some(len) => Ok(Resp::BulkString(self.parse_blob(len)?)),
+            None => Ok(Resp::NullBulkString),
+        }
     }
 
-    fn parse_int(&mut self) -> anyhow::Result<Resp> {
-        let int_start = self.swallow_char(':', self.idx)?;
+    fn parse_simple_string(&mut self) -> anyhow::Result<Resp> {
+        Ok(Resp::SimpleString(self.parse_line('+')?))
+    }
 
-        let int_end = self.peek_til_crlf()?;
+    fn parse_error(&mut self) -> anyhow::Result<Resp> {
+        Ok(Resp::Error(self.parse_line('-')?))
+    }
 
-        let int = self.input[int_start..int_end]
-            .iter()
-            .map(|&x| x as char)
-            .collect::<String>()
+    fn parse_int(&mut self) -> anyhow::Result<Resp> {
+        let int = self
+            .parse_line(':')?
             .parse::<i64>()
             .map_err(|x| anyhow!("ParseIntError: {x}"))?;
 
-        self.idx = int_end + 2;
-
         Ok(Resp::Int(int))
     }
 
     fn parse_array(&mut self) -> anyhow::Result<Resp> {
-        let idx = self.swallow_char('*', self.idx)?;
+        match self.parse_len('*')? {
+            Some(len) => Ok(Resp::Array(self.parse_elements(len)?)),
+            None => Ok(Resp::NullArray),
+        }
+    }
 
-        let crlf_idx = self.peek_til_crlf()?;
+    fn parse_null(&mut self) -> anyhow::Result<Resp> {
+        let line = self.parse_line('_')?;
 
-        let len = self.input[idx..crlf_idx]
-            .iter()
-            .map(|&x| x as char)
-            .collect::<String>()
-            .parse::<usize>()?;
+        if !line.is_empty() {
+            return Err(anyhow!("Malformed null"));
+        }
 
-        // For rolling back in case of any error
-        let last_idx = self.idx;
+        Ok(Resp::Null)
+    }
+
+    fn parse_boolean(&mut self) -> anyhow::Result<Resp> {
+        match self.parse_line('#')?.as_str() {
+            "t" => Ok(Resp::Boolean(true)),
+            "f" => Ok(Resp::Boolean(false)),
+            other => Err(anyhow!("Malformed boolean: {other}")),
+        }
+    }
+
+    fn parse_double(&mut self) -> anyhow::Result<Resp> {
+        let double = self
+            .parse_line(',')?
+            .parse::<f64>()
+            .map_err(|x| anyhow!("ParseFloatError: {x}"))?;
+
+        Ok(Resp::Double(double))
+    }
 
-        self.idx = crlf_idx + 2;
+    fn parse_big_number(&mut self) -> anyhow::Result<Resp> {
+        let number = self.parse_line('(')?;
 
-        let result: Vec<_> = (0..len)
-            .map(|_| self.parse())
-            .collect::<Result<_, _>>()
-            .map_err(|err| {
-                // Rollback
-                self.idx = last_idx;
-                err
-            })?;
+        let digits = number.strip_prefix('-').unwrap_or(&number);
 
-        Ok(Resp::Array(result))
+        if digits.is_empty() || !digits.bytes().all(|x| x.is_ascii_digit()) {
+            return Err(anyhow!("Malformed big number: {number}"));
+        }
+
+        Ok(Resp::BigNumber(number))
+    }
+
+    fn parse_verbatim_string(&mut self) -> anyhow::Result<Resp> {
+        let len = self
+            .parse_len('=')?
+            .ok_or(anyhow!("Malformed verbatim string"))?;
+
+        let blob = self.parse_blob(len)?;
+
+        if blob.len() < 4 || blob[3] != b':' {
+            return Err(anyhow!("Malformed verbatim string"));
+        }
+
+        let format = String::from_utf8_lossy(&blob[..3]).into_owned();
+        let text = blob.slice(4..);
+
+        Ok(Resp::VerbatimString { format, text })
+    }
+
+    fn parse_map(&mut self) -> anyhow::Result<Resp> {
+        let len = self.parse_len('%')?.ok_or(anyhow!("Malformed map"))?;
+        Ok(Resp::Map(self.parse_pairs(len)?))
+    }
+
+    fn parse_set(&mut self) -> anyhow::Result<Resp> {
+        let len = self.parse_len('~')?.ok_or(anyhow!("Malformed set"))?;
+        Ok(Resp::Set(self.parse_elements(len)?))
+    }
+
+    fn parse_attribute(&mut self) -> anyhow::Result<Resp> {
+        let len = self.parse_len('|')?.ok_or(anyhow!("Malformed attribute"))?;
+        Ok(Resp::Attribute(self.parse_pairs(len)?))
+    }
+
+    fn parse_push(&mut self) -> anyhow::Result<Resp> {
+        let len = self.parse_len('>')?.ok_or(anyhow!("Malformed push"))?;
+        Ok(Resp::Push(self.parse_elements(len)?))
+    }
+}
+
+/// Splits an inline command line into its arguments, following the quoting
+/// rules of Redis' `sdssplitargs`
+fn split_args(line: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut args = vec![];
+    let mut chars = line.iter().copied().peekable();
+
+    loop {
+        while chars.next_if(|x| x.is_ascii_whitespace()).is_some() {}
+
+        let Some(first) = chars.next() else {
+            return Ok(args);
+        };
+
+        let mut arg = vec![];
+
+        match first {
+            b'"' => loop {
+                match chars.next() {
+                    Some(b'"') => break,
+                    Some(b'\\') => match chars.next() {
+                        Some(b'x') => {
+                            let hex = [chars.next(), chars.next()];
+
+                            match hex {
+                                [Some(hi), Some(lo)]
+                                    if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() =>
+                                {
+                                    let hex = [hi, lo];
+                                    let hex = std::str::from_utf8(&hex)?;
+                                    arg.push(u8::from_str_radix(hex, 16)?);
+                                }
+                                _ => return Err(anyhow!("invalid escape in request")),
+                            }
+                        }
+                        Some(b'n') => arg.push(b'\n'),
+                        Some(b'r') => arg.push(b'\r'),
+                        Some(b't') => arg.push(b'\t'),
+                        Some(b'b') => arg.push(0x08),
+                        Some(b'a') => arg.push(0x07),
+                        Some(c) => arg.push(c),
+                        None => return Err(anyhow!("unbalanced quotes in request")),
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err(anyhow!("unbalanced quotes in request")),
+                }
+            },
+            b'\'' => loop {
+                match chars.next() {
+                    Some(b'\'') => break,
+                    Some(b'\\') if chars.peek() == Some(&b'\'') => {
+                        chars.next();
+                        arg.push(b'\'');
+                    }
+                    Some(c) => arg.push(c),
+                    None => return Err(anyhow!("unbalanced quotes in request")),
+                }
+            },
+            c => {
+                arg.push(c);
+
+                while let Some(c) = chars.next_if(|x| !x.is_ascii_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+
+        // A closing quote must be followed by a space or the end of the line
+        if matches!(first, b'"' | b'\'') && chars.peek().is_some_and(|x| !x.is_ascii_whitespace()) {
+            return Err(anyhow!("unbalanced quotes in request"));
+        }
+
+        args.push(arg);
     }
 }
 
@@ -149,8 +400,8 @@ mod tests {
     fn parse_simple_string() -> anyhow::Result<()> {
         let s = "lisp";
         let encoded = format!("+{s}\r\n");
-        let mut parser = Parser::new(&encoded);
-        let decoded = parser.parse()?.into_string();
+        let mut parser = Parser::new(encoded.as_bytes());
+        let decoded = parser.parse()?.into_bytes();
         assert_eq!(decoded, s);
         Ok(())
     }
@@ -159,8 +410,8 @@ mod tests {
     fn parse_empty_bulk_string() -> anyhow::Result<()> {
         let s = "";
         let encoded = format!("${}\r\n{}\r\n", s.len(), s);
-        let mut parser = Parser::new(&encoded);
-        let decoded = parser.parse()?.into_string();
+        let mut parser = Parser::new(encoded.as_bytes());
+        let decoded = parser.parse()?.into_bytes();
         assert_eq!(decoded, s);
         Ok(())
     }
@@ -169,8 +420,8 @@ mod tests {
     fn parse_bulk_string() -> anyhow::Result<()> {
         let s = "This is a bulk string!";
         let encoded = format!("${}\r\n{}\r\n", s.len(), s);
-        let mut parser = Parser::new(&encoded);
-        let decoded = parser.parse()?.into_string();
+        let mut parser = Parser::new(encoded.as_bytes());
+        let decoded = parser.parse()?.into_bytes();
         assert_eq!(decoded, s);
         Ok(())
     }
@@ -180,12 +431,12 @@ mod tests {
         let s = "*2\r\n$5\r\nhello\r\n$6\r\nworld!\r\n";
         let expected_out = vec!["hello", "world!"];
 
-        let mut parser = Parser::new(s);
+        let mut parser = Parser::new(s.as_bytes());
 
         let decoded = parser.parse()?;
 
         if let Resp::Array(vec) = decoded {
-            let decoded: Vec<_> = vec.into_iter().map(|x| x.into_string()).collect();
+            let decoded: Vec<_> = vec.into_iter().map(|x| x.into_bytes()).collect();
             assert_eq!(decoded, expected_out);
         } else {
             panic!("Expected an array!");
@@ -197,7 +448,7 @@ mod tests {
     fn parse_array_rollback() -> anyhow::Result<()> {
         let s = "abcd*2\r\n$5\r\nhello\r\n$6\r\nworld\r\n";
 
-        let mut parser = Parser::new(s);
+        let mut parser = Parser::new(s.as_bytes());
         parser.idx = 4;
 
         let decoded = parser.parse();
@@ -208,4 +459,123 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn parse_binary_bulk_string() -> anyhow::Result<()> {
+        let s: &[u8] = &[0xff, 0x00, b'\r', b'\n', 0x80, 0xfe];
+        let mut encoded = format!("${}\r\n", s.len()).into_bytes();
+        encoded.extend(s);
+        encoded.extend(b"\r\n");
+
+        let mut parser = Parser::new(&encoded);
+        let decoded = parser.parse()?;
+
+        assert_eq!(decoded.serialize(), encoded);
+        assert_eq!(decoded.into_bytes(), s);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_resp2_nulls() -> anyhow::Result<()> {
+        let mut parser = Parser::new(b"$-1\r\n*-1\r\n");
+
+        assert!(matches!(parser.parse()?, Resp::NullBulkString));
+        assert!(matches!(parser.parse()?, Resp::NullArray));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_resp3_types() -> anyhow::Result<()> {
+        let encoded: &[&[u8]] = &[
+            b"-ERR unknown command 'foo'\r\n",
+            b"_\r\n",
+            b"#t\r\n",
+            b"#f\r\n",
+            b",3.25\r\n",
+            b",inf\r\n",
+            b"(3492890328409238509324850943850943825024385\r\n",
+            b"=15\r\ntxt:Some string\r\n",
+            b"%2\r\n+first\r\n:1\r\n+second\r\n:2\r\n",
+            b"~2\r\n+orange\r\n+apple\r\n",
+            b"|1\r\n+key-popularity\r\n%1\r\n$1\r\na\r\n,0.1923\r\n",
+            b">3\r\n$7\r\nmessage\r\n$3\r\nfoo\r\n$3\r\nbar\r\n",
+        ];
+
+        for encoded in encoded {
+            let mut parser = Parser::new(encoded);
+            let decoded = parser.parse()?;
+            assert_eq!(&decoded.serialize(), encoded);
+            assert_eq!(parser.position(), encoded.len());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_incomplete_map_rollback() {
+        let mut parser = Parser::new(b"%2\r\n+first\r\n:1\r\n+second\r\n");
+
+        let decoded = parser.parse();
+
+        assert!(decoded.unwrap_err().is::<Incomplete>());
+        assert_eq!(parser.position(), 0);
+    }
+
+    #[test]
+    fn parse_inline_command() -> anyhow::Result<()> {
+        let s = b"SET  foo \"hello \\\"world\\\"\\x41\" 'it\\'s'\r\nPING\n";
+        let mut parser = Parser::new(s);
+
+        let decoded = parser.parse_command()?;
+        assert_eq!(
+            decoded.serialize(),
+            b"*4\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$14\r\nhello \"world\"A\r\n$4\r\nit's\r\n"
+        );
+
+        let decoded = parser.parse_command()?;
+        assert_eq!(decoded.serialize(), b"*1\r\n$4\r\nPING\r\n");
+
+        assert!(parser.parse_command().unwrap_err().is::<Incomplete>());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_too_big_inline_command() {
+        let mut line = vec![b'a'; MAX_INLINE_LEN];
+        let mut parser = Parser::new(&line);
+        assert!(parser.parse_command().unwrap_err().is::<Incomplete>());
+
+        line.push(b'a');
+        let mut parser = Parser::new(&line);
+        let err = parser.parse_command().unwrap_err();
+        assert_eq!(err.to_string(), "too big inline request");
+    }
+
+    #[test]
+    fn parse_too_big_header() {
+        for (start, error) in [
+            (&b"*"[..], "too big mbulk count string"),
+            (b"*1\r\n$", "too big bulk count string"),
+        ] {
+            // The limit applies from the start of the header
+            let header_start = start.len() - 1;
+            let mut header = start.to_vec();
+            header.resize(header_start + MAX_INLINE_LEN, b'1');
+            let mut parser = Parser::new(&header);
+            assert!(parser.parse_command().unwrap_err().is::<Incomplete>());
+
+            header.push(b'1');
+            let mut parser = Parser::new(&header);
+            let err = parser.parse_command().unwrap_err();
+            assert_eq!(err.to_string(), error);
+        }
+    }
+
+    #[test]
+    fn parse_inline_unbalanced_quotes() {
+        let mut parser = Parser::new(b"SET foo \"bar\r\n");
+        assert!(parser.parse_command().is_err());
+
+        let mut parser = Parser::new(b"SET foo \"bar\"baz\r\n");
+        assert!(parser.parse_command().is_err());
+    }
 }
@@ -0,0 +1,51 @@
+/// The number of hash slots the keyspace is split into, as in Redis Cluster
+pub const SLOT_COUNT: u16 = 16384;
+
+/// CRC16-CCITT (XMODEM), the checksum Redis Cluster hashes keys with
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// The hash slot of `key` the way Redis Cluster computes it. If the key has
+/// a non-empty hash tag, e.g. `{user1}` in `{user1}.name`, only the tag is
+/// hashed, so that related keys share a slot.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|&x| x == b'{').and_then(|start| {
+        let len = key[start + 1..].iter().position(|&x| x == b'}')?;
+        (len > 0).then(|| &key[start + 1..start + 1 + len])
+    });
+
+    crc16(tag.unwrap_or(key)) % SLOT_COUNT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_match_redis_cluster() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"user1000")
+        );
+        // Empty tags are hashed along with the rest of the key
+        assert_eq!(key_hash_slot(b"{}foo"), crc16(b"{}foo") % SLOT_COUNT);
+        assert_eq!(key_hash_slot(b"foo{bar"), crc16(b"foo{bar") % SLOT_COUNT);
+    }
+}
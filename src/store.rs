@@ -1,54 +1,134 @@
+mod blocking;
+mod db;
+mod decimal;
+mod error;
+mod expiry;
+mod glob;
+mod hash;
+mod list;
+mod sampled;
+mod scan;
+mod set;
+mod stream;
+mod value;
+mod zset;
+
+use bytes::Bytes;
 use std::{
-    collections::HashMap,
     ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
     time::{SystemTime, UNIX_EPOCH},
 };
 
-type Key = String;
+pub use blocking::{Blocking, BlockingOp, Served, StreamBlocking};
+use db::Db;
+use decimal::format_sum;
+pub use error::StoreError;
+pub use glob::glob_match;
+pub use list::ListEnd;
+pub use set::SetOp;
+pub use stream::{
+    Claim, ClaimOptions, NewStreamId, PendingRange, StreamEntry, StreamId, StreamReadOp,
+    StreamTrim, StreamsRead,
+};
+pub use value::Value;
+pub use zset::{
+    Aggregate, LexBound, LexRange, ScoreBound, ScoreRange, ZAddFlags, ZRange, ZRangeBy,
+};
+
+type Key = Bytes;
 
 #[derive(Debug)]
 pub struct StoreItem {
-    pub value: String,
+    pub value: Value,
     pub expiry: Option<u128>,
 }
 
-type StoreType = Arc<Mutex<HashMap<Key, StoreItem>>>;
+type StoreType = Arc<Mutex<Db>>;
 
-impl StoreItem {
-    pub fn new(value: &str, expiry: Option<u64>) -> anyhow::Result<Self> {
-        let value = value.to_owned();
-        let mut expiry_time = None;
+/// The current time in milliseconds since the UNIX epoch
+pub fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Unable to get the current time")
+        .as_millis()
+}
+
+/// Whether the absolute time `expiry`, in milliseconds, has passed
+fn is_past(expiry: u128) -> bool {
+    now_millis() > expiry
+}
 
-        if let Some(expiry) = expiry {
-            let current_time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+/// The condition under which `SET` writes a key: `NX` or `XX`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    IfNotExists,
+    IfExists,
+}
 
-            let current_time = current_time.as_millis();
+/// The `NX`/`XX`/`GT`/`LT` options of `EXPIRE`. `XX` may be combined with
+/// `GT` or `LT`.
+#[derive(Clone, Copy, Default)]
+pub struct ExpireCondition {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+}
 
-            expiry_time = Some(current_time + expiry as u128);
-        }
+impl ExpireCondition {
+    /// Whether `new` may replace the `current` expiry. A key without an
+    /// expiry is treated as having an infinite TTL.
+    fn allows(&self, current: Option<u128>, new: u128) -> bool {
+        (!self.nx || current.is_none())
+            && (!self.xx || current.is_some())
+            && (!self.gt || current.is_some_and(|current| new > current))
+            && (!self.lt || current.is_none_or(|current| new < current))
+    }
+}
 
-        Ok(Self {
-            value,
-            expiry: expiry_time,
-        })
+impl StoreItem {
+    /// Creates an item expiring at an absolute time in milliseconds
+    pub fn new(value: Value, expiry: Option<u128>) -> Self {
+        Self { value, expiry }
     }
 
     pub fn has_expired(&self) -> bool {
-        if self.expiry.is_none() {
-            return false;
-        }
+        self.expiry.is_some_and(is_past)
+    }
+}
 
-        let expiry = self.expiry.unwrap();
+/// Parses an integer the way Redis does, rejecting anything but the
+/// canonical representation, e.g. `+1`, `01` or ` 1`
+pub fn parse_i64(value: &[u8]) -> Option<i64> {
+    let value = std::str::from_utf8(value).ok()?;
+    let n = value.parse::<i64>().ok()?;
 
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Unable to get the current time");
+    (n.to_string() == value).then_some(n)
+}
 
-        let current_time = current_time.as_millis();
+/// Parses a float, rejecting NaN, surrounding spaces and trailing garbage
+pub fn parse_f64(value: &[u8]) -> Option<f64> {
+    let value = std::str::from_utf8(value).ok()?;
 
-        current_time > expiry
+    if value.is_empty() || value.trim() != value {
+        return None;
     }
+
+    value.parse::<f64>().ok().filter(|n| !n.is_nan())
+}
+
+/// The largest string a value may grow to, like Redis' default
+/// `proto-max-bulk-len`
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// A snapshot of the store's counters, for `INFO`
+pub struct StoreInfo {
+    pub keys: usize,
+    pub expires: usize,
+    pub expired_keys: u64,
+    pub expired_subkeys: u64,
+    pub expired_time_cap_reached_count: u64,
 }
 
 #[derive(Default)]
@@ -73,22 +153,691 @@ impl Store {
         Self(Arc::clone(&self.0))
     }
 
-    pub fn insert(&mut self, key: &str, value: &str, expiry: Option<u64>) -> anyhow::Result<()> {
-        let mut m = self.0.lock().unwrap();
-        let item = StoreItem::new(value, expiry)?;
-        m.insert(key.to_owned(), item);
-        Ok(())
+    /// Sets `key` to `value` expiring at `expiry`, provided `condition`
+    /// holds. Returns whether the key was set, along with its old value.
+    /// With `get`, the old value has to be a string.
+    pub fn set(
+        &mut self,
+        key: Bytes,
+        value: Bytes,
+        expiry: Option<u128>,
+        keep_ttl: bool,
+        condition: Option<SetCondition>,
+        get: bool,
+    ) -> Result<(bool, Option<Bytes>), StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let old = db.get(&key);
+
+        let allowed = match condition {
+            Some(SetCondition::IfNotExists) => old.is_none(),
+            Some(SetCondition::IfExists) => old.is_some(),
+            None => true,
+        };
+
+        let old_expiry = old.as_ref().and_then(|item| item.expiry);
+        let old_value = match old {
+            Some(item) if get => Some(item.value.as_string()?.clone()),
+            _ => None,
+        };
+
+        if !allowed {
+            return Ok((false, old_value));
+        }
+
+        let expiry = if keep_ttl { old_expiry } else { expiry };
+
+        db.insert(key, StoreItem::new(value.into(), expiry));
+
+        Ok((true, old_value))
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        get_string(&mut db, key)
     }
 
-    pub fn get(&self, key: &str) -> Option<String> {
-        let m = self.0.lock().unwrap();
+    /// Appends `value` to the string at `key`, creating it if needed.
+    /// Returns the new length.
+    pub fn append(&mut self, key: Bytes, value: &[u8]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
 
-        let item = m.get(key)?;
+        let mut new_value = get_string(&mut db, &key)?
+            .map(|value| value.to_vec())
+            .unwrap_or_default();
 
-        if item.has_expired() {
-            return None;
+        if new_value.len() + value.len() > MAX_STRING_LEN {
+            return Err(StoreError::StringTooLong);
         }
 
-        Some(item.value.to_owned())
+        new_value.extend_from_slice(value);
+        let len = new_value.len();
+
+        set_keeping_ttl(&mut db, key, new_value.into());
+
+        Ok(len)
+    }
+
+    /// Overwrites the string at `key` starting at `offset`, padding it with
+    /// zero bytes if it's too short. Returns the new length.
+    pub fn set_range(
+        &mut self,
+        key: Bytes,
+        offset: usize,
+        value: &[u8],
+    ) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let current = get_string(&mut db, &key)?;
+
+        // An empty value doesn't create the key, nor does it pad it
+        if value.is_empty() {
+            return Ok(current.map_or(0, |value| value.len()));
+        }
+
+        if offset + value.len() > MAX_STRING_LEN {
+            return Err(StoreError::StringTooLong);
+        }
+
+        let mut new_value = current.map(|value| value.to_vec()).unwrap_or_default();
+
+        if new_value.len() < offset + value.len() {
+            new_value.resize(offset + value.len(), 0);
+        }
+
+        new_value[offset..offset + value.len()].copy_from_slice(value);
+        let len = new_value.len();
+
+        set_keeping_ttl(&mut db, key, new_value.into());
+
+        Ok(len)
+    }
+
+    /// Deletes the string at `key`, returning its value
+    pub fn get_del(&mut self, key: &[u8]) -> Result<Option<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let value = get_string(&mut db, key)?;
+
+        if value.is_some() {
+            db.remove(key);
+        }
+
+        Ok(value)
+    }
+
+    /// Returns the value at `key`, and if `expiry` is given, replaces the
+    /// key's expiry with it. A deadline in the past deletes the key.
+    pub fn get_ex(
+        &mut self,
+        key: &[u8],
+        expiry: Option<Option<u128>>,
+    ) -> Result<Option<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(value) = get_string(&mut db, key)? else {
+            return Ok(None);
+        };
+
+        match expiry {
+            Some(Some(deadline)) if deadline <= now_millis() => {
+                db.remove(key);
+            }
+            Some(expiry) => db.set_expiry(key, expiry),
+            None => {}
+        }
+
+        Ok(Some(value))
+    }
+
+    /// The values at `keys`, where keys that don't hold strings are missing
+    pub fn mget(&self, keys: &[Bytes]) -> Vec<Option<Bytes>> {
+        let mut db = self.0.lock().unwrap();
+
+        keys.iter()
+            .map(|key| get_string(&mut db, key).ok().flatten())
+            .collect()
+    }
+
+    /// Sets every key to its value, discarding any expiries. With `nx`,
+    /// nothing is set if any of the keys exists. Returns whether the keys
+    /// were set.
+    pub fn mset(&mut self, pairs: Vec<(Bytes, Bytes)>, nx: bool) -> bool {
+        let mut db = self.0.lock().unwrap();
+
+        if nx && pairs.iter().any(|(key, _)| db.get(key).is_some()) {
+            return false;
+        }
+
+        for (key, value) in pairs {
+            db.insert(key, StoreItem::new(value.into(), None));
+        }
+
+        true
+    }
+
+    /// Sets the expiry of `key`, provided it exists and `condition` holds.
+    /// A deadline in the past deletes the key.
+    pub fn expire(&mut self, key: &[u8], deadline: u128, condition: ExpireCondition) -> bool {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(item) = db.get(key) else {
+            return false;
+        };
+
+        if !condition.allows(item.expiry, deadline) {
+            return false;
+        }
+
+        if deadline <= now_millis() {
+            db.remove(key);
+        } else {
+            db.set_expiry(key, Some(deadline));
+        }
+
+        true
+    }
+
+    /// The expiry of `key`, or `None` if the key doesn't exist
+    pub fn expiry(&self, key: &[u8]) -> Option<Option<u128>> {
+        let mut db = self.0.lock().unwrap();
+
+        let item = db.get(key)?;
+
+        Some(item.expiry)
+    }
+
+    /// Removes the expiry of `key`. Returns whether there was one.
+    pub fn persist(&mut self, key: &[u8]) -> bool {
+        let mut db = self.0.lock().unwrap();
+
+        let had_expiry = db.get(key).is_some_and(|item| item.expiry.is_some());
+
+        if had_expiry {
+            db.set_expiry(key, None);
+        }
+
+        had_expiry
+    }
+
+    /// Deletes `keys`, returning the number of keys that existed
+    pub fn del(&mut self, keys: &[Bytes]) -> usize {
+        let mut db = self.0.lock().unwrap();
+
+        keys.iter()
+            .filter(|key| db.get(key).is_some() && db.remove(key).is_some())
+            .count()
+    }
+
+    /// The number of `keys` that exist, counting repeated keys every time
+    pub fn exists(&self, keys: &[Bytes]) -> usize {
+        let mut db = self.0.lock().unwrap();
+
+        keys.iter().filter(|key| db.get(key).is_some()).count()
+    }
+
+    /// The type of the value at `key`, as reported by `TYPE`
+    pub fn key_type(&self, key: &[u8]) -> Option<&'static str> {
+        let mut db = self.0.lock().unwrap();
+
+        db.get(key).map(|item| item.value.type_name())
+    }
+
+    /// Every key matching the glob-style `pattern`
+    pub fn keys(&self, pattern: &[u8]) -> Vec<Bytes> {
+        let db = self.0.lock().unwrap();
+
+        db.iter()
+            .map(|(key, _)| key)
+            .filter(|key| glob_match(pattern, key, false))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the next batch of keys from a `SCAN` at `cursor`, along with
+    /// the cursor to continue from. As in Redis, `pattern` and `key_type`
+    /// filter the batch after it's picked, so it may end up empty.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&[u8]>,
+        key_type: Option<&str>,
+    ) -> (u64, Vec<Bytes>) {
+        let mut db = self.0.lock().unwrap();
+
+        let (cursor, keys) = db.scan(cursor, count);
+
+        let keys = keys
+            .into_iter()
+            .filter(|key| pattern.is_none_or(|pattern| glob_match(pattern, key, false)))
+            .filter(|key| match db.get(key) {
+                Some(item) => key_type.is_none_or(|key_type| item.value.type_name() == key_type),
+                None => false,
+            })
+            .collect();
+
+        (cursor, keys)
+    }
+
+    /// Renames `key` to `new_key`, keeping its expiry. With `nx`, an existing
+    /// `new_key` isn't overwritten. Returns whether the key was renamed, or
+    /// `None` if `key` doesn't exist.
+    pub fn rename(&mut self, key: &[u8], new_key: Bytes, nx: bool) -> Option<bool> {
+        let mut db = self.0.lock().unwrap();
+
+        db.get(key)?;
+
+        if key == new_key {
+            return Some(!nx);
+        }
+
+        if nx && db.get(&new_key).is_some() {
+            return Some(false);
+        }
+
+        let item = db.remove(key)?;
+        db.insert(new_key, item);
+        db.serve_blocked();
+
+        Some(true)
+    }
+
+    /// Adds `delta` to the integer at `key`, treating a missing key as 0.
+    /// The key's expiry is kept.
+    pub fn incr_by(&mut self, key: Bytes, delta: i64) -> Result<i64, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let current = match get_string(&mut db, &key)? {
+            Some(value) => parse_i64(&value).ok_or(StoreError::NotInteger)?,
+            None => 0,
+        };
+
+        let value = current.checked_add(delta).ok_or(StoreError::Overflow)?;
+
+        set_keeping_ttl(&mut db, key, value.to_string().into());
+
+        Ok(value)
+    }
+
+    /// Adds `delta` to the float at `key`, treating a missing key as 0.
+    /// Returns the new value as stored. The key's expiry is kept.
+    pub fn incr_by_float(&mut self, key: Bytes, delta: f64) -> Result<Bytes, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let current = match get_string(&mut db, &key)? {
+            Some(value) => parse_f64(&value).ok_or(StoreError::NotFloat)?,
+            None => 0.0,
+        };
+
+        if !(current + delta).is_finite() {
+            return Err(StoreError::NanOrInfinity);
+        }
+
+        let value: Bytes = format_sum(current, delta).into();
+
+        set_keeping_ttl(&mut db, key, value.clone());
+
+        Ok(value)
+    }
+
+    pub fn info(&self) -> StoreInfo {
+        let db = self.0.lock().unwrap();
+
+        StoreInfo {
+            keys: db.len(),
+            expires: db.expires_len(),
+            expired_keys: db.stats.expired_keys,
+            expired_subkeys: db.stats.expired_subkeys,
+            expired_time_cap_reached_count: db.stats.expired_time_cap_reached_count,
+        }
+    }
+}
+
+/// The string at `key`, failing if the key holds another type
+fn get_string(db: &mut Db, key: &[u8]) -> Result<Option<Bytes>, StoreError> {
+    db.get(key)
+        .map(|item| item.value.as_string().cloned())
+        .transpose()
+}
+
+/// Replaces the value at `key` with a string, keeping the expiry of the
+/// existing key
+fn set_keeping_ttl(db: &mut Db, key: Bytes, value: Bytes) {
+    match db.get(&key) {
+        Some(item) => item.value = value.into(),
+        None => db.insert(key, StoreItem::new(value.into(), None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn set(store: &mut Store, key: &'static str, value: &'static str) {
+        store
+            .set(key.into(), value.into(), None, false, None, false)
+            .unwrap();
+    }
+
+    #[test]
+    fn accessors_reject_other_types() {
+        type Accessor = fn(&mut Db, &[u8]) -> Result<(), StoreError>;
+
+        let mut store = Store::default();
+        let field = || vec![("f".into(), "v".into())];
+
+        set(&mut store, "string", "v");
+        store
+            .push("list".into(), vec!["a".into()], ListEnd::Right, false)
+            .unwrap();
+        store.hset("hash".into(), field()).unwrap();
+        store.sadd("set".into(), vec!["m".into()]).unwrap();
+        store
+            .zadd("zset".into(), vec![(1.0, "m".into())], ZAddFlags::default())
+            .unwrap();
+        store
+            .xadd("stream".into(), NewStreamId::Auto, field(), false, None)
+            .unwrap();
+
+        let accessors: [(&str, Accessor); 6] = [
+            ("string", |db, key| get_string(db, key).map(drop)),
+            ("list", |db, key| db.get_list(key).map(drop)),
+            ("hash", |db, key| db.get_hash(key).map(drop)),
+            ("set", |db, key| db.get_set(key).map(drop)),
+            ("zset", |db, key| db.get_zset(key).map(drop)),
+            ("stream", |db, key| db.get_stream(key).map(drop)),
+        ];
+
+        // Each key is named after its type
+        for (key, _) in accessors {
+            for (type_name, accessor) in accessors {
+                let result = accessor(&mut store.lock().unwrap(), key.as_bytes());
+
+                if type_name == key {
+                    assert!(result.is_ok());
+                } else {
+                    let err = result.unwrap_err();
+                    assert!(matches!(err, StoreError::WrongType), "{type_name} of {key}");
+                }
+            }
+
+            assert_eq!(store.key_type(key.as_bytes()), Some(key));
+        }
+
+        // The values are left as they were
+        assert_eq!(store.get(b"string").unwrap(), Some("v".into()));
+        assert_eq!(store.lrange(b"list", 0, -1).unwrap(), vec!["a"]);
+        assert_eq!(store.hgetall(b"hash").unwrap(), field());
+        assert_eq!(store.smembers(b"set").unwrap(), vec!["m"]);
+        assert_eq!(store.zcard(b"zset").unwrap(), 1);
+        assert_eq!(store.xlen(b"stream").unwrap(), 1);
+    }
+
+    #[test]
+    fn expire_conditions() {
+        let nx = ExpireCondition {
+            nx: true,
+            ..Default::default()
+        };
+        let xx = ExpireCondition {
+            xx: true,
+            ..Default::default()
+        };
+        let gt = ExpireCondition {
+            gt: true,
+            ..Default::default()
+        };
+        let lt = ExpireCondition {
+            lt: true,
+            ..Default::default()
+        };
+
+        // A key without an expiry has an infinite TTL, which nothing is
+        // greater than, while anything is less
+        assert!(nx.allows(None, 10));
+        assert!(!xx.allows(None, 10));
+        assert!(!gt.allows(None, 10));
+        assert!(lt.allows(None, 10));
+
+        assert!(!nx.allows(Some(10), 20));
+        assert!(xx.allows(Some(10), 20));
+        assert!(gt.allows(Some(10), 20));
+        assert!(!gt.allows(Some(10), 10));
+        assert!(lt.allows(Some(10), 5));
+        assert!(!lt.allows(Some(10), 10));
+
+        let xx_lt = ExpireCondition { xx: true, ..lt };
+        assert!(!xx_lt.allows(None, 10));
+        assert!(xx_lt.allows(Some(10), 5));
+    }
+
+    #[test]
+    fn expire_keys() {
+        let mut store = Store::default();
+        let later = now_millis() + 60_000;
+        let always = ExpireCondition::default();
+        let gt = ExpireCondition {
+            gt: true,
+            ..Default::default()
+        };
+
+        assert!(!store.expire(b"k", later, always));
+        assert_eq!(store.expiry(b"k"), None);
+
+        set(&mut store, "k", "v");
+        assert_eq!(store.expiry(b"k"), Some(None));
+        assert!(!store.expire(b"k", later, gt));
+        assert!(store.expire(b"k", later, always));
+        assert_eq!(store.expiry(b"k"), Some(Some(later)));
+        assert!(store.expire(b"k", later + 1, gt));
+
+        assert!(store.persist(b"k"));
+        assert!(!store.persist(b"k"));
+        assert_eq!(store.expiry(b"k"), Some(None));
+
+        // A deadline in the past deletes the key
+        assert!(store.expire(b"k", now_millis() - 1, always));
+        assert_eq!(store.expiry(b"k"), None);
+        assert_eq!(store.exists(&["k".into()]), 0);
+    }
+
+    #[test]
+    fn del_and_exists() {
+        let mut store = Store::default();
+        set(&mut store, "a", "1");
+        set(&mut store, "b", "2");
+        set(&mut store, "expired", "3");
+        store
+            .lock()
+            .unwrap()
+            .set_expiry(b"expired", Some(now_millis() - 1));
+
+        let keys: Vec<Bytes> = vec!["a".into(), "a".into(), "expired".into(), "c".into()];
+        assert_eq!(store.exists(&keys), 2);
+
+        // Expired keys don't count as deleted
+        assert_eq!(store.del(&keys), 1);
+        assert_eq!(store.exists(&["a".into(), "b".into()]), 1);
+    }
+
+    #[test]
+    fn rename_keys() {
+        let mut store = Store::default();
+        let later = now_millis() + 60_000;
+
+        assert_eq!(store.rename(b"a", "b".into(), false), None);
+
+        set(&mut store, "a", "1");
+        store.expire(b"a", later, ExpireCondition::default());
+        set(&mut store, "b", "2");
+
+        // RENAMENX leaves an existing key alone
+        assert_eq!(store.rename(b"a", "b".into(), true), Some(false));
+        assert_eq!(store.get(b"b").unwrap(), Some("2".into()));
+
+        // RENAME overwrites it, carrying the expiry over
+        assert_eq!(store.rename(b"a", "b".into(), false), Some(true));
+        assert_eq!(store.get(b"a").unwrap(), None);
+        assert_eq!(store.get(b"b").unwrap(), Some("1".into()));
+        assert_eq!(store.expiry(b"b"), Some(Some(later)));
+
+        assert_eq!(store.rename(b"b", "b".into(), false), Some(true));
+        assert_eq!(store.rename(b"b", "b".into(), true), Some(false));
+    }
+
+    #[tokio::test]
+    async fn rename_wakes_list_waiters() {
+        let mut store = Store::default();
+        let op = BlockingOp::Pop {
+            end: ListEnd::Left,
+            count: 1,
+        };
+
+        let client = match store.pop_or_block(vec!["dst".into()], op) {
+            Ok(Blocking::Blocked(client)) => client,
+            _ => panic!("The client should have blocked"),
+        };
+
+        store
+            .push("src".into(), vec!["a".into()], ListEnd::Right, false)
+            .unwrap();
+        assert_eq!(store.rename(b"src", "dst".into(), false), Some(true));
+
+        let served = client.wait(Some(Duration::from_millis(10))).await.unwrap();
+        assert_eq!(served, Some(("dst".into(), vec!["a".into()])));
+        assert_eq!(store.exists(&["dst".into()]), 0);
+    }
+
+    #[test]
+    fn set_range_pads_with_zeros() {
+        let mut store = Store::default();
+        let later = now_millis() + 60_000;
+
+        // An empty value neither creates nor pads the key
+        assert_eq!(store.set_range("k".into(), 5, b"").unwrap(), 0);
+        assert_eq!(store.exists(&["k".into()]), 0);
+
+        assert_eq!(store.set_range("k".into(), 3, b"ab").unwrap(), 5);
+        assert_eq!(store.get(b"k").unwrap(), Some("\0\0\0ab".into()));
+
+        store.expire(b"k", later, ExpireCondition::default());
+        assert_eq!(store.set_range("k".into(), 1, b"xyzw").unwrap(), 5);
+        assert_eq!(store.get(b"k").unwrap(), Some("\0xyzw".into()));
+        assert_eq!(store.expiry(b"k"), Some(Some(later)));
+
+        for (offset, value) in [(MAX_STRING_LEN, &b"a"[..]), (MAX_STRING_LEN - 1, b"ab")] {
+            let err = store.set_range("k".into(), offset, value).unwrap_err();
+            assert!(matches!(err, StoreError::StringTooLong));
+        }
+        assert_eq!(store.get(b"k").unwrap(), Some("\0xyzw".into()));
+    }
+
+    #[test]
+    fn get_ex_expiries() {
+        let mut store = Store::default();
+        let later = now_millis() + 60_000;
+
+        assert_eq!(store.get_ex(b"k", Some(Some(later))).unwrap(), None);
+        assert_eq!(store.expiry(b"k"), None);
+
+        set(&mut store, "k", "v");
+        assert_eq!(
+            store.get_ex(b"k", Some(Some(later))).unwrap(),
+            Some("v".into())
+        );
+        assert_eq!(store.expiry(b"k"), Some(Some(later)));
+
+        // Without options the expiry is left alone, while PERSIST drops it
+        assert_eq!(store.get_ex(b"k", None).unwrap(), Some("v".into()));
+        assert_eq!(store.expiry(b"k"), Some(Some(later)));
+        assert_eq!(store.get_ex(b"k", Some(None)).unwrap(), Some("v".into()));
+        assert_eq!(store.expiry(b"k"), Some(None));
+
+        // A deadline in the past still returns the value, but deletes the key
+        let past = Some(Some(now_millis() - 1));
+        assert_eq!(store.get_ex(b"k", past).unwrap(), Some("v".into()));
+        assert_eq!(store.expiry(b"k"), None);
+    }
+
+    #[test]
+    fn msetnx_is_all_or_nothing() {
+        let mut store = Store::default();
+        let later = now_millis() + 60_000;
+        let pairs = |pairs: &[(&'static str, &'static str)]| {
+            pairs
+                .iter()
+                .map(|&(key, value)| (key.into(), value.into()))
+                .collect::<Vec<(Bytes, Bytes)>>()
+        };
+
+        set(&mut store, "b", "old");
+        assert!(!store.mset(pairs(&[("a", "1"), ("b", "2")]), true));
+        assert_eq!(store.get(b"a").unwrap(), None);
+        assert_eq!(store.get(b"b").unwrap(), Some("old".into()));
+
+        assert!(store.mset(pairs(&[("a", "1"), ("c", "3")]), true));
+        assert_eq!(
+            store.mget(&["a".into(), "b".into(), "c".into()]),
+            vec![Some("1".into()), Some("old".into()), Some("3".into())]
+        );
+
+        // MSET overwrites, dropping the expiries
+        store.expire(b"b", later, ExpireCondition::default());
+        assert!(store.mset(pairs(&[("b", "2")]), false));
+        assert_eq!(store.get(b"b").unwrap(), Some("2".into()));
+        assert_eq!(store.expiry(b"b"), Some(None));
+    }
+
+    #[test]
+    fn incr_integers() {
+        let mut store = Store::default();
+        let later = now_millis() + 60_000;
+
+        assert_eq!(store.incr_by("n".into(), 5).unwrap(), 5);
+        store.expire(b"n", later, ExpireCondition::default());
+        assert_eq!(store.incr_by("n".into(), -7).unwrap(), -2);
+        assert_eq!(store.expiry(b"n"), Some(Some(later)));
+
+        set(&mut store, "max", "9223372036854775807");
+        let err = store.incr_by("max".into(), 1).unwrap_err();
+        assert!(matches!(err, StoreError::Overflow));
+        assert_eq!(err.to_string(), "ERR increment or decrement would overflow");
+        assert_eq!(
+            store.get(b"max").unwrap(),
+            Some("9223372036854775807".into())
+        );
+
+        for value in ["01", " 1", "1 ", "+1", "1.0", ""] {
+            set(&mut store, "n", value);
+            let err = store.incr_by("n".into(), 1).unwrap_err();
+            assert!(matches!(err, StoreError::NotInteger), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn incr_floats() {
+        let mut store = Store::default();
+
+        set(&mut store, "f", "0.1");
+        assert_eq!(store.incr_by_float("f".into(), 0.2).unwrap(), "0.3");
+        assert_eq!(store.incr_by_float("f".into(), 1e3).unwrap(), "1000.3");
+        assert_eq!(store.incr_by_float("f".into(), -1000.3).unwrap(), "0");
+        assert_eq!(store.incr_by_float("new".into(), 1.5).unwrap(), "1.5");
+
+        let err = store.incr_by_float("f".into(), f64::INFINITY).unwrap_err();
+        assert!(matches!(err, StoreError::NanOrInfinity));
+
+        set(&mut store, "f", "inf");
+        let err = store.incr_by_float("f".into(), 1.0).unwrap_err();
+        assert!(matches!(err, StoreError::NanOrInfinity));
+
+        for value in ["nan", " 1", "1x", ""] {
+            set(&mut store, "f", value);
+            let err = store.incr_by_float("f".into(), 1.0).unwrap_err();
+            assert!(matches!(err, StoreError::NotFloat), "{value:?}");
+        }
     }
 }
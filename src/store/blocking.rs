@@ -0,0 +1,435 @@
+use bytes::Bytes;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+use super::{db::Db, Key, ListEnd, Store, StoreError, StreamReadOp, StreamsRead};
+
+/// What a blocked client does to the first list that has elements
+#[derive(Clone)]
+pub enum BlockingOp {
+    /// Pops up to `count` elements, for `BLPOP`, `BRPOP` and `BLMPOP`
+    Pop { end: ListEnd, count: usize },
+    /// Moves an element to another list, for `BLMOVE`
+    Move {
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    },
+}
+
+/// The key a blocked client was served from, along with the elements it got
+pub type Served = (Key, Vec<Bytes>);
+
+struct Waiter {
+    keys: Vec<Key>,
+    op: BlockingOp,
+    sender: oneshot::Sender<Result<Served, StoreError>>,
+}
+
+/// The clients blocked on list keys. Clients blocked on the same key are
+/// served in the order they blocked.
+#[derive(Default)]
+pub struct Waiters {
+    next_id: u64,
+    entries: HashMap<u64, Waiter>,
+    by_key: HashMap<Key, VecDeque<u64>>,
+    /// The keys that were pushed to since clients were last served
+    ready: Vec<Key>,
+}
+
+impl Waiters {
+    fn add(&mut self, waiter: Waiter) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        for key in &waiter.keys {
+            self.by_key.entry(key.clone()).or_default().push_back(id);
+        }
+
+        self.entries.insert(id, waiter);
+
+        id
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Waiter> {
+        let waiter = self.entries.remove(&id)?;
+
+        for key in &waiter.keys {
+            if let Some(queue) = self.by_key.get_mut(key) {
+                queue.retain(|&x| x != id);
+
+                if queue.is_empty() {
+                    self.by_key.remove(key);
+                }
+            }
+        }
+
+        Some(waiter)
+    }
+
+    /// Notes that `key` may now hold a list, if anyone is waiting for it
+    pub fn signal(&mut self, key: &[u8]) {
+        if self.by_key.contains_key(key) {
+            self.ready.push(Bytes::copy_from_slice(key));
+        }
+    }
+}
+
+/// The clients blocked on streams by `XREAD` and `XREADGROUP`. They are all
+/// woken when one of their streams is appended to, and read it themselves.
+#[derive(Default)]
+pub struct StreamWaiters {
+    next_id: u64,
+    entries: HashMap<u64, (Vec<Key>, oneshot::Sender<()>)>,
+    by_key: HashMap<Key, Vec<u64>>,
+}
+
+impl StreamWaiters {
+    fn add(&mut self, keys: Vec<Key>) -> (u64, oneshot::Receiver<()>) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        for key in &keys {
+            self.by_key.entry(key.clone()).or_default().push(id);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        self.entries.insert(id, (keys, sender));
+
+        (id, receiver)
+    }
+
+    fn remove(&mut self, id: u64) -> Option<oneshot::Sender<()>> {
+        let (keys, sender) = self.entries.remove(&id)?;
+
+        for key in &keys {
+            if let Some(ids) = self.by_key.get_mut(key) {
+                ids.retain(|&x| x != id);
+
+                if ids.is_empty() {
+                    self.by_key.remove(key);
+                }
+            }
+        }
+
+        Some(sender)
+    }
+
+    /// Wakes the clients reading the stream at `key`
+    pub fn wake(&mut self, key: &[u8]) {
+        for id in self.by_key.get(key).cloned().unwrap_or_default() {
+            if let Some(sender) = self.remove(id) {
+                let _ = sender.send(());
+            }
+        }
+    }
+}
+
+impl Db {
+    /// Applies `op` to the first of `keys` holding a list
+    fn pop_first(&mut self, keys: &[Key], op: &BlockingOp) -> Result<Option<Served>, StoreError> {
+        for key in keys {
+            if self.get_list(key)?.is_none() {
+                continue;
+            }
+
+            let elements = match op.clone() {
+                BlockingOp::Pop { end, count } => self.pop(key, end, count)?.unwrap_or_default(),
+                BlockingOp::Move {
+                    destination,
+                    from,
+                    to,
+                } => self
+                    .move_element(key, destination, from, to)?
+                    .into_iter()
+                    .collect(),
+            };
+
+            return Ok(Some((key.clone(), elements)));
+        }
+
+        Ok(None)
+    }
+
+    /// Serves the clients blocked on the keys pushed to since the last call.
+    /// Serving a client may push to another key, which is served in turn.
+    pub fn serve_blocked(&mut self) {
+        while !self.waiters.ready.is_empty() {
+            for key in std::mem::take(&mut self.waiters.ready) {
+                self.serve_key(&key);
+            }
+        }
+    }
+
+    fn serve_key(&mut self, key: &Key) {
+        while let Some(id) = self
+            .waiters
+            .by_key
+            .get(key)
+            .and_then(|x| x.front().copied())
+        {
+            if !matches!(self.get_list(key), Ok(Some(_))) {
+                return;
+            }
+
+            let waiter = self.waiters.remove(id).expect("Queued waiters exist");
+
+            // The client has gone away, so it mustn't take any elements
+            if waiter.sender.is_closed() {
+                continue;
+            }
+
+            let served = self
+                .pop_first(std::slice::from_ref(key), &waiter.op)
+                .map(|served| served.expect("The list was just checked"));
+
+            let _ = waiter.sender.send(served);
+        }
+    }
+}
+
+/// The outcome of a blocking list operation
+pub enum Blocking {
+    Ready(Served),
+    Blocked(BlockedClient),
+}
+
+/// A client waiting for one of its keys to be pushed to. It stops waiting
+/// when dropped.
+pub struct BlockedClient {
+    store: Store,
+    id: u64,
+    receiver: oneshot::Receiver<Result<Served, StoreError>>,
+}
+
+impl BlockedClient {
+    /// Waits until the client is served, or for `timeout` if given, in
+    /// which case `None` is returned
+    pub async fn wait(mut self, timeout: Option<Duration>) -> Result<Option<Served>, StoreError> {
+        let received = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, &mut self.receiver).await.ok(),
+            None => Some((&mut self.receiver).await),
+        };
+
+        match received {
+            Some(served) => served.ok().transpose(),
+            None => {
+                // The client may have been served while the timeout fired
+                self.unblock();
+                self.receiver.try_recv().ok().transpose()
+            }
+        }
+    }
+
+    fn unblock(&self) {
+        let mut db = self.store.lock().unwrap();
+
+        db.waiters.remove(self.id);
+    }
+}
+
+impl Drop for BlockedClient {
+    fn drop(&mut self) {
+        self.unblock();
+    }
+}
+
+/// The outcome of `XREAD` and `XREADGROUP`
+pub enum StreamBlocking {
+    Ready(StreamsRead),
+    Blocked(BlockedReader),
+}
+
+/// A client waiting for new entries in one of its streams. It stops waiting
+/// when dropped.
+pub struct BlockedReader {
+    store: Store,
+    id: u64,
+    receiver: oneshot::Receiver<()>,
+    keys: Vec<Key>,
+    op: StreamReadOp,
+    count: usize,
+}
+
+impl BlockedReader {
+    /// Waits until there are new entries to read, or for `timeout` if given,
+    /// in which case `None` is returned
+    pub async fn wait(
+        mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<StreamsRead>, StoreError> {
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+
+        loop {
+            let woken = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, &mut self.receiver)
+                    .await
+                    .is_ok(),
+                None => (&mut self.receiver).await.is_ok(),
+            };
+
+            if !woken {
+                return Ok(None);
+            }
+
+            let mut db = self.store.lock().unwrap();
+            let read = db.read_streams(&self.keys, &self.op, self.count)?;
+
+            if !read.is_empty() {
+                return Ok(Some(read));
+            }
+
+            // The new entries were deleted before the client got to them
+            (self.id, self.receiver) = db.stream_waiters.add(self.keys.clone());
+        }
+    }
+}
+
+impl Drop for BlockedReader {
+    fn drop(&mut self) {
+        let mut db = self.store.lock().unwrap();
+
+        db.stream_waiters.remove(self.id);
+    }
+}
+
+impl Store {
+    /// Applies `op` to the first of `keys` holding a list, or returns `None`
+    /// if there's none
+    pub fn pop_first(
+        &mut self,
+        keys: &[Key],
+        op: BlockingOp,
+    ) -> Result<Option<Served>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let served = db.pop_first(keys, &op)?;
+        db.serve_blocked();
+
+        Ok(served)
+    }
+
+    /// Like `pop_first`, but blocks the client on `keys` if none of them
+    /// holds a list
+    pub fn pop_or_block(&mut self, keys: Vec<Key>, op: BlockingOp) -> Result<Blocking, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        if let Some(served) = db.pop_first(&keys, &op)? {
+            db.serve_blocked();
+            return Ok(Blocking::Ready(served));
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let id = db.waiters.add(Waiter { keys, op, sender });
+
+        Ok(Blocking::Blocked(BlockedClient {
+            store: self.clone(),
+            id,
+            receiver,
+        }))
+    }
+
+    /// Reads `keys` as `op` says, for `XREAD` and `XREADGROUP`. If there's
+    /// nothing to read yet, the client blocks if `block` is given.
+    pub fn xread(
+        &self,
+        keys: Vec<Key>,
+        op: StreamReadOp,
+        count: usize,
+        block: bool,
+    ) -> Result<StreamBlocking, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let op = db.resolve_read_op(&keys, op)?;
+        let read = db.read_streams(&keys, &op, count)?;
+
+        if !read.is_empty() || !block {
+            return Ok(StreamBlocking::Ready(read));
+        }
+
+        let (id, receiver) = db.stream_waiters.add(keys.clone());
+
+        Ok(StreamBlocking::Blocked(BlockedReader {
+            store: self.clone(),
+            id,
+            receiver,
+            keys,
+            op,
+            count,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::NewStreamId;
+
+    fn block(store: &mut Store, key: &'static str) -> BlockedClient {
+        let op = BlockingOp::Pop {
+            end: ListEnd::Left,
+            count: 1,
+        };
+
+        match store.pop_or_block(vec![Bytes::from(key)], op).unwrap() {
+            Blocking::Blocked(client) => client,
+            Blocking::Ready(_) => panic!("The client should have blocked"),
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_are_served_in_order() {
+        let mut store = Store::default();
+
+        let first = block(&mut store, "list");
+        let second = block(&mut store, "list");
+
+        store
+            .push("list".into(), vec!["a".into()], ListEnd::Right, false)
+            .unwrap();
+
+        let timeout = Some(Duration::from_millis(10));
+
+        let served = first.wait(timeout).await.unwrap();
+        assert_eq!(served, Some(("list".into(), vec!["a".into()])));
+        assert_eq!(second.wait(timeout).await.unwrap(), None);
+
+        // The timed out client no longer takes elements
+        store
+            .push("list".into(), vec!["b".into()], ListEnd::Right, false)
+            .unwrap();
+        assert_eq!(store.llen(b"list").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn readers_are_woken_by_appends() {
+        let mut store = Store::default();
+        let add = |store: &mut Store| {
+            let fields = vec![("field".into(), "value".into())];
+            store
+                .xadd("stream".into(), NewStreamId::Auto, fields, false, None)
+                .unwrap()
+                .unwrap()
+        };
+
+        add(&mut store);
+
+        let op = StreamReadOp::After(vec![None]);
+
+        let reader = match store.xread(vec!["stream".into()], op, 10, true) {
+            Ok(StreamBlocking::Blocked(reader)) => reader,
+            _ => panic!("The client should have blocked"),
+        };
+
+        let id = add(&mut store);
+
+        let read = reader.wait(Some(Duration::from_millis(10))).await.unwrap();
+        let read = read.expect("The client should have been woken");
+        assert_eq!(read[0].0, Bytes::from("stream"));
+        assert_eq!(read[0].1[0].0, id);
+        assert!(store.lock().unwrap().stream_waiters.by_key.is_empty());
+    }
+}
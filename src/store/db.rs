@@ -0,0 +1,333 @@
+use std::collections::{BTreeSet, HashMap};
+
+use super::{
+    blocking::{StreamWaiters, Waiters},
+    now_millis,
+    sampled::SampledSet,
+    scan::{next_batch, scan_hash},
+    value::Value,
+    Key, StoreItem,
+};
+
+#[derive(Default, Clone)]
+pub struct Stats {
+    pub expired_keys: u64,
+    /// The number of hash fields that expired
+    pub expired_subkeys: u64,
+    pub expired_time_cap_reached_count: u64,
+}
+
+/// The keyspace, along with the bookkeeping needed to expire keys.
+///
+/// Keys must be added and removed and expiries changed through the methods
+/// here, so that the index of keys with an expiry stays in sync. Hashes
+/// given a field expiry have to be indexed with `index_field_expiry`.
+#[derive(Default)]
+pub struct Db {
+    items: HashMap<Key, StoreItem>,
+    /// The keys with an expiry, which the active expiry cycle samples from
+    expiring: SampledSet,
+    /// The hashes that may have fields with an expiry, which the active
+    /// expiry cycle samples from too
+    field_expiring: SampledSet,
+    /// The keys ordered by their scan hash, for `SCAN`
+    scan_index: BTreeSet<(u64, Key)>,
+    pub stats: Stats,
+    /// The clients blocked on list keys
+    pub waiters: Waiters,
+    /// The clients blocked on streams
+    pub stream_waiters: StreamWaiters,
+    rng_state: u64,
+}
+
+impl Db {
+    /// The item at `key`, unless it has expired, in which case it's deleted
+    pub fn get(&mut self, key: &[u8]) -> Option<&mut StoreItem> {
+        if self.items.get(key).is_some_and(|item| item.has_expired()) {
+            self.remove(key);
+            self.stats.expired_keys += 1;
+            return None;
+        }
+
+        self.items.get_mut(key)
+    }
+
+    pub fn insert(&mut self, key: Key, item: StoreItem) {
+        self.index_expiry(&key, item.expiry.is_some());
+
+        if !self.items.contains_key(&key) {
+            self.scan_index.insert((scan_hash(&key), key.clone()));
+        }
+
+        match &item.value {
+            Value::List(_) => self.waiters.signal(&key),
+            Value::Stream(_) => self.stream_waiters.wake(&key),
+            Value::Hash(hash) if hash.has_expiries() => self.field_expiring.set(&key, true),
+            _ => {}
+        }
+
+        self.items.insert(key, item);
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<StoreItem> {
+        let (key, item) = self.items.remove_entry(key)?;
+        self.index_expiry(&key, false);
+        self.field_expiring.set(&key, false);
+        self.scan_index.remove(&(scan_hash(&key), key));
+        Some(item)
+    }
+
+    /// Deletes `key` if it holds an empty collection, as Redis never keeps
+    /// those
+    pub fn remove_if_empty(&mut self, key: &[u8]) {
+        if self
+            .items
+            .get(key)
+            .is_some_and(|item| item.value.is_empty())
+        {
+            self.remove(key);
+        }
+    }
+
+    /// Every key that hasn't expired, along with its item
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &StoreItem)> {
+        self.items.iter().filter(|(_, item)| !item.has_expired())
+    }
+
+    /// Returns the next batch of about `count` keys starting at `cursor`,
+    /// along with the cursor to continue from
+    pub fn scan(&mut self, cursor: u64, count: usize) -> (u64, Vec<Key>) {
+        let from_cursor = self
+            .scan_index
+            .range((cursor, Key::new())..)
+            .map(|(hash, key)| (*hash, key.clone()));
+
+        next_batch(from_cursor, count)
+    }
+
+    pub fn set_expiry(&mut self, key: &[u8], expiry: Option<u128>) {
+        let Some((key, _)) = self.items.get_key_value(key) else {
+            return;
+        };
+
+        let key = key.clone();
+        let has_expiry = expiry.is_some();
+
+        if let Some(item) = self.items.get_mut(&key) {
+            item.expiry = expiry;
+        }
+
+        self.index_expiry(&key, has_expiry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn expires_len(&self) -> usize {
+        self.expiring.len()
+    }
+
+    fn index_expiry(&mut self, key: &Key, has_expiry: bool) {
+        self.expiring.set(key, has_expiry);
+    }
+
+    /// Lets the active expiry cycle know that the hash at `key` has fields
+    /// with an expiry
+    pub fn index_field_expiry(&mut self, key: &Key) {
+        self.field_expiring.set(key, true);
+    }
+
+    /// Samples up to `count` keys with an expiry and deletes the expired
+    /// ones. Returns the number of keys sampled and deleted.
+    pub fn expire_sample(&mut self, count: usize) -> (usize, usize) {
+        let sampled = count.min(self.expiring.len());
+        let mut expired = 0;
+
+        for _ in 0..sampled {
+            if self.expiring.is_empty() {
+                break;
+            }
+
+            let idx = self.random() as usize % self.expiring.len();
+            let Some((key, _)) = self.expiring.get_index(idx) else {
+                break;
+            };
+            let key = key.clone();
+
+            if self.items.get(&key).is_some_and(|item| item.has_expired()) {
+                self.remove(&key);
+                self.stats.expired_keys += 1;
+                expired += 1;
+            }
+        }
+
+        (sampled, expired)
+    }
+
+    /// Samples up to `count` hashes that may have fields with an expiry and
+    /// deletes their expired fields. Returns the number of hashes sampled
+    /// and the number that had expired fields.
+    pub fn expire_field_sample(&mut self, count: usize) -> (usize, usize) {
+        let sampled = count.min(self.field_expiring.len());
+        let mut expired = 0;
+
+        for _ in 0..sampled {
+            if self.field_expiring.is_empty() {
+                break;
+            }
+
+            let idx = self.random() as usize % self.field_expiring.len();
+            let Some((key, _)) = self.field_expiring.get_index(idx) else {
+                break;
+            };
+            let key = key.clone();
+
+            let Some(Value::Hash(hash)) = self.items.get_mut(&key).map(|item| &mut item.value)
+            else {
+                self.field_expiring.set(&key, false);
+                continue;
+            };
+
+            let removed = hash.remove_expired();
+            let (empty, has_expiries) = (hash.is_empty(), hash.has_expiries());
+
+            if removed > 0 {
+                self.stats.expired_subkeys += removed as u64;
+                expired += 1;
+            }
+
+            if empty {
+                self.remove(&key);
+            } else if !has_expiries {
+                self.field_expiring.set(&key, false);
+            }
+        }
+
+        (sampled, expired)
+    }
+
+    /// Picks `count` random positions below `len`, which are distinct
+    /// unless `count` is negative, in which case they may repeat
+    pub fn random_positions(&mut self, len: usize, count: i64) -> Vec<usize> {
+        let Ok(count) = usize::try_from(count) else {
+            // The count comes from the client, so it mustn't size the result
+            let mut picked = vec![];
+
+            if len > 0 {
+                for _ in 0..count.unsigned_abs() {
+                    picked.push(self.random() as usize % len);
+                }
+            }
+
+            return picked;
+        };
+
+        self.distinct_positions(len, count)
+    }
+
+    /// Picks up to `count` distinct random positions below `len`, in
+    /// O(count)
+    pub fn distinct_positions(&mut self, len: usize, count: usize) -> Vec<usize> {
+        // A partial Fisher-Yates shuffle of `0..len`, which only keeps track
+        // of the positions that were swapped
+        let mut swapped = HashMap::new();
+
+        (0..count.min(len))
+            .map(|i| {
+                let j = i + self.random() as usize % (len - i);
+                let picked = swapped.get(&j).copied().unwrap_or(j);
+                swapped.insert(j, swapped.get(&i).copied().unwrap_or(i));
+                picked
+            })
+            .collect()
+    }
+
+    /// A xorshift PRNG, which is plenty for sampling keys and picking
+    /// random members
+    pub fn random(&mut self) -> u64 {
+        if self.rng_state == 0 {
+            self.rng_state = now_millis() as u64 | 1;
+        }
+
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn item(expiry: Option<u128>) -> StoreItem {
+        StoreItem::new(Bytes::from("value").into(), expiry)
+    }
+
+    #[test]
+    fn expiry_index_follows_keys() {
+        let mut db = Db::default();
+        let later = now_millis() + 60_000;
+
+        db.insert("a".into(), item(Some(later)));
+        db.insert("b".into(), item(Some(later)));
+        db.insert("c".into(), item(None));
+        assert_eq!(db.expires_len(), 2);
+
+        db.set_expiry(b"a", None);
+        db.set_expiry(b"c", Some(later));
+        assert_eq!(db.expires_len(), 2);
+
+        db.remove(b"b");
+        db.insert("c".into(), item(None));
+        assert_eq!(db.expires_len(), 0);
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.scan_index.len(), 2);
+    }
+
+    #[test]
+    fn expired_keys_are_reclaimed() {
+        let mut db = Db::default();
+        let earlier = now_millis() - 1;
+
+        for i in 0..10 {
+            db.insert(format!("expired:{i}").into(), item(Some(earlier)));
+        }
+        db.insert("live".into(), item(None));
+
+        assert!(db.get(b"expired:0").is_none());
+        assert_eq!(db.stats.expired_keys, 1);
+
+        while db.expires_len() > 0 {
+            db.expire_sample(20);
+        }
+
+        assert_eq!(db.len(), 1);
+        assert_eq!(db.stats.expired_keys, 10);
+    }
+
+    #[test]
+    fn random_positions() {
+        let mut db = Db::default();
+
+        let mut distinct = db.random_positions(10, 20);
+        distinct.sort();
+        assert_eq!(distinct, (0..10).collect::<Vec<_>>());
+
+        let mut some = db.distinct_positions(1000, 50);
+        some.sort();
+        some.dedup();
+        assert_eq!(some.len(), 50);
+        assert!(some.iter().all(|&idx| idx < 1000));
+
+        let repeated = db.random_positions(10, -30);
+        assert_eq!(repeated.len(), 30);
+        assert!(repeated.iter().all(|&idx| idx < 10));
+        assert!(db.random_positions(0, -5).is_empty());
+        assert!(db.random_positions(0, 5).is_empty());
+    }
+}
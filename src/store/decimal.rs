@@ -0,0 +1,199 @@
+use std::cmp::Ordering;
+
+/// The number of decimal places `INCRBYFLOAT` results are rounded to, like
+/// Redis' `%.17Lf`
+const DECIMAL_PLACES: i32 = 17;
+
+/// An exact decimal number, `digits * 10^exponent`, with the most
+/// significant digit first
+struct Decimal {
+    negative: bool,
+    digits: Vec<u8>,
+    exponent: i32,
+}
+
+impl Decimal {
+    /// The decimal that `x`, which has to be finite, is the closest float to,
+    /// with the fewest digits
+    fn new(x: f64) -> Self {
+        let shortest = format!("{:e}", x.abs());
+        let (mantissa, exponent) = shortest.split_once('e').expect("Floats have an exponent");
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+        Self {
+            negative: x.is_sign_negative(),
+            digits: int.bytes().chain(frac.bytes()).map(|x| x - b'0').collect(),
+            exponent: exponent.parse::<i32>().expect("Exponents are integers") - frac.len() as i32,
+        }
+    }
+
+    /// The digits of the number written with a lower `exponent`
+    fn scaled(&self, exponent: i32) -> Vec<u8> {
+        let mut digits = self.digits.clone();
+        digits.resize(digits.len() + (self.exponent - exponent) as usize, 0);
+        digits
+    }
+
+    fn add(&self, other: &Decimal) -> Decimal {
+        let exponent = self.exponent.min(other.exponent);
+        let (a, b) = (self.scaled(exponent), other.scaled(exponent));
+
+        let (negative, digits) = if self.negative == other.negative {
+            (self.negative, add_digits(&a, &b))
+        } else if compare_digits(&a, &b) == Ordering::Less {
+            (other.negative, sub_digits(&b, &a))
+        } else {
+            (self.negative, sub_digits(&a, &b))
+        };
+
+        Decimal {
+            negative,
+            digits,
+            exponent,
+        }
+    }
+
+    /// Rounds the number half away from zero so that it has at most
+    /// `places` decimal places
+    fn round(&mut self, places: i32) {
+        let dropped = -places - self.exponent;
+
+        if dropped <= 0 {
+            return;
+        }
+
+        let kept = self.digits.len().saturating_sub(dropped as usize);
+        let round_up = self.digits.get(kept).is_some_and(|&x| x >= 5);
+
+        self.digits.truncate(kept);
+        self.exponent += dropped;
+
+        if round_up {
+            self.digits = add_digits(&self.digits, &[1]);
+        }
+    }
+}
+
+/// Adds two numbers given as digits, most significant first
+fn add_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut sum = vec![];
+    let mut carry = 0;
+    let (mut a, mut b) = (a.iter().rev(), b.iter().rev());
+
+    loop {
+        let (x, y) = (a.next(), b.next());
+
+        if x.is_none() && y.is_none() {
+            break;
+        }
+
+        let digit = x.unwrap_or(&0) + y.unwrap_or(&0) + carry;
+        sum.push(digit % 10);
+        carry = digit / 10;
+    }
+
+    if carry > 0 {
+        sum.push(carry);
+    }
+
+    sum.reverse();
+    sum
+}
+
+/// Subtracts `b` from `a`, which mustn't be smaller
+fn sub_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut difference = vec![];
+    let mut borrow = 0;
+    let mut b = b.iter().rev();
+
+    for &x in a.iter().rev() {
+        let y = b.next().unwrap_or(&0) + borrow;
+
+        borrow = (x < y) as u8;
+        difference.push(x + borrow * 10 - y);
+    }
+
+    difference.reverse();
+    difference
+}
+
+fn compare_digits(a: &[u8], b: &[u8]) -> Ordering {
+    let strip = |digits: &[u8]| {
+        let start = digits.iter().position(|&x| x != 0).unwrap_or(digits.len());
+        digits[start..].to_vec()
+    };
+
+    let (a, b) = (strip(a), strip(b));
+
+    a.len().cmp(&b.len()).then_with(|| a.cmp(&b))
+}
+
+/// Formats `a + b` the way Redis stores the result of `INCRBYFLOAT` and
+/// `HINCRBYFLOAT`. Redis adds long doubles, which are precise enough for the
+/// sum of two short decimals to be printed as expected, e.g. `0.1 + 0.2` is
+/// `0.3` rather than `0.30000000000000004`. We get the same by adding the
+/// decimals the floats were parsed from exactly, then rounding to 17 decimal
+/// places and dropping trailing zeros, like Redis' `%.17Lf`. Sums with more
+/// digits than a long double holds, e.g. `1e20 + 1`, keep digits that Redis
+/// loses. The sum has to be finite.
+pub fn format_sum(a: f64, b: f64) -> String {
+    let mut sum = Decimal::new(a).add(&Decimal::new(b));
+    sum.round(DECIMAL_PLACES);
+
+    // Write the digits with a decimal point, padding with zeros as needed
+    let (int, frac) = if sum.exponent >= 0 {
+        let mut int = sum.digits.clone();
+        int.resize(int.len() + sum.exponent as usize, 0);
+        (int, vec![])
+    } else {
+        let frac_len = -sum.exponent as usize;
+        let mut digits = vec![0; (frac_len + 1).saturating_sub(sum.digits.len())];
+        digits.extend(&sum.digits);
+        let frac = digits.split_off(digits.len() - frac_len);
+        (digits, frac)
+    };
+
+    let to_str = |digits: &[u8]| {
+        digits
+            .iter()
+            .map(|x| (x + b'0') as char)
+            .collect::<String>()
+    };
+
+    let int = to_str(&int);
+    let int = int.trim_start_matches('0');
+    let frac = to_str(&frac);
+    let frac = frac.trim_end_matches('0');
+
+    let mut number = if int.is_empty() { "0" } else { int }.to_owned();
+
+    if !frac.is_empty() {
+        number = format!("{}.{}", number, frac);
+    }
+
+    if sum.negative && number != "0" {
+        number.insert(0, '-');
+    }
+
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_are_formatted_like_redis() {
+        assert_eq!(format_sum(0.1, 0.2), "0.3");
+        assert_eq!(format_sum(10.5, 0.1), "10.6");
+        assert_eq!(format_sum(3.0, 1.5), "4.5");
+        assert_eq!(format_sum(5.0e3, 2.0e2), "5200");
+        assert_eq!(format_sum(1.0, -1.0), "0");
+        assert_eq!(format_sum(-0.1, -0.2), "-0.3");
+        assert_eq!(format_sum(0.3, -0.5), "-0.2");
+        assert_eq!(format_sum(1.0e20, 1.0), "100000000000000000001");
+        assert_eq!(format_sum(1.0e-18, 0.0), "0");
+        assert_eq!(format_sum(5.0e-18, 0.0), "0.00000000000000001");
+        assert_eq!(format_sum(0.99999999999999999, 0.0), "1");
+    }
+}
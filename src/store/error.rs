@@ -0,0 +1,62 @@
+/// Errors from operations on the values in the store. Like `CommandError`,
+/// the message starts with the error code the client sees.
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+
+    #[error("ERR value is not a valid float")]
+    NotFloat,
+
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+
+    #[error("ERR increment would produce NaN or Infinity")]
+    NanOrInfinity,
+
+    #[error("ERR offset is out of range")]
+    OffsetOutOfRange,
+
+    #[error("ERR string exceeds maximum allowed size (proto-max-bulk-len)")]
+    StringTooLong,
+
+    #[error("ERR hash value is not an integer")]
+    HashNotInteger,
+
+    #[error("ERR hash value is not a float")]
+    HashNotFloat,
+
+    #[error("ERR resulting score is not a number (NaN)")]
+    ScoreNaN,
+
+    #[error("ERR no such key")]
+    NoSuchKey,
+
+    #[error("ERR index out of range")]
+    IndexOutOfRange,
+
+    #[error("ERR The ID specified in XADD is equal or smaller than the target stream top item")]
+    StreamIdTooSmall,
+
+    #[error("ERR The ID specified in XADD must be greater than 0-0")]
+    StreamIdZero,
+
+    #[error("BUSYGROUP Consumer Group name already exists")]
+    BusyGroup,
+
+    #[error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")]
+    GroupKeyMissing,
+
+    #[error("NOGROUP No such consumer group '{group}' for key name '{key}'")]
+    NoGroup { key: String, group: String },
+
+    #[error("NOGROUP No such key '{key}' or consumer group '{group}'{context}")]
+    NoKeyOrGroup {
+        key: String,
+        group: String,
+        context: &'static str,
+    },
+}
@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+use super::Store;
+
+/// How often the active expiry cycle runs, like Redis' default `hz` of 10
+const CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The number of keys sampled each time the store is locked
+const KEYS_PER_LOOP: usize = 20;
+
+/// Sampling continues as long as more than this share of the sampled keys
+/// turn out to be expired
+const ACCEPTABLE_STALE_PERCENT: usize = 10;
+
+/// The longest a single cycle may run, a quarter of the interval
+const CYCLE_TIME_LIMIT: Duration = Duration::from_millis(25);
+
+impl Store {
+    /// Reclaims expired keys and hash fields that are never accessed again,
+    /// using Redis' adaptive sampling: keys with an expiry are sampled at
+    /// random, and sampling continues while many of them turn out to be
+    /// expired. Hashes with field expiries are sampled alongside.
+    pub fn active_expire_cycle(&self) {
+        let start = Instant::now();
+
+        loop {
+            // The lock is released between rounds so clients aren't stalled
+            let mut db = self.0.lock().unwrap();
+
+            let (keys_sampled, keys_expired) = db.expire_sample(KEYS_PER_LOOP);
+            let (hashes_sampled, hashes_expired) = db.expire_field_sample(KEYS_PER_LOOP);
+
+            let sampled = keys_sampled + hashes_sampled;
+            let expired = keys_expired + hashes_expired;
+
+            if sampled == 0 || expired * 100 <= sampled * ACCEPTABLE_STALE_PERCENT {
+                return;
+            }
+
+            if start.elapsed() > CYCLE_TIME_LIMIT {
+                db.stats.expired_time_cap_reached_count += 1;
+                return;
+            }
+        }
+    }
+
+    /// Runs the active expiry cycle forever
+    pub async fn run_active_expiry(self) {
+        let mut interval = tokio::time::interval(CYCLE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            self.active_expire_cycle();
+        }
+    }
+}
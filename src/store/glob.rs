@@ -0,0 +1,148 @@
+/// Matches `string` against a glob-style `pattern` the way Redis'
+/// `stringmatchlen` does: `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to
+/// escape special characters.
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    let (mut p, mut s) = (0, 0);
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                // Consecutive stars match the same as a single one
+                while pattern.get(p + 1) == Some(&b'*') {
+                    p += 1;
+                }
+
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+
+                return (s..=string.len())
+                    .any(|start| glob_match(&pattern[p + 1..], &string[start..], nocase));
+            }
+            b'?' => {
+                if s >= string.len() {
+                    return false;
+                }
+                s += 1;
+            }
+            b'[' => {
+                let Some(&c) = string.get(s) else {
+                    return false;
+                };
+
+                p += 1;
+                let negate = pattern.get(p) == Some(&b'^');
+                if negate {
+                    p += 1;
+                }
+
+                let mut matched = false;
+
+                // An unterminated class simply runs to the end of the pattern
+                while p < pattern.len() && pattern[p] != b']' {
+                    if pattern[p] == b'\\' && p + 1 < pattern.len() {
+                        p += 1;
+                        matched |= eq(pattern[p], c);
+                    } else if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() {
+                        let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+
+                        let in_range = |c: u8| c >= start && c <= end;
+
+                        matched |= if nocase {
+                            in_range(c.to_ascii_lowercase()) || in_range(c.to_ascii_uppercase())
+                        } else {
+                            in_range(c)
+                        };
+
+                        p += 2;
+                    } else {
+                        matched |= eq(pattern[p], c);
+                    }
+
+                    p += 1;
+                }
+
+                if matched == negate {
+                    return false;
+                }
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+
+                if s >= string.len() || !eq(pattern[p], string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                if s >= string.len() || !eq(c, string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+
+        p += 1;
+    }
+
+    s == string.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, string: &str) -> bool {
+        glob_match(pattern.as_bytes(), string.as_bytes(), false)
+    }
+
+    #[test]
+    fn match_wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h*llo", "heeeello"));
+        assert!(matches("h**o", "hello"));
+        assert!(!matches("h*llo", "hello!"));
+        assert!(matches("user:*:name", "user:42:name"));
+    }
+
+    #[test]
+    fn match_classes() {
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-b]llo", "hbllo"));
+        assert!(matches("h[b-a]llo", "hallo"));
+        assert!(!matches("h[a-b]llo", "hcllo"));
+        assert!(matches("h[\\]]llo", "h]llo"));
+    }
+
+    #[test]
+    fn match_escapes() {
+        assert!(matches("h\\*llo", "h*llo"));
+        assert!(!matches("h\\*llo", "hello"));
+        assert!(matches("h\\?llo", "h?llo"));
+    }
+
+    #[test]
+    fn match_nocase() {
+        assert!(glob_match(b"H[A-Z]LLO", b"hello", true));
+        assert!(!glob_match(b"H[A-Z]LLO", b"hello", false));
+    }
+}
@@ -0,0 +1,549 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+
+use super::{
+    db::Db,
+    format_sum, glob_match, is_past, now_millis, parse_f64, parse_i64,
+    sampled::SampledMap,
+    scan::{next_batch, scan_hash},
+    ExpireCondition, Store, StoreError, StoreItem, Value,
+};
+
+/// A hash, whose fields may each have an expiry. Expired fields are only
+/// dropped by `remove_expired`, which `Db::get_hash` calls on every access.
+#[derive(Debug, Default)]
+pub struct Hash {
+    fields: SampledMap<Bytes>,
+    /// The expiry of each field that has one, in milliseconds since the
+    /// UNIX epoch
+    expiries: HashMap<Bytes, u128>,
+}
+
+impl Hash {
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        self.fields.get(field)
+    }
+
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    /// Sets `field` to `value`, clearing any expiry it had. Returns the old
+    /// value.
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> Option<Bytes> {
+        self.expiries.remove(&field);
+        self.fields.insert(field, value)
+    }
+
+    /// Sets `field` to `value`, keeping any expiry it had
+    fn update(&mut self, field: Bytes, value: Bytes) {
+        self.fields.insert(field, value);
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> Option<Bytes> {
+        self.expiries.remove(field);
+        self.fields.remove(field)
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Bytes)> {
+        self.fields.iter()
+    }
+
+    /// The field at position `idx`, where the positions go from 0 to the
+    /// length
+    pub fn get_index(&self, idx: usize) -> Option<(&Bytes, &Bytes)> {
+        self.fields.get_index(idx)
+    }
+
+    /// The expiry of `field`, or `None` if the field doesn't exist
+    fn expiry(&self, field: &[u8]) -> Option<Option<u128>> {
+        self.fields
+            .contains_key(field)
+            .then(|| self.expiries.get(field).copied())
+    }
+
+    fn set_expiry(&mut self, field: &Bytes, expiry: Option<u128>) {
+        match expiry {
+            Some(expiry) => self.expiries.insert(field.clone(), expiry),
+            None => self.expiries.remove(field),
+        };
+    }
+
+    pub fn has_expiries(&self) -> bool {
+        !self.expiries.is_empty()
+    }
+
+    /// Deletes the expired fields, returning how many there were
+    pub fn remove_expired(&mut self) -> usize {
+        let expired: Vec<_> = self
+            .expiries
+            .iter()
+            .filter(|(_, expiry)| is_past(**expiry))
+            .map(|(field, _)| field.clone())
+            .collect();
+
+        for field in &expired {
+            self.remove(field);
+        }
+
+        expired.len()
+    }
+}
+
+impl Db {
+    /// The hash at `key`, failing if the key holds another type. Expired
+    /// fields are deleted first, along with the hash if they were the last.
+    pub fn get_hash(&mut self, key: &[u8]) -> Result<Option<&mut Hash>, StoreError> {
+        let (expired, empty) = match self.get(key).map(|item| &mut item.value) {
+            Some(Value::Hash(hash)) => (hash.remove_expired(), hash.is_empty()),
+            Some(_) => return Err(StoreError::WrongType),
+            None => return Ok(None),
+        };
+
+        self.stats.expired_subkeys += expired as u64;
+
+        if expired > 0 && empty {
+            self.remove(key);
+            return Ok(None);
+        }
+
+        match self.get(key).map(|item| &mut item.value) {
+            Some(Value::Hash(hash)) => Ok(Some(hash)),
+            _ => unreachable!("The hash was just checked"),
+        }
+    }
+
+    /// The hash at `key`, which is created if needed
+    fn get_or_create_hash(&mut self, key: Bytes) -> Result<&mut Hash, StoreError> {
+        if self.get_hash(&key)?.is_none() {
+            self.insert(
+                key.clone(),
+                StoreItem::new(Value::Hash(Hash::default()), None),
+            );
+        }
+
+        Ok(self.get_hash(&key)?.expect("The hash was just created"))
+    }
+}
+
+impl Store {
+    /// Sets the fields in `pairs`, creating the hash if needed. Returns the
+    /// number of fields that were added.
+    pub fn hset(&mut self, key: Bytes, pairs: Vec<(Bytes, Bytes)>) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let hash = db.get_or_create_hash(key)?;
+
+        Ok(pairs
+            .into_iter()
+            .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+            .count())
+    }
+
+    /// Sets `field` unless it already exists. Returns whether it was set.
+    pub fn hsetnx(&mut self, key: Bytes, field: Bytes, value: Bytes) -> Result<bool, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let hash = db.get_or_create_hash(key)?;
+
+        if hash.contains_key(&field) {
+            return Ok(false);
+        }
+
+        hash.insert(field, value);
+
+        Ok(true)
+    }
+
+    pub fn hget(&self, key: &[u8], field: &[u8]) -> Result<Option<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.get_hash(key)?.and_then(|hash| hash.get(field).cloned()))
+    }
+
+    pub fn hmget(&self, key: &[u8], fields: &[Bytes]) -> Result<Vec<Option<Bytes>>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(hash) = db.get_hash(key)? else {
+            return Ok(vec![None; fields.len()]);
+        };
+
+        Ok(fields
+            .iter()
+            .map(|field| hash.get(field).cloned())
+            .collect())
+    }
+
+    /// Deletes `fields`, and the hash along with its last field. Returns the
+    /// number of fields deleted.
+    pub fn hdel(&mut self, key: &[u8], fields: &[Bytes]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(hash) = db.get_hash(key)? else {
+            return Ok(0);
+        };
+
+        let deleted = fields
+            .iter()
+            .filter(|field| hash.remove(field).is_some())
+            .count();
+
+        db.remove_if_empty(key);
+
+        Ok(deleted)
+    }
+
+    pub fn hgetall(&self, key: &[u8]) -> Result<Vec<(Bytes, Bytes)>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.get_hash(key)?.map_or(vec![], |hash| {
+            hash.iter()
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect()
+        }))
+    }
+
+    pub fn hlen(&self, key: &[u8]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.get_hash(key)?.map_or(0, |hash| hash.len()))
+    }
+
+    /// Adds `delta` to the integer in `field`, treating a missing field as 0
+    pub fn hincr_by(&mut self, key: Bytes, field: Bytes, delta: i64) -> Result<i64, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let hash = db.get_or_create_hash(key)?;
+
+        let current = match hash.get(&field) {
+            Some(value) => parse_i64(value).ok_or(StoreError::HashNotInteger)?,
+            None => 0,
+        };
+
+        let value = current.checked_add(delta).ok_or(StoreError::Overflow)?;
+
+        hash.update(field, value.to_string().into());
+
+        Ok(value)
+    }
+
+    /// Adds `delta` to the float in `field`, treating a missing field as 0.
+    /// Returns the new value as stored.
+    pub fn hincr_by_float(
+        &mut self,
+        key: Bytes,
+        field: Bytes,
+        delta: f64,
+    ) -> Result<Bytes, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let hash = db.get_or_create_hash(key)?;
+
+        let current = match hash.get(&field) {
+            Some(value) => parse_f64(value).ok_or(StoreError::HashNotFloat)?,
+            None => 0.0,
+        };
+
+        if !(current + delta).is_finite() {
+            return Err(StoreError::NanOrInfinity);
+        }
+
+        let value: Bytes = format_sum(current, delta).into();
+
+        hash.update(field, value.clone());
+
+        Ok(value)
+    }
+
+    /// Sets the expiry of `fields` to `deadline`, provided `condition` holds.
+    /// Returns a code for each field like `HEXPIRE`: -2 if it doesn't exist,
+    /// 0 if the condition doesn't hold, 1 if the expiry was set and 2 if the
+    /// field was deleted because the deadline has passed.
+    pub fn hexpire(
+        &mut self,
+        key: &[u8],
+        fields: &[Bytes],
+        deadline: u128,
+        condition: ExpireCondition,
+    ) -> Result<Vec<i64>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(hash) = db.get_hash(key)? else {
+            return Ok(vec![-2; fields.len()]);
+        };
+
+        let expired = deadline <= now_millis();
+
+        let codes = fields
+            .iter()
+            .map(|field| match hash.expiry(field) {
+                None => -2,
+                Some(current) if !condition.allows(current, deadline) => 0,
+                Some(_) if expired => {
+                    hash.remove(field);
+                    2
+                }
+                Some(_) => {
+                    hash.set_expiry(field, Some(deadline));
+                    1
+                }
+            })
+            .collect();
+
+        let has_expiries = hash.has_expiries();
+
+        if has_expiries {
+            let key = Bytes::copy_from_slice(key);
+            db.index_field_expiry(&key);
+        }
+
+        db.remove_if_empty(key);
+
+        Ok(codes)
+    }
+
+    /// The expiry of each of `fields`, which is `None` if the field doesn't
+    /// exist
+    pub fn hexpiry(
+        &self,
+        key: &[u8],
+        fields: &[Bytes],
+    ) -> Result<Vec<Option<Option<u128>>>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(hash) = db.get_hash(key)? else {
+            return Ok(vec![None; fields.len()]);
+        };
+
+        Ok(fields.iter().map(|field| hash.expiry(field)).collect())
+    }
+
+    /// Removes the expiry of `fields`. Returns a code for each field like
+    /// `HPERSIST`: -2 if it doesn't exist, -1 if it has no expiry and 1 if
+    /// the expiry was removed.
+    pub fn hpersist(&mut self, key: &[u8], fields: &[Bytes]) -> Result<Vec<i64>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(hash) = db.get_hash(key)? else {
+            return Ok(vec![-2; fields.len()]);
+        };
+
+        Ok(fields
+            .iter()
+            .map(|field| match hash.expiry(field) {
+                None => -2,
+                Some(None) => -1,
+                Some(Some(_)) => {
+                    hash.set_expiry(field, None);
+                    1
+                }
+            })
+            .collect())
+    }
+
+    /// Picks `count` random fields along with their values. A negative
+    /// `count` may pick the same field more than once.
+    pub fn hrandfield(
+        &mut self,
+        key: &[u8],
+        count: i64,
+    ) -> Result<Vec<(Bytes, Bytes)>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let len = db.get_hash(key)?.map_or(0, |hash| hash.len());
+        let positions = db.random_positions(len, count);
+
+        let Some(hash) = db.get_hash(key)? else {
+            return Ok(vec![]);
+        };
+
+        Ok(positions
+            .into_iter()
+            .filter_map(|idx| hash.get_index(idx))
+            .map(|(field, value)| (field.clone(), value.clone()))
+            .collect())
+    }
+
+    /// Returns the next batch of about `count` fields starting at `cursor`,
+    /// along with the cursor to continue from
+    pub fn hscan(
+        &self,
+        key: &[u8],
+        cursor: u64,
+        count: usize,
+        pattern: Option<&[u8]>,
+    ) -> Result<(u64, Vec<(Bytes, Bytes)>), StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(hash) = db.get_hash(key)? else {
+            return Ok((0, vec![]));
+        };
+
+        let mut sorted: Vec<_> = hash
+            .iter()
+            .map(|(field, value)| (scan_hash(field), (field.clone(), value.clone())))
+            .filter(|(hash, _)| *hash >= cursor)
+            .collect();
+        sorted.sort_unstable_by_key(|(hash, _)| *hash);
+
+        let (cursor, pairs) = next_batch(sorted.into_iter(), count);
+
+        let pairs = pairs
+            .into_iter()
+            .filter(|(field, _)| pattern.is_none_or(|pattern| glob_match(pattern, field, false)))
+            .collect();
+
+        Ok((cursor, pairs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_fields_are_removed() {
+        let mut hash = Hash::default();
+        let field = Bytes::from("a");
+
+        hash.insert(field.clone(), "1".into());
+        hash.insert("b".into(), "2".into());
+        hash.set_expiry(&field, Some(now_millis() - 1));
+        assert!(hash.has_expiries());
+
+        assert_eq!(hash.remove_expired(), 1);
+        assert_eq!(hash.len(), 1);
+        assert!(!hash.has_expiries());
+
+        // Setting a field again clears its expiry
+        hash.set_expiry(&"b".into(), Some(now_millis() - 1));
+        hash.insert("b".into(), "3".into());
+        assert_eq!(hash.remove_expired(), 0);
+    }
+
+    fn hash(fields: &[(&'static str, &'static str)]) -> Store {
+        let mut store = Store::default();
+        let pairs = fields.iter().map(|&(f, v)| (f.into(), v.into())).collect();
+        store.hset("h".into(), pairs).unwrap();
+        store
+    }
+
+    #[test]
+    fn hincr_by_errors() {
+        let mut store = hash(&[("n", "5"), ("s", "abc"), ("f", "1.5")]);
+
+        assert_eq!(store.hincr_by("h".into(), "n".into(), -7).unwrap(), -2);
+        assert_eq!(store.hincr_by("h".into(), "new".into(), 3).unwrap(), 3);
+
+        for field in ["s", "f"] {
+            let err = store.hincr_by("h".into(), field.into(), 1).unwrap_err();
+            assert!(matches!(err, StoreError::HashNotInteger), "{field}");
+        }
+
+        store
+            .hset(
+                "h".into(),
+                vec![("max".into(), i64::MAX.to_string().into())],
+            )
+            .unwrap();
+        let err = store.hincr_by("h".into(), "max".into(), 1).unwrap_err();
+        assert!(matches!(err, StoreError::Overflow));
+
+        // A failed increment leaves the field alone
+        assert_eq!(
+            store.hget(b"h", b"max").unwrap(),
+            Some(i64::MAX.to_string().into())
+        );
+    }
+
+    #[test]
+    fn hsetnx_keeps_existing_fields() {
+        let mut store = hash(&[("a", "1")]);
+
+        assert!(!store.hsetnx("h".into(), "a".into(), "2".into()).unwrap());
+        assert!(store.hsetnx("h".into(), "b".into(), "3".into()).unwrap());
+        assert_eq!(
+            store.hmget(b"h", &["a".into(), "b".into()]).unwrap(),
+            [Some("1".into()), Some("3".into())]
+        );
+
+        store
+            .set("s".into(), "x".into(), None, false, None, false)
+            .unwrap();
+        let err = store
+            .hsetnx("s".into(), "a".into(), "1".into())
+            .unwrap_err();
+        assert!(matches!(err, StoreError::WrongType));
+    }
+
+    #[test]
+    fn hrandfield_counts() {
+        let mut store = hash(&[("a", "1"), ("b", "2"), ("c", "3")]);
+
+        // A positive count picks distinct fields, at most all of them
+        let mut fields = store.hrandfield(b"h", 10).unwrap();
+        fields.sort();
+        assert_eq!(
+            fields,
+            [
+                ("a".into(), "1".into()),
+                ("b".into(), "2".into()),
+                ("c".into(), "3".into())
+            ]
+        );
+        assert_eq!(store.hrandfield(b"h", 2).unwrap().len(), 2);
+
+        // A negative count picks exactly that many, possibly repeated
+        let fields = store.hrandfield(b"h", -10).unwrap();
+        assert_eq!(fields.len(), 10);
+        assert!(fields
+            .iter()
+            .all(|(field, value)| store.hget(b"h", field).unwrap().as_ref() == Some(value)));
+
+        assert!(store.hrandfield(b"h", 0).unwrap().is_empty());
+        assert!(store.hrandfield(b"missing", -5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn hscan_visits_every_field() {
+        let fields: Vec<_> = (0..50).map(|i| format!("field:{i}")).collect();
+        let mut store = Store::default();
+        let pairs = fields
+            .iter()
+            .map(|field| (field.clone().into(), "v".into()))
+            .collect();
+        store.hset("h".into(), pairs).unwrap();
+
+        let mut seen = vec![];
+        let mut cursor = 0;
+
+        loop {
+            let (next, pairs) = store.hscan(b"h", cursor, 7, None).unwrap();
+            seen.extend(pairs.into_iter().map(|(field, _)| field));
+
+            if next == 0 {
+                break;
+            }
+
+            cursor = next;
+        }
+
+        seen.sort();
+        let mut expected: Vec<Bytes> = fields.into_iter().map(Bytes::from).collect();
+        expected.sort();
+        assert_eq!(seen, expected);
+
+        // Only the fields matching the pattern come back
+        let (_, pairs) = store.hscan(b"h", 0, 100, Some(b"field:1?")).unwrap();
+        assert_eq!(pairs.len(), 10);
+        assert_eq!(store.hscan(b"missing", 0, 10, None).unwrap(), (0, vec![]));
+    }
+}
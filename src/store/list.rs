@@ -0,0 +1,515 @@
+use bytes::Bytes;
+use std::collections::VecDeque;
+
+use super::{db::Db, Store, StoreError, StoreItem, Value};
+
+/// One of the two ends of a list
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// Resolves the inclusive range `start..=stop` of a sequence of `len`
+/// elements, where negative indexes count from the end. Returns `None` if
+/// the range is empty.
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+
+    if start > stop || start >= len {
+        return None;
+    }
+
+    Some((start as usize, stop as usize))
+}
+
+/// Resolves an index into a sequence of `len` elements, where negative
+/// indexes count from the end
+fn normalize_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+
+    (index >= 0 && index < len as i64).then_some(index as usize)
+}
+
+impl Db {
+    /// The list at `key`, failing if the key holds another type
+    pub fn get_list(&mut self, key: &[u8]) -> Result<Option<&mut VecDeque<Bytes>>, StoreError> {
+        self.get(key)
+            .map(|item| match &mut item.value {
+                Value::List(list) => Ok(list),
+                _ => Err(StoreError::WrongType),
+            })
+            .transpose()
+    }
+
+    /// Pushes `values` onto the list at `key`, creating the list if needed.
+    /// Returns the new length.
+    pub fn push(
+        &mut self,
+        key: Bytes,
+        values: Vec<Bytes>,
+        end: ListEnd,
+    ) -> Result<usize, StoreError> {
+        if self.get_list(&key)?.is_none() {
+            self.insert(
+                key.clone(),
+                StoreItem::new(Value::List(VecDeque::new()), None),
+            );
+        }
+
+        let list = self.get_list(&key)?.expect("The list was just created");
+
+        for value in values {
+            match end {
+                ListEnd::Left => list.push_front(value),
+                ListEnd::Right => list.push_back(value),
+            }
+        }
+
+        let len = list.len();
+        self.waiters.signal(&key);
+
+        Ok(len)
+    }
+
+    /// Pops up to `count` elements off the list at `key`, or returns `None`
+    /// if there's no list
+    pub fn pop(
+        &mut self,
+        key: &[u8],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Bytes>>, StoreError> {
+        let Some(list) = self.get_list(key)? else {
+            return Ok(None);
+        };
+
+        let count = count.min(list.len());
+
+        let popped = match end {
+            ListEnd::Left => list.drain(..count).collect(),
+            ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+        };
+
+        self.remove_if_empty(key);
+
+        Ok(Some(popped))
+    }
+
+    /// Pops an element off the `from` end of the list at `source` and pushes
+    /// it onto the `to` end of the list at `destination`
+    pub fn move_element(
+        &mut self,
+        source: &[u8],
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Bytes>, StoreError> {
+        if self.get_list(source)?.is_none() {
+            return Ok(None);
+        }
+
+        // Check the destination's type before anything is popped
+        self.get_list(&destination)?;
+
+        let Some(element) = self.pop(source, from, 1)?.and_then(|mut x| x.pop()) else {
+            return Ok(None);
+        };
+
+        self.push(destination, vec![element.clone()], to)?;
+
+        Ok(Some(element))
+    }
+}
+
+impl Store {
+    /// Pushes `values` onto the list at `key`. With `only_existing`, nothing
+    /// is pushed unless the list exists. Returns the new length.
+    pub fn push(
+        &mut self,
+        key: Bytes,
+        values: Vec<Bytes>,
+        end: ListEnd,
+        only_existing: bool,
+    ) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        if only_existing && db.get_list(&key)?.is_none() {
+            return Ok(0);
+        }
+
+        let len = db.push(key, values, end)?;
+        db.serve_blocked();
+
+        Ok(len)
+    }
+
+    pub fn pop(
+        &mut self,
+        key: &[u8],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Bytes>>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        db.pop(key, end, count)
+    }
+
+    pub fn lrange(&self, key: &[u8], start: i64, stop: i64) -> Result<Vec<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(list) = db.get_list(key)? else {
+            return Ok(vec![]);
+        };
+
+        let Some((start, stop)) = normalize_range(start, stop, list.len()) else {
+            return Ok(vec![]);
+        };
+
+        Ok(list.range(start..=stop).cloned().collect())
+    }
+
+    pub fn llen(&self, key: &[u8]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.get_list(key)?.map_or(0, |list| list.len()))
+    }
+
+    pub fn lindex(&self, key: &[u8], index: i64) -> Result<Option<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(list) = db.get_list(key)? else {
+            return Ok(None);
+        };
+
+        Ok(normalize_index(index, list.len()).map(|index| list[index].clone()))
+    }
+
+    pub fn lset(&mut self, key: &[u8], index: i64, value: Bytes) -> Result<(), StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let list = db.get_list(key)?.ok_or(StoreError::NoSuchKey)?;
+        let index = normalize_index(index, list.len()).ok_or(StoreError::IndexOutOfRange)?;
+
+        list[index] = value;
+
+        Ok(())
+    }
+
+    /// Removes the first `count` occurrences of `element`, starting from the
+    /// tail if `count` is negative, or every occurrence if it's 0. Returns
+    /// the number of elements removed.
+    pub fn lrem(&mut self, key: &[u8], count: i64, element: &[u8]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(list) = db.get_list(key)? else {
+            return Ok(0);
+        };
+
+        let limit = match count {
+            0 => usize::MAX,
+            _ => count.unsigned_abs() as usize,
+        };
+
+        // From the tail, the occurrences before the last `limit` are kept
+        let mut skipped = match count {
+            0.. => 0,
+            _ => list
+                .iter()
+                .filter(|x| *x == element)
+                .count()
+                .saturating_sub(limit),
+        };
+        let mut removed = 0;
+
+        list.retain(|x| {
+            if x != element || removed == limit {
+                return true;
+            }
+
+            if skipped > 0 {
+                skipped -= 1;
+                return true;
+            }
+
+            removed += 1;
+            false
+        });
+
+        db.remove_if_empty(key);
+
+        Ok(removed)
+    }
+
+    pub fn ltrim(&mut self, key: &[u8], start: i64, stop: i64) -> Result<(), StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(list) = db.get_list(key)? else {
+            return Ok(());
+        };
+
+        match normalize_range(start, stop, list.len()) {
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            }
+            None => list.clear(),
+        }
+
+        db.remove_if_empty(key);
+
+        Ok(())
+    }
+
+    /// Inserts `element` before or after `pivot`. Returns the new length, 0
+    /// if there's no list and -1 if the pivot wasn't found.
+    pub fn linsert(
+        &mut self,
+        key: &[u8],
+        before: bool,
+        pivot: &[u8],
+        element: Bytes,
+    ) -> Result<i64, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(list) = db.get_list(key)? else {
+            return Ok(0);
+        };
+
+        let Some(index) = list.iter().position(|x| x == pivot) else {
+            return Ok(-1);
+        };
+
+        let index = if before { index } else { index + 1 };
+        list.insert(index, element);
+
+        Ok(list.len() as i64)
+    }
+
+    /// The indexes of the matches of `element`, starting from the `rank`th
+    /// match (counting from the tail if negative). At most `count` matches
+    /// are returned, with 0 meaning all of them, and at most `maxlen`
+    /// elements are compared, with 0 meaning the whole list.
+    pub fn lpos(
+        &self,
+        key: &[u8],
+        element: &[u8],
+        rank: i64,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(list) = db.get_list(key)? else {
+            return Ok(vec![]);
+        };
+
+        let maxlen = if maxlen == 0 { list.len() } else { maxlen };
+        let count = if count == 0 { usize::MAX } else { count };
+        let skip = rank.unsigned_abs() as usize - 1;
+
+        let indexes: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+            Box::new(0..list.len())
+        } else {
+            Box::new((0..list.len()).rev())
+        };
+
+        Ok(indexes
+            .take(maxlen)
+            .filter(|&i| list[i] == element)
+            .skip(skip)
+            .take(count)
+            .collect())
+    }
+
+    pub fn lmove(
+        &mut self,
+        source: &[u8],
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let element = db.move_element(source, destination, from, to)?;
+        db.serve_blocked();
+
+        Ok(element)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_ranges() {
+        assert_eq!(normalize_range(0, -1, 5), Some((0, 4)));
+        assert_eq!(normalize_range(-3, -2, 5), Some((2, 3)));
+        assert_eq!(normalize_range(-100, 100, 5), Some((0, 4)));
+        assert_eq!(normalize_range(3, 1, 5), None);
+        assert_eq!(normalize_range(5, 10, 5), None);
+        assert_eq!(normalize_range(0, -1, 0), None);
+        assert_eq!(normalize_range(0, -6, 5), None);
+    }
+
+    /// A store with a list at `l` holding `elements`
+    fn list(elements: &str) -> Store {
+        let mut store = Store::default();
+        let elements = elements.split(' ').map(|x| Bytes::from(x.to_owned()));
+        store
+            .push("l".into(), elements.collect(), ListEnd::Right, false)
+            .unwrap();
+        store
+    }
+
+    fn elements(store: &Store) -> String {
+        let elements = store.lrange(b"l", 0, -1).unwrap();
+        let elements: Vec<_> = elements
+            .iter()
+            .map(|x| String::from_utf8_lossy(x))
+            .collect();
+        elements.join(" ")
+    }
+
+    #[test]
+    fn lrem_counts() {
+        let cases = [
+            (0, 4, "b c d"),
+            (2, 2, "b c a d a"),
+            (-2, 2, "a b a c d"),
+            (-10, 4, "b c d"),
+            (1, 1, "b a c a d a"),
+        ];
+
+        for (count, removed, left) in cases {
+            let mut store = list("a b a c a d a");
+            assert_eq!(store.lrem(b"l", count, b"a").unwrap(), removed, "{count}");
+            assert_eq!(elements(&store), left, "{count}");
+        }
+
+        let mut store = list("a a");
+        assert_eq!(store.lrem(b"l", 0, b"x").unwrap(), 0);
+        assert_eq!(store.lrem(b"l", -1, b"a").unwrap(), 1);
+        assert_eq!(store.lrem(b"l", 0, b"a").unwrap(), 1);
+        assert_eq!(store.key_type(b"l"), None);
+    }
+
+    #[test]
+    fn lpos_options() {
+        let store = list("a b c a b c a");
+        let lpos = |rank, count, maxlen| store.lpos(b"l", b"a", rank, count, maxlen).unwrap();
+
+        assert_eq!(lpos(1, 1, 0), [0]);
+        assert_eq!(lpos(2, 1, 0), [3]);
+        assert_eq!(lpos(-1, 1, 0), [6]);
+        assert_eq!(lpos(-2, 0, 0), [3, 0]);
+        assert_eq!(lpos(1, 0, 0), [0, 3, 6]);
+        assert_eq!(lpos(1, 2, 0), [0, 3]);
+        assert!(lpos(4, 0, 0).is_empty());
+
+        // MAXLEN bounds how many elements are compared, from either end
+        assert_eq!(lpos(1, 0, 4), [0, 3]);
+        assert_eq!(lpos(-1, 0, 3), [6]);
+        assert!(lpos(2, 0, 3).is_empty());
+
+        assert!(store.lpos(b"missing", b"a", 1, 0, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn linsert_around_pivot() {
+        let mut store = list("a b c");
+
+        assert_eq!(store.linsert(b"l", true, b"b", "x".into()).unwrap(), 4);
+        assert_eq!(store.linsert(b"l", false, b"c", "y".into()).unwrap(), 5);
+        assert_eq!(elements(&store), "a x b c y");
+
+        assert_eq!(store.linsert(b"l", true, b"z", "x".into()).unwrap(), -1);
+        assert_eq!(
+            store.linsert(b"missing", true, b"a", "x".into()).unwrap(),
+            0
+        );
+        assert_eq!(store.key_type(b"missing"), None);
+    }
+
+    #[test]
+    fn ltrim_ranges() {
+        let cases = [
+            (1, -2, "b c d"),
+            (-2, -1, "d e"),
+            (0, 100, "a b c d e"),
+            (-100, 0, "a"),
+        ];
+
+        for (start, stop, left) in cases {
+            let mut store = list("a b c d e");
+            store.ltrim(b"l", start, stop).unwrap();
+            assert_eq!(elements(&store), left, "{start} {stop}");
+        }
+
+        // An empty range deletes the list
+        let mut store = list("a b c");
+        store.ltrim(b"l", 2, 1).unwrap();
+        assert_eq!(store.key_type(b"l"), None);
+    }
+
+    #[test]
+    fn lset_errors() {
+        let mut store = list("a b c");
+
+        store.lset(b"l", -1, "z".into()).unwrap();
+        assert_eq!(elements(&store), "a b z");
+
+        let err = store.lset(b"l", 3, "x".into()).unwrap_err();
+        assert!(matches!(err, StoreError::IndexOutOfRange));
+        let err = store.lset(b"l", -4, "x".into()).unwrap_err();
+        assert!(matches!(err, StoreError::IndexOutOfRange));
+
+        let err = store.lset(b"missing", 0, "x".into()).unwrap_err();
+        assert!(matches!(err, StoreError::NoSuchKey));
+    }
+
+    #[test]
+    fn lmove_onto_the_same_list() {
+        let mut store = list("a b c");
+
+        // Moving from one end to the other rotates the list
+        let moved = store.lmove(b"l", "l".into(), ListEnd::Left, ListEnd::Right);
+        assert_eq!(moved.unwrap(), Some("a".into()));
+        assert_eq!(elements(&store), "b c a");
+
+        let moved = store.lmove(b"l", "l".into(), ListEnd::Right, ListEnd::Right);
+        assert_eq!(moved.unwrap(), Some("a".into()));
+        assert_eq!(elements(&store), "b c a");
+
+        let mut store = list("a");
+        let moved = store.lmove(b"l", "l".into(), ListEnd::Right, ListEnd::Left);
+        assert_eq!(moved.unwrap(), Some("a".into()));
+        assert_eq!(elements(&store), "a");
+    }
+
+    #[test]
+    fn emptied_lists_are_deleted() {
+        let mut store = list("a b");
+
+        assert_eq!(
+            store.pop(b"l", ListEnd::Left, 5).unwrap(),
+            Some(vec!["a".into(), "b".into()])
+        );
+        assert_eq!(store.key_type(b"l"), None);
+
+        // Moving the last element away deletes the source
+        let mut store = list("a");
+        let moved = store.lmove(b"l", "other".into(), ListEnd::Left, ListEnd::Left);
+        assert_eq!(moved.unwrap(), Some("a".into()));
+        assert_eq!(store.key_type(b"l"), None);
+        assert_eq!(store.llen(b"other").unwrap(), 1);
+    }
+}
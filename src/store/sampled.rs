@@ -0,0 +1,170 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// A map whose entries can also be looked up by position, so that random
+/// entries can be picked in O(1). Removing an entry moves the last one into
+/// its place.
+#[derive(Debug, Clone)]
+pub struct SampledMap<V> {
+    entries: Vec<(Bytes, V)>,
+    /// The position of each key in `entries`
+    positions: HashMap<Bytes, usize>,
+}
+
+impl<V> Default for SampledMap<V> {
+    fn default() -> Self {
+        Self {
+            entries: vec![],
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl<V> SampledMap<V> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        self.positions.get(key).map(|&idx| &self.entries[idx].1)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// Sets `key` to `value`, returning the old value
+    pub fn insert(&mut self, key: Bytes, value: V) -> Option<V> {
+        if let Some(&idx) = self.positions.get(&key) {
+            return Some(std::mem::replace(&mut self.entries[idx].1, value));
+        }
+
+        self.positions.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+
+        None
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let idx = self.positions.remove(key)?;
+        let (_, value) = self.entries.swap_remove(idx);
+
+        if let Some((moved, _)) = self.entries.get(idx) {
+            self.positions.insert(moved.clone(), idx);
+        }
+
+        Some(value)
+    }
+
+    /// The entry at position `idx`, where the positions go from 0 to the
+    /// length
+    pub fn get_index(&self, idx: usize) -> Option<(&Bytes, &V)> {
+        self.entries.get(idx).map(|(key, value)| (key, value))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Bytes> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    /// Keeps the entries for which `f` holds
+    pub fn retain(&mut self, mut f: impl FnMut(&Bytes, &V) -> bool) {
+        self.entries.retain(|(key, value)| f(key, value));
+
+        self.positions = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(idx, (key, _))| (key.clone(), idx))
+            .collect();
+    }
+}
+
+/// A set whose members can be picked at random in O(1)
+pub type SampledSet = SampledMap<()>;
+
+impl SampledSet {
+    /// Adds `key`, returning whether it's new
+    pub fn add(&mut self, key: Bytes) -> bool {
+        self.insert(key, ()).is_none()
+    }
+
+    /// Adds `key` if `present`, or removes it otherwise
+    pub fn set(&mut self, key: &Bytes, present: bool) {
+        if present {
+            if !self.contains_key(key) {
+                self.add(key.clone());
+            }
+        } else {
+            self.remove(key);
+        }
+    }
+}
+
+impl FromIterator<Bytes> for SampledSet {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(iter: I) -> Self {
+        iter.into_iter().map(|key| (key, ())).collect()
+    }
+}
+
+impl<V> FromIterator<(Bytes, V)> for SampledMap<V> {
+    fn from_iter<I: IntoIterator<Item = (Bytes, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+
+        map
+    }
+}
+
+impl<V> IntoIterator for SampledMap<V> {
+    type Item = (Bytes, V);
+    type IntoIter = std::vec::IntoIter<(Bytes, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_follow_removals() {
+        let mut map: SampledMap<u32> = (0..5).map(|i| (format!("k{i}").into(), i)).collect();
+
+        assert_eq!(map.insert("k1".into(), 10), Some(1));
+        assert_eq!(map.remove(b"k0"), Some(0));
+        assert_eq!(map.remove(b"k0"), None);
+
+        // The last entry took the place of the removed one
+        assert_eq!(map.get_index(0), Some((&Bytes::from("k4"), &4)));
+        assert_eq!(map.get(b"k4"), Some(&4));
+
+        map.retain(|_, value| *value > 2);
+        let mut entries: Vec<_> = map
+            .iter()
+            .map(|(key, value)| (key.clone(), *value))
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [("k1".into(), 10), ("k3".into(), 3), ("k4".into(), 4)]
+        );
+
+        for idx in 0..map.len() {
+            let (key, value) = map.get_index(idx).unwrap();
+            assert_eq!(map.get(key), Some(value));
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// The position of `key` in a scan. Cursors are positions in this hash
+/// space, so they stay valid while keys are added and removed.
+pub fn scan_hash(key: &[u8]) -> u64 {
+    // The default hasher is keyed with zeros, so positions are stable
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Takes roughly `count` items off `sorted`, an iterator over `(hash, item)`
+/// pairs in hash order that starts at the cursor. Items with equal hashes
+/// always end up in the same batch, so every item that's present for the
+/// whole iteration is returned. Returns the next cursor, which is 0 once the
+/// iteration is done, and the batch.
+pub fn next_batch<T>(sorted: impl Iterator<Item = (u64, T)>, count: usize) -> (u64, Vec<T>) {
+    let mut batch = vec![];
+    let mut last_hash = None;
+
+    for (hash, item) in sorted {
+        if batch.len() >= count && last_hash != Some(hash) {
+            return (hash, batch);
+        }
+
+        last_hash = Some(hash);
+        batch.push(item);
+    }
+
+    (0, batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_cover_everything() {
+        let mut items: Vec<_> = (0..100u64).map(|x| (x / 3 + 1, x)).collect();
+        items.sort();
+
+        let mut cursor = 0;
+        let mut seen = vec![];
+
+        loop {
+            let from_cursor = items.iter().copied().filter(|(hash, _)| *hash >= cursor);
+            let (next, batch) = next_batch(from_cursor, 10);
+
+            seen.extend(batch);
+            cursor = next;
+
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        seen.sort();
+        assert_eq!(seen, (0..100).collect::<Vec<_>>());
+    }
+}
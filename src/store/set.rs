@@ -0,0 +1,450 @@
+use bytes::Bytes;
+
+use super::{
+    db::Db,
+    glob_match,
+    sampled::SampledSet,
+    scan::{next_batch, scan_hash},
+    Store, StoreError, StoreItem, Value,
+};
+
+/// The set operations of `SINTER`, `SUNION` and `SDIFF`
+#[derive(Clone, Copy)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+impl Db {
+    /// The set at `key`, failing if the key holds another type
+    pub fn get_set(&mut self, key: &[u8]) -> Result<Option<&mut SampledSet>, StoreError> {
+        self.get(key)
+            .map(|item| match &mut item.value {
+                Value::Set(set) => Ok(set),
+                _ => Err(StoreError::WrongType),
+            })
+            .transpose()
+    }
+
+    /// The set at `key`, which is created if needed
+    fn get_or_create_set(&mut self, key: Bytes) -> Result<&mut SampledSet, StoreError> {
+        if self.get_set(&key)?.is_none() {
+            self.insert(
+                key.clone(),
+                StoreItem::new(Value::Set(SampledSet::default()), None),
+            );
+        }
+
+        Ok(self.get_set(&key)?.expect("The set was just created"))
+    }
+
+    /// Applies `op` to the sets at `keys`, where missing keys are empty sets
+    fn combine_sets(&mut self, op: SetOp, keys: &[Bytes]) -> Result<SampledSet, StoreError> {
+        // Every key is type checked, even once the result is known
+        let mut sets = vec![];
+
+        for key in keys {
+            sets.push(self.get_set(key)?.cloned().unwrap_or_default());
+        }
+
+        let mut sets = sets.into_iter();
+        let mut result = sets.next().unwrap_or_default();
+
+        for set in sets {
+            match op {
+                SetOp::Inter => result.retain(|member, _| set.contains_key(member)),
+                SetOp::Union => {
+                    for (member, _) in set {
+                        result.add(member);
+                    }
+                }
+                SetOp::Diff => result.retain(|member, _| !set.contains_key(member)),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl Store {
+    /// Adds `members` to the set at `key`, creating it if needed. Returns
+    /// the number of members that were added.
+    pub fn sadd(&mut self, key: Bytes, members: Vec<Bytes>) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let set = db.get_or_create_set(key)?;
+
+        Ok(members
+            .into_iter()
+            .filter(|member| set.add(member.clone()))
+            .count())
+    }
+
+    /// Removes `members`, and the set along with its last member. Returns
+    /// the number of members removed.
+    pub fn srem(&mut self, key: &[u8], members: &[Bytes]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(set) = db.get_set(key)? else {
+            return Ok(0);
+        };
+
+        let removed = members
+            .iter()
+            .filter(|member| set.remove(member).is_some())
+            .count();
+
+        db.remove_if_empty(key);
+
+        Ok(removed)
+    }
+
+    pub fn smembers(&self, key: &[u8]) -> Result<Vec<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db
+            .get_set(key)?
+            .map_or(vec![], |set| set.keys().cloned().collect()))
+    }
+
+    /// Whether each of `members` is in the set at `key`
+    pub fn smismember(&self, key: &[u8], members: &[Bytes]) -> Result<Vec<bool>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(set) = db.get_set(key)? else {
+            return Ok(vec![false; members.len()]);
+        };
+
+        Ok(members
+            .iter()
+            .map(|member| set.contains_key(member))
+            .collect())
+    }
+
+    pub fn scard(&self, key: &[u8]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.get_set(key)?.map_or(0, |set| set.len()))
+    }
+
+    /// Removes and returns up to `count` random members
+    pub fn spop(&mut self, key: &[u8], count: usize) -> Result<Vec<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(set) = db.get_set(key)? else {
+            return Ok(vec![]);
+        };
+
+        let len = set.len();
+
+        if count >= len {
+            return Ok(match db.remove(key).map(|item| item.value) {
+                Some(Value::Set(set)) => set.into_iter().map(|(member, _)| member).collect(),
+                _ => vec![],
+            });
+        }
+
+        let positions = db.distinct_positions(len, count);
+
+        let Some(set) = db.get_set(key)? else {
+            return Ok(vec![]);
+        };
+
+        let popped: Vec<_> = positions
+            .into_iter()
+            .filter_map(|idx| set.get_index(idx).map(|(member, _)| member.clone()))
+            .collect();
+
+        for member in &popped {
+            set.remove(member);
+        }
+
+        Ok(popped)
+    }
+
+    /// Picks `count` random members. A negative `count` may pick the same
+    /// member more than once.
+    pub fn srandmember(&mut self, key: &[u8], count: i64) -> Result<Vec<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let len = db.get_set(key)?.map_or(0, |set| set.len());
+        let positions = db.random_positions(len, count);
+
+        let Some(set) = db.get_set(key)? else {
+            return Ok(vec![]);
+        };
+
+        Ok(positions
+            .into_iter()
+            .filter_map(|idx| set.get_index(idx).map(|(member, _)| member.clone()))
+            .collect())
+    }
+
+    pub fn combine_sets(&self, op: SetOp, keys: &[Bytes]) -> Result<Vec<Bytes>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db
+            .combine_sets(op, keys)?
+            .into_iter()
+            .map(|(member, _)| member)
+            .collect())
+    }
+
+    /// Stores the result of `op` at `destination`, replacing whatever was
+    /// there, or deletes it if the result is empty. Returns the size of the
+    /// result.
+    pub fn combine_sets_store(
+        &mut self,
+        op: SetOp,
+        destination: Bytes,
+        keys: &[Bytes],
+    ) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let result = db.combine_sets(op, keys)?;
+        let len = result.len();
+
+        if result.is_empty() {
+            db.remove(&destination);
+        } else {
+            db.insert(destination, StoreItem::new(Value::Set(result), None));
+        }
+
+        Ok(len)
+    }
+
+    /// The size of the intersection of the sets at `keys`, counting no
+    /// further than `limit` unless it's 0
+    pub fn sintercard(&self, keys: &[Bytes], limit: usize) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let len = db.combine_sets(SetOp::Inter, keys)?.len();
+
+        Ok(if limit == 0 { len } else { len.min(limit) })
+    }
+
+    /// Moves `member` from the set at `source` to the one at `destination`.
+    /// Returns whether it was moved.
+    pub fn smove(
+        &mut self,
+        source: &[u8],
+        destination: Bytes,
+        member: Bytes,
+    ) -> Result<bool, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        // Check the destination's type before anything is removed
+        db.get_set(&destination)?;
+
+        let Some(set) = db.get_set(source)? else {
+            return Ok(false);
+        };
+
+        if !set.contains_key(&member) {
+            return Ok(false);
+        }
+
+        if source == destination {
+            return Ok(true);
+        }
+
+        set.remove(&member);
+        db.remove_if_empty(source);
+
+        db.get_or_create_set(destination)?.add(member);
+
+        Ok(true)
+    }
+
+    /// Returns the next batch of about `count` members starting at `cursor`,
+    /// along with the cursor to continue from
+    pub fn sscan(
+        &self,
+        key: &[u8],
+        cursor: u64,
+        count: usize,
+        pattern: Option<&[u8]>,
+    ) -> Result<(u64, Vec<Bytes>), StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(set) = db.get_set(key)? else {
+            return Ok((0, vec![]));
+        };
+
+        let mut sorted: Vec<_> = set
+            .keys()
+            .map(|member| (scan_hash(member), member.clone()))
+            .filter(|(hash, _)| *hash >= cursor)
+            .collect();
+        sorted.sort_unstable_by_key(|(hash, _)| *hash);
+
+        let (cursor, members) = next_batch(sorted.into_iter(), count);
+
+        let members = members
+            .into_iter()
+            .filter(|member| pattern.is_none_or(|pattern| glob_match(pattern, member, false)))
+            .collect();
+
+        Ok((cursor, members))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(set: SampledSet) -> Vec<Bytes> {
+        let mut members: Vec<_> = set.into_iter().map(|(member, _)| member).collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn set_algebra() {
+        let mut db = Db::default();
+        let set = |members: &[&'static str]| {
+            let set = members.iter().map(|x| Bytes::from(*x)).collect();
+            StoreItem::new(Value::Set(set), None)
+        };
+
+        db.insert("a".into(), set(&["1", "2", "3"]));
+        db.insert("b".into(), set(&["2", "3", "4"]));
+        db.insert("s".into(), StoreItem::new(Bytes::from("x").into(), None));
+
+        let keys = ["a".into(), "b".into()];
+        assert_eq!(
+            members(db.combine_sets(SetOp::Inter, &keys).unwrap()),
+            ["2", "3"]
+        );
+        assert_eq!(members(db.combine_sets(SetOp::Diff, &keys).unwrap()), ["1"]);
+        assert_eq!(db.combine_sets(SetOp::Union, &keys).unwrap().len(), 4);
+
+        // A missing key empties an intersection, but a string is still an error
+        let keys = ["missing".into(), "s".into()];
+        assert!(db.combine_sets(SetOp::Inter, &keys).is_err());
+    }
+
+    /// A store with a set at `s` holding `members`
+    fn set_store(members: &[&'static str]) -> Store {
+        let mut store = Store::default();
+        let members = members.iter().map(|x| Bytes::from(*x)).collect();
+        store.sadd("s".into(), members).unwrap();
+        store
+    }
+
+    #[test]
+    fn spop_counts() {
+        let mut store = set_store(&["a", "b", "c", "d"]);
+
+        let mut popped = store.spop(b"s", 3).unwrap();
+        assert_eq!(popped.len(), 3);
+        assert_eq!(store.scard(b"s").unwrap(), 1);
+
+        // A count past the size, however large, pops everything
+        popped.extend(store.spop(b"s", usize::MAX).unwrap());
+        popped.sort();
+        assert_eq!(popped, ["a", "b", "c", "d"]);
+        assert_eq!(store.key_type(b"s"), None);
+
+        assert!(store.spop(b"s", 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn srandmember_counts() {
+        let mut store = set_store(&["a", "b", "c"]);
+
+        let mut picked = store.srandmember(b"s", 10).unwrap();
+        picked.sort();
+        assert_eq!(picked, ["a", "b", "c"]);
+
+        let mut picked = store.srandmember(b"s", 2).unwrap();
+        picked.sort();
+        picked.dedup();
+        assert_eq!(picked.len(), 2);
+
+        // A negative count may repeat members, but picks exactly that many
+        let picked = store.srandmember(b"s", -20).unwrap();
+        assert_eq!(picked.len(), 20);
+        assert!(store
+            .smismember(b"s", &picked)
+            .unwrap()
+            .into_iter()
+            .all(|x| x));
+
+        assert_eq!(store.scard(b"s").unwrap(), 3);
+        assert!(store.srandmember(b"missing", -3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn smove_across_types() {
+        let mut store = set_store(&["a", "b"]);
+        store
+            .set("str".into(), "x".into(), None, false, None, false)
+            .unwrap();
+
+        // A destination of the wrong type leaves the source alone
+        let err = store.smove(b"s", "str".into(), "a".into()).unwrap_err();
+        assert!(matches!(err, StoreError::WrongType));
+        assert_eq!(store.scard(b"s").unwrap(), 2);
+
+        let err = store.smove(b"str", "s".into(), "x".into()).unwrap_err();
+        assert!(matches!(err, StoreError::WrongType));
+
+        assert!(!store.smove(b"s", "t".into(), "z".into()).unwrap());
+        assert!(store.smove(b"s", "s".into(), "a".into()).unwrap());
+        assert!(store.smove(b"s", "t".into(), "a".into()).unwrap());
+        assert!(store.smove(b"s", "t".into(), "b".into()).unwrap());
+        assert_eq!(store.key_type(b"s"), None);
+        assert_eq!(store.scard(b"t").unwrap(), 2);
+    }
+
+    #[test]
+    fn sintercard_limit() {
+        let mut store = set_store(&["a", "b", "c", "d"]);
+        let members = ["b", "c", "d", "e"].map(Bytes::from).to_vec();
+        store.sadd("t".into(), members).unwrap();
+
+        let keys = ["s".into(), "t".into()];
+        assert_eq!(store.sintercard(&keys, 0).unwrap(), 3);
+        assert_eq!(store.sintercard(&keys, 2).unwrap(), 2);
+        assert_eq!(store.sintercard(&keys, 10).unwrap(), 3);
+        assert_eq!(
+            store
+                .sintercard(&["s".into(), "missing".into()], 0)
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn sscan_visits_every_member() {
+        let members: Vec<_> = (0..50).map(|i| Bytes::from(format!("m:{i}"))).collect();
+        let mut store = Store::default();
+        store.sadd("s".into(), members.clone()).unwrap();
+
+        let mut seen = vec![];
+        let mut cursor = 0;
+
+        loop {
+            let (next, batch) = store.sscan(b"s", cursor, 7, None).unwrap();
+            seen.extend(batch);
+
+            if next == 0 {
+                break;
+            }
+
+            cursor = next;
+        }
+
+        seen.sort();
+        let mut expected = members;
+        expected.sort();
+        assert_eq!(seen, expected);
+
+        // Only the members matching the pattern come back
+        let (_, batch) = store.sscan(b"s", 0, 100, Some(b"m:1?")).unwrap();
+        assert_eq!(batch.len(), 10);
+        assert_eq!(store.sscan(b"missing", 0, 10, None).unwrap(), (0, vec![]));
+    }
+}
@@ -0,0 +1,454 @@
+mod group;
+
+use bytes::Bytes;
+use std::{collections::BTreeMap, fmt, ops::RangeInclusive};
+
+pub use group::{Claim, ClaimOptions, PendingRange};
+
+use super::{db::Db, now_millis, Key, Store, StoreError, StoreItem, Value};
+use group::ConsumerGroup;
+
+/// The ID of a stream entry, `<ms>-<seq>`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parses `<ms>-<seq>`, or just `<ms>` with `default_seq` as the
+    /// sequence number
+    pub fn parse(token: &[u8], default_seq: u64) -> Option<StreamId> {
+        let token = std::str::from_utf8(token).ok()?;
+
+        let (ms, seq) = match token.split_once('-') {
+            Some((ms, seq)) => (ms.parse().ok()?, seq.parse().ok()?),
+            None => (token.parse().ok()?, default_seq),
+        };
+
+        Some(StreamId { ms, seq })
+    }
+
+    /// The ID right after this one, if there is one
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+
+    /// The ID right before this one, if there is one
+    pub fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_sub(1)?,
+                seq: u64::MAX,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The ID given to `XADD`, which may leave parts of it to be generated
+#[derive(Clone, Copy)]
+pub enum NewStreamId {
+    /// `*`
+    Auto,
+    /// `<ms>-*`
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+/// How `XADD` and `XTRIM` trim a stream
+#[derive(Clone, Copy)]
+pub enum StreamTrim {
+    /// Keep at most this many entries
+    MaxLen(usize),
+    /// Evict the entries with a lower ID than this
+    MinId(StreamId),
+}
+
+/// An entry of a stream, along with its fields and values
+pub type StreamEntry = (StreamId, Vec<(Bytes, Bytes)>);
+
+/// An entry read by `XREAD` or `XREADGROUP`. Pending entries that were
+/// deleted since they were delivered have no fields.
+pub type ReadEntry = (StreamId, Option<Vec<(Bytes, Bytes)>>);
+
+/// The entries read from each stream by `XREAD` and `XREADGROUP`
+pub type StreamsRead = Vec<(Key, Vec<ReadEntry>)>;
+
+/// How `XREAD` and `XREADGROUP` read streams
+#[derive(Clone)]
+pub enum StreamReadOp {
+    /// Reads the entries after the given ID of each stream, where `None` is
+    /// `$`, the last ID of the stream when the client started reading
+    After(Vec<Option<StreamId>>),
+    /// Reads as `consumer` of `group`, either the entries never delivered to
+    /// the group, given as `>` or `None`, or else the consumer's pending
+    /// entries after the given ID
+    Group {
+        group: Bytes,
+        consumer: Bytes,
+        after: Vec<Option<StreamId>>,
+        no_ack: bool,
+    },
+}
+
+/// An append-only log of entries ordered by their IDs
+#[derive(Debug, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(Bytes, Bytes)>>,
+    last_id: StreamId,
+    /// The greatest ID deleted by `XDEL`, which tells whether the number of
+    /// entries a group has read can still be counted
+    max_deleted_id: StreamId,
+    /// The number of entries ever added
+    entries_added: u64,
+    groups: BTreeMap<Bytes, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// The ID of the first entry, or `0-0` if there's none
+    fn first_id(&self) -> StreamId {
+        self.entries
+            .first_key_value()
+            .map_or(StreamId::MIN, |(id, _)| *id)
+    }
+
+    /// Resolves the ID of a new entry, which has to be greater than every
+    /// ID the stream has had
+    fn new_id(&self, id: NewStreamId) -> Result<StreamId, StoreError> {
+        let last = self.last_id;
+
+        let id = match id {
+            NewStreamId::Auto => {
+                let ms = (now_millis() as u64).max(last.ms);
+
+                if ms == last.ms {
+                    last.next().ok_or(StoreError::StreamIdTooSmall)?
+                } else {
+                    StreamId { ms, seq: 0 }
+                }
+            }
+            NewStreamId::AutoSeq(ms) if ms == last.ms => StreamId {
+                ms,
+                seq: last
+                    .seq
+                    .checked_add(1)
+                    .ok_or(StoreError::StreamIdTooSmall)?,
+            },
+            // 0-0 is never a valid ID, so the first sequence number is 1
+            NewStreamId::AutoSeq(0) => StreamId { ms: 0, seq: 1 },
+            NewStreamId::AutoSeq(ms) => StreamId { ms, seq: 0 },
+            NewStreamId::Explicit(id) => id,
+        };
+
+        if id == StreamId::MIN {
+            return Err(StoreError::StreamIdZero);
+        }
+
+        if id <= last {
+            return Err(StoreError::StreamIdTooSmall);
+        }
+
+        Ok(id)
+    }
+
+    /// Appends an entry, returning its ID
+    pub fn add(
+        &mut self,
+        id: NewStreamId,
+        fields: Vec<(Bytes, Bytes)>,
+    ) -> Result<StreamId, StoreError> {
+        let id = self.new_id(id)?;
+
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+
+        Ok(id)
+    }
+
+    /// Evicts the oldest entries as `trim` says, but no more than `limit`.
+    /// Returns the number of entries evicted.
+    pub fn trim(&mut self, trim: StreamTrim, limit: Option<usize>) -> usize {
+        let excess = match trim {
+            StreamTrim::MaxLen(max_len) => self.len().saturating_sub(max_len),
+            StreamTrim::MinId(min_id) => self.entries.range(..min_id).count(),
+        };
+
+        let evicted = excess.min(limit.unwrap_or(usize::MAX));
+
+        for _ in 0..evicted {
+            self.entries.pop_first();
+        }
+
+        evicted
+    }
+
+    /// Up to `count` entries with IDs in `range`, from the newest if `rev`
+    pub fn range(
+        &self,
+        range: RangeInclusive<StreamId>,
+        count: usize,
+        rev: bool,
+    ) -> Vec<StreamEntry> {
+        if range.start() > range.end() {
+            return vec![];
+        }
+
+        let entries = self.entries.range(range);
+        let to_entry = |(id, fields): (&StreamId, &Vec<(Bytes, Bytes)>)| (*id, fields.clone());
+
+        if rev {
+            entries.rev().take(count).map(to_entry).collect()
+        } else {
+            entries.take(count).map(to_entry).collect()
+        }
+    }
+
+    /// Deletes the entries with `ids`, returning how many there were. The
+    /// last ID stays, so new IDs still have to be greater than it.
+    pub fn remove(&mut self, ids: &[StreamId]) -> usize {
+        let mut removed = 0;
+
+        for id in ids {
+            if self.entries.remove(id).is_some() {
+                self.max_deleted_id = self.max_deleted_id.max(*id);
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+}
+
+impl Db {
+    /// The stream at `key`, failing if the key holds another type
+    pub fn get_stream(&mut self, key: &[u8]) -> Result<Option<&mut Stream>, StoreError> {
+        self.get(key)
+            .map(|item| match &mut item.value {
+                Value::Stream(stream) => Ok(stream),
+                _ => Err(StoreError::WrongType),
+            })
+            .transpose()
+    }
+
+    /// Replaces each `$` of an `XREAD` with the last ID of the stream at the
+    /// matching key, or `0-0` if there's no stream
+    pub fn resolve_read_op(
+        &mut self,
+        keys: &[Key],
+        op: StreamReadOp,
+    ) -> Result<StreamReadOp, StoreError> {
+        let StreamReadOp::After(after) = op else {
+            return Ok(op);
+        };
+
+        let after = keys
+            .iter()
+            .zip(after)
+            .map(|(key, after)| match after {
+                Some(after) => Ok(Some(after)),
+                None => Ok(Some(
+                    self.get_stream(key)?
+                        .map_or(StreamId::MIN, |stream| stream.last_id()),
+                )),
+            })
+            .collect::<Result<_, StoreError>>()?;
+
+        Ok(StreamReadOp::After(after))
+    }
+
+    /// Reads up to `count` entries of each of `keys` as `op` says. Streams
+    /// with nothing new to read are left out.
+    pub fn read_streams(
+        &mut self,
+        keys: &[Key],
+        op: &StreamReadOp,
+        count: usize,
+    ) -> Result<StreamsRead, StoreError> {
+        let after = match op {
+            StreamReadOp::After(after) => after,
+            StreamReadOp::Group {
+                group,
+                consumer,
+                after,
+                no_ack,
+            } => return self.read_group(keys, group, consumer, after, count, *no_ack),
+        };
+
+        let mut read = vec![];
+
+        for (key, after) in keys.iter().zip(after) {
+            // An unresolved `$` has nothing after it yet
+            let Some(start) = after.and_then(|after| after.next()) else {
+                continue;
+            };
+
+            let Some(stream) = self.get_stream(key)? else {
+                continue;
+            };
+
+            let entries = stream.range(start..=StreamId::MAX, count, false);
+
+            if !entries.is_empty() {
+                let entries = entries
+                    .into_iter()
+                    .map(|(id, fields)| (id, Some(fields)))
+                    .collect();
+
+                read.push((key.clone(), entries));
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+impl Store {
+    /// Appends an entry to the stream at `key`, and then trims the stream.
+    /// The stream is created if needed, unless `no_create` is given, in
+    /// which case `None` is returned.
+    pub fn xadd(
+        &mut self,
+        key: Bytes,
+        id: NewStreamId,
+        fields: Vec<(Bytes, Bytes)>,
+        no_create: bool,
+        trim: Option<(StreamTrim, Option<usize>)>,
+    ) -> Result<Option<StreamId>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let id = match db.get_stream(&key)? {
+            Some(stream) => {
+                let id = stream.add(id, fields)?;
+
+                db.stream_waiters.wake(&key);
+                id
+            }
+            None if no_create => return Ok(None),
+            None => {
+                let mut stream = Stream::default();
+                let id = stream.add(id, fields)?;
+
+                db.insert(key.clone(), StoreItem::new(Value::Stream(stream), None));
+                id
+            }
+        };
+
+        if let Some((trim, limit)) = trim {
+            if let Some(stream) = db.get_stream(&key)? {
+                stream.trim(trim, limit);
+            }
+        }
+
+        Ok(Some(id))
+    }
+
+    pub fn xrange(
+        &self,
+        key: &[u8],
+        range: RangeInclusive<StreamId>,
+        count: usize,
+        rev: bool,
+    ) -> Result<Vec<StreamEntry>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db
+            .get_stream(key)?
+            .map_or(vec![], |stream| stream.range(range, count, rev)))
+    }
+
+    pub fn xlen(&self, key: &[u8]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.get_stream(key)?.map_or(0, |stream| stream.len()))
+    }
+
+    pub fn xdel(&mut self, key: &[u8], ids: &[StreamId]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.get_stream(key)?.map_or(0, |stream| stream.remove(ids)))
+    }
+
+    pub fn xtrim(
+        &mut self,
+        key: &[u8],
+        trim: StreamTrim,
+        limit: Option<usize>,
+    ) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db
+            .get_stream(key)?
+            .map_or(0, |stream| stream.trim(trim, limit)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    #[test]
+    fn new_ids() {
+        let mut stream = Stream::default();
+
+        assert!(matches!(
+            stream.add(NewStreamId::Explicit(id(0, 0)), vec![]),
+            Err(StoreError::StreamIdZero)
+        ));
+        assert_eq!(
+            stream.add(NewStreamId::AutoSeq(0), vec![]).unwrap(),
+            id(0, 1)
+        );
+        assert_eq!(
+            stream.add(NewStreamId::AutoSeq(0), vec![]).unwrap(),
+            id(0, 2)
+        );
+        assert_eq!(
+            stream.add(NewStreamId::AutoSeq(5), vec![]).unwrap(),
+            id(5, 0)
+        );
+        assert!(matches!(
+            stream.add(NewStreamId::Explicit(id(5, 0)), vec![]),
+            Err(StoreError::StreamIdTooSmall)
+        ));
+        assert!(stream.add(NewStreamId::Auto, vec![]).unwrap().ms > 5);
+        assert_eq!(stream.len(), 4);
+
+        assert_eq!(stream.trim(StreamTrim::MaxLen(2), None), 2);
+        assert_eq!(
+            stream.range(StreamId::MIN..=StreamId::MAX, 10, false)[0].0,
+            id(5, 0)
+        );
+    }
+}
@@ -0,0 +1,1115 @@
+use bytes::Bytes;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::RangeInclusive,
+};
+
+use super::{ReadEntry, Stream, StreamEntry, StreamId};
+use crate::store::{db::Db, now_millis, Key, Store, StoreError, StoreItem, StreamsRead, Value};
+
+/// An entry delivered to a consumer that hasn't been acknowledged yet
+#[derive(Clone, Debug)]
+pub struct PendingEntry {
+    pub consumer: Bytes,
+    /// When the entry was last delivered, in milliseconds since the epoch
+    pub delivery_time: u128,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug)]
+struct Consumer {
+    /// The IDs of the entries pending for the consumer
+    pending: BTreeSet<StreamId>,
+    /// When the consumer last tried to read or claim entries
+    seen_time: u128,
+    /// When the consumer last got entries, if ever
+    active_time: Option<u128>,
+}
+
+/// A consumer group of a stream, which delivers each entry to one of its
+/// consumers and keeps track of it until it's acknowledged
+#[derive(Debug)]
+pub struct ConsumerGroup {
+    last_delivered: StreamId,
+    /// The number of entries the group has read, if it can be counted
+    entries_read: Option<u64>,
+    /// The entries delivered and not acknowledged yet, of every consumer
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<Bytes, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered: StreamId, entries_read: Option<u64>) -> Self {
+        Self {
+            last_delivered,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    /// The consumer called `name`, which is created if needed, noting that
+    /// it was just seen
+    fn seen(&mut self, name: &Bytes, now: u128) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.clone())
+            .or_insert_with(|| Consumer {
+                pending: BTreeSet::new(),
+                seen_time: now,
+                active_time: None,
+            });
+
+        consumer.seen_time = now;
+        consumer
+    }
+
+    /// Makes `consumer`, which has to exist, the owner of the pending entry
+    /// `id`, adding the entry to the group's pending entries if needed
+    fn assign(&mut self, id: StreamId, consumer: &Bytes, now: u128) -> &mut PendingEntry {
+        let entry = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.clone(),
+            delivery_time: now,
+            delivery_count: 1,
+        });
+
+        if entry.consumer != consumer {
+            if let Some(previous) = self.consumers.get_mut(&entry.consumer) {
+                previous.pending.remove(&id);
+            }
+
+            entry.consumer = consumer.clone();
+        }
+
+        if let Some(consumer) = self.consumers.get_mut(consumer) {
+            consumer.pending.insert(id);
+            consumer.active_time = Some(now);
+        }
+
+        entry
+    }
+
+    /// Drops the pending entry `id`, returning whether there was one
+    fn acknowledge(&mut self, id: &StreamId) -> bool {
+        let Some(entry) = self.pending.remove(id) else {
+            return false;
+        };
+
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(id);
+        }
+
+        true
+    }
+
+    /// Claims the pending entry `id` for `consumer`, as `XCLAIM` and
+    /// `XAUTOCLAIM` do
+    fn claim(
+        &mut self,
+        id: StreamId,
+        claim: &Claim,
+        delivery_time: u128,
+        retry_count: Option<u64>,
+    ) {
+        let entry = self.assign(id, &claim.consumer, now_millis());
+
+        entry.delivery_time = delivery_time;
+
+        match retry_count {
+            Some(retry_count) => entry.delivery_count = retry_count,
+            None if !claim.just_id => entry.delivery_count += 1,
+            None => {}
+        }
+    }
+
+    /// Whether the pending entry `id` has been idle for at least `min_idle`
+    /// milliseconds
+    fn is_idle(&self, id: &StreamId, min_idle: u128, now: u128) -> bool {
+        self.pending
+            .get(id)
+            .is_none_or(|entry| now.saturating_sub(entry.delivery_time) >= min_idle)
+    }
+}
+
+/// A consumer claiming the pending entries of its group, for `XCLAIM` and
+/// `XAUTOCLAIM`
+pub struct Claim {
+    pub group: Bytes,
+    pub consumer: Bytes,
+    /// How long an entry has to have been idle to be claimed, in milliseconds
+    pub min_idle: u128,
+    /// Replies with just the IDs, and leaves the delivery counts alone
+    pub just_id: bool,
+}
+
+/// The options of `XCLAIM`
+#[derive(Default)]
+pub struct ClaimOptions {
+    /// When the claimed entries count as delivered, instead of now
+    pub delivery_time: Option<u128>,
+    pub retry_count: Option<u64>,
+    /// Claims entries that aren't pending, as long as they exist
+    pub force: bool,
+    /// Moves the group's last delivered ID forward to this one
+    pub last_id: Option<StreamId>,
+}
+
+/// The pending entries to list with `XPENDING`
+pub struct PendingRange {
+    pub range: RangeInclusive<StreamId>,
+    pub count: usize,
+    pub consumer: Option<Bytes>,
+    /// How long an entry has to have been idle to be listed, in milliseconds
+    pub min_idle: u128,
+}
+
+/// The reply of `XPENDING` without a range
+pub struct PendingSummary {
+    pub count: usize,
+    /// The smallest and greatest pending IDs
+    pub bounds: Option<(StreamId, StreamId)>,
+    /// The number of entries pending for each consumer with any
+    pub consumers: Vec<(Bytes, usize)>,
+}
+
+/// A stream as `XINFO STREAM` reports it
+pub struct StreamInfo {
+    pub length: usize,
+    pub last_id: StreamId,
+    pub max_deleted_id: StreamId,
+    pub entries_added: u64,
+    pub first_id: StreamId,
+    pub groups: usize,
+    pub first_entry: Option<StreamEntry>,
+    pub last_entry: Option<StreamEntry>,
+}
+
+/// A consumer group as `XINFO GROUPS` reports it
+pub struct GroupInfo {
+    pub name: Bytes,
+    pub consumers: usize,
+    pub pending: usize,
+    pub last_delivered: StreamId,
+    pub entries_read: Option<u64>,
+    /// The number of entries left for the group to read, if it's known
+    pub lag: Option<u64>,
+}
+
+/// A consumer as `XINFO CONSUMERS` reports it
+pub struct ConsumerInfo {
+    pub name: Bytes,
+    pub pending: usize,
+    /// The milliseconds since the consumer was last seen
+    pub idle: u128,
+    /// The milliseconds since the consumer last got entries, if ever
+    pub inactive: Option<u128>,
+}
+
+impl Stream {
+    /// Runs `f` on the group called `name`, which is taken out of the
+    /// stream meanwhile, so that `f` can look at the stream's entries too
+    fn with_group<T>(
+        &mut self,
+        name: &[u8],
+        f: impl FnOnce(&mut Stream, &mut ConsumerGroup) -> T,
+    ) -> Option<T> {
+        let (name, mut group) = self.groups.remove_entry(name)?;
+
+        let result = f(self, &mut group);
+        self.groups.insert(name, group);
+
+        Some(result)
+    }
+
+    /// Whether an entry at or after `id` was deleted, in which case the
+    /// entries read past `id` can't be counted
+    fn has_tombstones_from(&self, id: StreamId) -> bool {
+        self.len() > 0 && self.max_deleted_id != StreamId::MIN && self.max_deleted_id >= id
+    }
+
+    /// The number of entries added up to and including `id`, if it can be
+    /// worked out, the way Redis estimates it
+    fn entries_added_until(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 || (self.len() == 0 && id <= self.last_id) {
+            return Some(self.entries_added);
+        }
+
+        if id == self.last_id {
+            return Some(self.entries_added);
+        }
+
+        if id > self.last_id {
+            return None;
+        }
+
+        let first_id = self.first_id();
+
+        // Without deletions past the first entry, the entries are contiguous
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first_id {
+            let len = self.len() as u64;
+
+            if id < first_id {
+                return Some(self.entries_added - len);
+            }
+
+            if id == first_id {
+                return Some(self.entries_added - len + 1);
+            }
+        }
+
+        None
+    }
+
+    fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+
+        let entries_read = match group.entries_read {
+            Some(read) if !self.has_tombstones_from(group.last_delivered) => Some(read),
+            _ => self.entries_added_until(group.last_delivered),
+        };
+
+        entries_read.map(|read| self.entries_added.saturating_sub(read))
+    }
+
+    /// Creates a group that has been delivered everything up to `id`, where
+    /// `None` is the last ID
+    fn create_group(
+        &mut self,
+        name: Bytes,
+        id: Option<StreamId>,
+        entries_read: Option<u64>,
+    ) -> Result<(), StoreError> {
+        if self.groups.contains_key(&name) {
+            return Err(StoreError::BusyGroup);
+        }
+
+        let last_delivered = id.unwrap_or(self.last_id);
+        self.groups
+            .insert(name, ConsumerGroup::new(last_delivered, entries_read));
+
+        Ok(())
+    }
+
+    /// Reads the entries of the group called `name` for `consumer`, as
+    /// `StreamReadOp::Group` describes
+    fn read_group(
+        &mut self,
+        name: &[u8],
+        consumer: &Bytes,
+        after: Option<StreamId>,
+        count: usize,
+        no_ack: bool,
+    ) -> Option<Vec<ReadEntry>> {
+        self.with_group(name, |stream, group| {
+            let now = now_millis();
+            let pending = &group.seen(consumer, now).pending;
+
+            // Reading the consumer's history changes nothing
+            if let Some(after) = after {
+                let ids: Vec<_> = match after.next() {
+                    Some(start) => pending.range(start..).take(count).copied().collect(),
+                    None => vec![],
+                };
+
+                return ids
+                    .into_iter()
+                    .map(|id| (id, stream.entries.get(&id).cloned()))
+                    .collect();
+            }
+
+            let Some(start) = group.last_delivered.next() else {
+                return vec![];
+            };
+
+            let entries = stream.range(start..=StreamId::MAX, count, false);
+
+            for (id, _) in &entries {
+                group.entries_read = match group.entries_read {
+                    Some(read) if !stream.has_tombstones_from(*id) => Some(read + 1),
+                    _ => stream.entries_added_until(*id),
+                };
+                group.last_delivered = *id;
+
+                if no_ack {
+                    group.seen(consumer, now).active_time = Some(now);
+                } else {
+                    let entry = group.assign(*id, consumer, now);
+
+                    entry.delivery_time = now;
+                    entry.delivery_count = 1;
+                }
+            }
+
+            entries
+                .into_iter()
+                .map(|(id, fields)| (id, Some(fields)))
+                .collect()
+        })
+    }
+
+    /// Claims `ids` as `XCLAIM` does. Pending entries that were deleted from
+    /// the stream are dropped.
+    fn claim(
+        &mut self,
+        claim: &Claim,
+        ids: &[StreamId],
+        options: &ClaimOptions,
+    ) -> Option<Vec<StreamEntry>> {
+        self.with_group(&claim.group, |stream, group| {
+            let now = now_millis();
+            group.seen(&claim.consumer, now);
+
+            if let Some(last_id) = options.last_id {
+                group.last_delivered = group.last_delivered.max(last_id);
+            }
+
+            let mut claimed = vec![];
+
+            for &id in ids {
+                let Some(fields) = stream.entries.get(&id) else {
+                    group.acknowledge(&id);
+                    continue;
+                };
+
+                if !options.force && !group.pending.contains_key(&id) {
+                    continue;
+                }
+
+                if !group.is_idle(&id, claim.min_idle, now) {
+                    continue;
+                }
+
+                let delivery_time = options.delivery_time.unwrap_or(now);
+                group.claim(id, claim, delivery_time, options.retry_count);
+                claimed.push((id, fields.clone()));
+            }
+
+            claimed
+        })
+    }
+
+    /// Claims up to `count` idle pending entries from `start` on, as
+    /// `XAUTOCLAIM` does. Returns the ID to continue from, the claimed
+    /// entries, and the IDs of the pending entries that were deleted from the
+    /// stream, which are dropped.
+    fn auto_claim(
+        &mut self,
+        claim: &Claim,
+        start: StreamId,
+        count: usize,
+    ) -> Option<(StreamId, Vec<StreamEntry>, Vec<StreamId>)> {
+        self.with_group(&claim.group, |stream, group| {
+            let now = now_millis();
+            group.seen(&claim.consumer, now);
+
+            // Like Redis, no more than ten times `count` entries are looked at
+            let attempts = count.saturating_mul(10);
+            let mut ids = group
+                .pending
+                .range(start..)
+                .map(|(id, _)| *id)
+                .take(attempts.saturating_add(1))
+                .collect::<Vec<_>>()
+                .into_iter();
+
+            let mut claimed = vec![];
+            let mut deleted = vec![];
+
+            for id in ids.by_ref().take(attempts) {
+                let Some(fields) = stream.entries.get(&id) else {
+                    group.acknowledge(&id);
+                    deleted.push(id);
+                    continue;
+                };
+
+                if !group.is_idle(&id, claim.min_idle, now) {
+                    continue;
+                }
+
+                group.claim(id, claim, now, None);
+                claimed.push((id, fields.clone()));
+
+                if claimed.len() == count {
+                    break;
+                }
+            }
+
+            (ids.next().unwrap_or(StreamId::MIN), claimed, deleted)
+        })
+    }
+
+    fn info(&self) -> StreamInfo {
+        let entry = |(id, fields): (&StreamId, &Vec<(Bytes, Bytes)>)| (*id, fields.clone());
+
+        StreamInfo {
+            length: self.len(),
+            last_id: self.last_id,
+            max_deleted_id: self.max_deleted_id,
+            entries_added: self.entries_added,
+            first_id: self.first_id(),
+            groups: self.groups.len(),
+            first_entry: self.entries.first_key_value().map(entry),
+            last_entry: self.entries.last_key_value().map(entry),
+        }
+    }
+}
+
+/// The error for a missing consumer group, or a missing stream
+fn no_group(key: &[u8], group: &[u8], context: &'static str) -> StoreError {
+    StoreError::NoKeyOrGroup {
+        key: String::from_utf8_lossy(key).into_owned(),
+        group: String::from_utf8_lossy(group).into_owned(),
+        context,
+    }
+}
+
+impl Db {
+    /// The stream at `key` for the `XGROUP` subcommands, which fail if there
+    /// isn't one
+    fn group_stream(&mut self, key: &[u8]) -> Result<&mut Stream, StoreError> {
+        self.get_stream(key)?.ok_or(StoreError::GroupKeyMissing)
+    }
+
+    /// The group called `group` of the stream at `key`, for the `XGROUP`
+    /// subcommands
+    fn group(&mut self, key: &[u8], group: &[u8]) -> Result<&mut ConsumerGroup, StoreError> {
+        self.group_stream(key)?
+            .groups
+            .get_mut(group)
+            .ok_or_else(|| StoreError::NoGroup {
+                key: String::from_utf8_lossy(key).into_owned(),
+                group: String::from_utf8_lossy(group).into_owned(),
+            })
+    }
+
+    /// Reads each of `keys` as `consumer` of `group`. Every group has to
+    /// exist before anything is read.
+    pub fn read_group(
+        &mut self,
+        keys: &[Key],
+        group: &[u8],
+        consumer: &Bytes,
+        after: &[Option<StreamId>],
+        count: usize,
+        no_ack: bool,
+    ) -> Result<StreamsRead, StoreError> {
+        let context = " in XREADGROUP with GROUP option";
+
+        for key in keys {
+            if !self
+                .get_stream(key)?
+                .is_some_and(|stream| stream.groups.contains_key(group))
+            {
+                return Err(no_group(key, group, context));
+            }
+        }
+
+        let mut read = vec![];
+
+        for (key, after) in keys.iter().zip(after) {
+            let entries = self
+                .get_stream(key)?
+                .and_then(|stream| stream.read_group(group, consumer, *after, count, no_ack))
+                .ok_or_else(|| no_group(key, group, context))?;
+
+            // A consumer's history is replied with even if it's empty
+            if after.is_some() || !entries.is_empty() {
+                read.push((key.clone(), entries));
+            }
+        }
+
+        Ok(read)
+    }
+
+    /// The stream at `key` and its group called `group`, for the commands
+    /// that fail with `NOGROUP` if either is missing
+    fn stream_with_group(&mut self, key: &[u8], group: &[u8]) -> Result<&mut Stream, StoreError> {
+        match self.get_stream(key)? {
+            Some(stream) if stream.groups.contains_key(group) => Ok(stream),
+            _ => Err(no_group(key, group, "")),
+        }
+    }
+}
+
+impl Store {
+    /// Creates a consumer group, as `XGROUP CREATE` does. The stream is
+    /// created if it doesn't exist and `make_stream` is given.
+    pub fn xgroup_create(
+        &mut self,
+        key: Bytes,
+        group: Bytes,
+        id: Option<StreamId>,
+        make_stream: bool,
+        entries_read: Option<u64>,
+    ) -> Result<(), StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        if make_stream && db.get_stream(&key)?.is_none() {
+            let stream = Value::Stream(Stream::default());
+            db.insert(key.clone(), StoreItem::new(stream, None));
+        }
+
+        db.group_stream(&key)?.create_group(group, id, entries_read)
+    }
+
+    /// Sets the last ID delivered to a group, where `None` is the last ID of
+    /// the stream
+    pub fn xgroup_setid(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        id: Option<StreamId>,
+        entries_read: Option<u64>,
+    ) -> Result<(), StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let last_id = db.group_stream(key)?.last_id;
+        let group = db.group(key, group)?;
+
+        group.last_delivered = id.unwrap_or(last_id);
+        group.entries_read = entries_read;
+
+        Ok(())
+    }
+
+    pub fn xgroup_destroy(&mut self, key: &[u8], group: &[u8]) -> Result<bool, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.group_stream(key)?.groups.remove(group).is_some())
+    }
+
+    /// Adds a consumer to a group, returning whether it's new
+    pub fn xgroup_create_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &Bytes,
+    ) -> Result<bool, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let group = db.group(key, group)?;
+        let created = !group.consumers.contains_key(consumer);
+
+        group.seen(consumer, now_millis());
+
+        Ok(created)
+    }
+
+    /// Removes a consumer from a group along with its pending entries,
+    /// returning how many of those there were
+    pub fn xgroup_del_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+    ) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let group = db.group(key, group)?;
+
+        let Some(consumer) = group.consumers.remove(consumer) else {
+            return Ok(0);
+        };
+
+        for id in &consumer.pending {
+            group.pending.remove(id);
+        }
+
+        Ok(consumer.pending.len())
+    }
+
+    /// Acknowledges the pending entries with `ids`, returning how many of
+    /// them were pending
+    pub fn xack(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        ids: &[StreamId],
+    ) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(group) = db
+            .get_stream(key)?
+            .and_then(|stream| stream.groups.get_mut(group))
+        else {
+            return Ok(0);
+        };
+
+        Ok(ids.iter().filter(|id| group.acknowledge(id)).count())
+    }
+
+    pub fn xpending_summary(&self, key: &[u8], group: &[u8]) -> Result<PendingSummary, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let group = &db.stream_with_group(key, group)?.groups[group];
+
+        let bounds = group
+            .pending
+            .first_key_value()
+            .zip(group.pending.last_key_value())
+            .map(|((first, _), (last, _))| (*first, *last));
+
+        let consumers = group
+            .consumers
+            .iter()
+            .filter(|(_, consumer)| !consumer.pending.is_empty())
+            .map(|(name, consumer)| (name.clone(), consumer.pending.len()))
+            .collect();
+
+        Ok(PendingSummary {
+            count: group.pending.len(),
+            bounds,
+            consumers,
+        })
+    }
+
+    pub fn xpending(
+        &self,
+        key: &[u8],
+        group: &[u8],
+        range: &PendingRange,
+    ) -> Result<Vec<(StreamId, PendingEntry)>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let group = &db.stream_with_group(key, group)?.groups[group];
+
+        if range.range.start() > range.range.end() {
+            return Ok(vec![]);
+        }
+
+        let now = now_millis();
+
+        Ok(group
+            .pending
+            .range(range.range.clone())
+            .filter(|(_, entry)| {
+                range
+                    .consumer
+                    .as_ref()
+                    .is_none_or(|consumer| entry.consumer == consumer)
+            })
+            .filter(|(id, _)| group.is_idle(id, range.min_idle, now))
+            .take(range.count)
+            .map(|(id, entry)| (*id, entry.clone()))
+            .collect())
+    }
+
+    pub fn xclaim(
+        &mut self,
+        key: &[u8],
+        claim: &Claim,
+        ids: &[StreamId],
+        options: &ClaimOptions,
+    ) -> Result<Vec<StreamEntry>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let stream = db.stream_with_group(key, &claim.group)?;
+
+        Ok(stream
+            .claim(claim, ids, options)
+            .expect("The group was just checked"))
+    }
+
+    pub fn xautoclaim(
+        &mut self,
+        key: &[u8],
+        claim: &Claim,
+        start: StreamId,
+        count: usize,
+    ) -> Result<(StreamId, Vec<StreamEntry>, Vec<StreamId>), StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let stream = db.stream_with_group(key, &claim.group)?;
+
+        Ok(stream
+            .auto_claim(claim, start, count)
+            .expect("The group was just checked"))
+    }
+
+    pub fn xinfo_stream(&self, key: &[u8]) -> Result<StreamInfo, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let stream = db.get_stream(key)?.ok_or(StoreError::NoSuchKey)?;
+
+        Ok(stream.info())
+    }
+
+    pub fn xinfo_groups(&self, key: &[u8]) -> Result<Vec<GroupInfo>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let stream = db.get_stream(key)?.ok_or(StoreError::NoSuchKey)?;
+
+        Ok(stream
+            .groups
+            .iter()
+            .map(|(name, group)| GroupInfo {
+                name: name.clone(),
+                consumers: group.consumers.len(),
+                pending: group.pending.len(),
+                last_delivered: group.last_delivered,
+                entries_read: group.entries_read,
+                lag: stream.lag(group),
+            })
+            .collect())
+    }
+
+    pub fn xinfo_consumers(
+        &self,
+        key: &[u8],
+        group: &[u8],
+    ) -> Result<Vec<ConsumerInfo>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        db.get_stream(key)?.ok_or(StoreError::NoSuchKey)?;
+        let group = db.group(key, group)?;
+        let now = now_millis();
+
+        Ok(group
+            .consumers
+            .iter()
+            .map(|(name, consumer)| ConsumerInfo {
+                name: name.clone(),
+                pending: consumer.pending.len(),
+                idle: now.saturating_sub(consumer.seen_time),
+                inactive: consumer.active_time.map(|time| now.saturating_sub(time)),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::NewStreamId;
+
+    fn read(stream: &mut Stream, consumer: &'static str, after: Option<StreamId>) -> Vec<StreamId> {
+        stream
+            .read_group(b"group", &consumer.into(), after, 10, false)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn entries_are_delivered_and_claimed() {
+        let mut stream = Stream::default();
+        let fields = || vec![("field".into(), "value".into())];
+
+        let first = stream.add(NewStreamId::Auto, fields()).unwrap();
+        stream
+            .create_group("group".into(), Some(StreamId::MIN), None)
+            .unwrap();
+        let second = stream.add(NewStreamId::Auto, fields()).unwrap();
+
+        assert_eq!(read(&mut stream, "alice", None), vec![first, second]);
+        assert!(read(&mut stream, "bob", None).is_empty());
+        assert_eq!(stream.lag(&stream.groups[&b"group"[..]]), Some(0));
+
+        let claim = Claim {
+            group: "group".into(),
+            consumer: "bob".into(),
+            min_idle: 0,
+            just_id: false,
+        };
+        let claimed = stream
+            .claim(&claim, &[second], &ClaimOptions::default())
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+
+        // The deleted entry is still pending, but has no fields left
+        stream.remove(&[first]);
+        let history = stream
+            .read_group(b"group", &"alice".into(), Some(StreamId::MIN), 10, false)
+            .unwrap();
+        assert_eq!(history, vec![(first, None)]);
+        assert_eq!(read(&mut stream, "bob", Some(StreamId::MIN)), vec![second]);
+
+        let group = &stream.groups[&b"group"[..]];
+        assert_eq!(group.pending[&second].delivery_count, 2);
+        assert_eq!(group.pending[&second].consumer, "bob");
+
+        let (cursor, claimed, deleted) = stream.auto_claim(&claim, StreamId::MIN, 10).unwrap();
+        assert_eq!(
+            (cursor, claimed.len(), deleted),
+            (StreamId::MIN, 1, vec![first])
+        );
+    }
+
+    /// A store with a stream of `len` entries at `s`, with a group `g` that
+    /// hasn't been delivered any of them
+    fn stream_with_group(len: usize) -> (Store, Vec<StreamId>) {
+        let mut store = Store::default();
+
+        let ids = (0..len)
+            .map(|_| {
+                let fields = vec![("field".into(), "value".into())];
+                store
+                    .xadd("s".into(), NewStreamId::Auto, fields, false, None)
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect();
+
+        store
+            .xgroup_create("s".into(), "g".into(), Some(StreamId::MIN), false, None)
+            .unwrap();
+
+        (store, ids)
+    }
+
+    /// Reads up to `count` new entries of `g` as `consumer`
+    fn read_new(store: &mut Store, consumer: &'static str, count: usize) -> Vec<StreamId> {
+        let read = store
+            .lock()
+            .unwrap()
+            .read_group(&["s".into()], b"g", &consumer.into(), &[None], count, false)
+            .unwrap();
+
+        read.into_iter()
+            .flat_map(|(_, entries)| entries)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    fn group_info(store: &Store) -> GroupInfo {
+        store.xinfo_groups(b"s").unwrap().remove(0)
+    }
+
+    fn claim_as(consumer: &'static str, min_idle: u128) -> Claim {
+        Claim {
+            group: "g".into(),
+            consumer: consumer.into(),
+            min_idle,
+            just_id: false,
+        }
+    }
+
+    fn pending(store: &Store, consumer: Option<&'static str>, min_idle: u128) -> Vec<StreamId> {
+        let range = PendingRange {
+            range: StreamId::MIN..=StreamId::MAX,
+            count: 10,
+            consumer: consumer.map(Bytes::from),
+            min_idle,
+        };
+
+        store
+            .xpending(b"s", b"g", &range)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn set_id_with_entries_read() {
+        let (mut store, ids) = stream_with_group(5);
+        assert_eq!(group_info(&store).lag, Some(5));
+
+        store
+            .xgroup_setid(b"s", b"g", Some(ids[1]), Some(2))
+            .unwrap();
+        let info = group_info(&store);
+        assert_eq!(info.last_delivered, ids[1]);
+        assert_eq!((info.entries_read, info.lag), (Some(2), Some(3)));
+
+        assert_eq!(read_new(&mut store, "alice", 10), ids[2..]);
+        let info = group_info(&store);
+        assert_eq!((info.entries_read, info.lag), (Some(5), Some(0)));
+
+        // Without ENTRIESREAD, the count is worked out from the stream
+        store.xgroup_setid(b"s", b"g", None, None).unwrap();
+        let info = group_info(&store);
+        assert_eq!(info.last_delivered, ids[4]);
+        assert_eq!((info.entries_read, info.lag), (None, Some(0)));
+
+        let err = store
+            .xgroup_setid(b"s", b"missing", None, None)
+            .unwrap_err();
+        assert!(matches!(err, StoreError::NoGroup { .. }));
+        let err = store
+            .xgroup_setid(b"missing", b"g", None, None)
+            .unwrap_err();
+        assert!(matches!(err, StoreError::GroupKeyMissing));
+    }
+
+    #[test]
+    fn deleting_a_consumer_drops_its_pending_entries() {
+        let (mut store, ids) = stream_with_group(3);
+
+        assert_eq!(read_new(&mut store, "alice", 2), ids[..2]);
+        assert_eq!(read_new(&mut store, "bob", 1), ids[2..]);
+
+        assert_eq!(store.xgroup_del_consumer(b"s", b"g", b"alice").unwrap(), 2);
+        assert_eq!(store.xgroup_del_consumer(b"s", b"g", b"alice").unwrap(), 0);
+
+        let summary = store.xpending_summary(b"s", b"g").unwrap();
+        assert_eq!(summary.count, 1);
+        assert_eq!(summary.bounds, Some((ids[2], ids[2])));
+        assert_eq!(summary.consumers, vec![("bob".into(), 1)]);
+
+        // Alice's entries aren't pending anymore, so there's nothing to ack
+        assert_eq!(store.xack(b"s", b"g", &ids).unwrap(), 1);
+        assert_eq!(group_info(&store).consumers, 1);
+    }
+
+    #[test]
+    fn pending_entries_by_idle_time_and_consumer() {
+        let (mut store, ids) = stream_with_group(4);
+
+        assert_eq!(read_new(&mut store, "alice", 3), ids[..3]);
+        assert_eq!(read_new(&mut store, "bob", 1), ids[3..]);
+
+        // Make one entry of each consumer look like it was delivered a
+        // while ago
+        let options = ClaimOptions {
+            delivery_time: Some(now_millis() - 10_000),
+            ..Default::default()
+        };
+        store
+            .xclaim(b"s", &claim_as("alice", 0), &[ids[0]], &options)
+            .unwrap();
+        store
+            .xclaim(b"s", &claim_as("bob", 0), &[ids[3]], &options)
+            .unwrap();
+
+        assert_eq!(pending(&store, None, 5_000), [ids[0], ids[3]]);
+        assert_eq!(pending(&store, Some("alice"), 5_000), [ids[0]]);
+        assert_eq!(pending(&store, Some("alice"), 0), ids[..3]);
+        assert_eq!(pending(&store, Some("bob"), 0), [ids[3]]);
+        assert_eq!(pending(&store, Some("carol"), 0), []);
+    }
+
+    #[test]
+    fn claim_options() {
+        let (mut store, ids) = stream_with_group(4);
+        let entry = |store: &Store, id: StreamId| {
+            store
+                .lock()
+                .unwrap()
+                .get_stream(b"s")
+                .unwrap()
+                .unwrap()
+                .groups[&b"g"[..]]
+                .pending[&id]
+                .clone()
+        };
+        let force = ClaimOptions {
+            force: true,
+            ..Default::default()
+        };
+
+        assert_eq!(read_new(&mut store, "alice", 1), [ids[0]]);
+
+        // A recently delivered entry isn't idle enough
+        let claimed = store
+            .xclaim(b"s", &claim_as("bob", 60_000), &[ids[0]], &force)
+            .unwrap();
+        assert!(claimed.is_empty());
+
+        // An entry that was never delivered takes FORCE, and has to exist
+        let missing = ids[3].next().unwrap();
+        let options = ClaimOptions::default();
+        let claimed = store
+            .xclaim(b"s", &claim_as("bob", 0), &[ids[1]], &options)
+            .unwrap();
+        assert!(claimed.is_empty());
+        let claimed = store
+            .xclaim(b"s", &claim_as("bob", 0), &[ids[1], missing], &force)
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(entry(&store, ids[1]).consumer, "bob");
+        assert_eq!(entry(&store, ids[1]).delivery_count, 2);
+        assert_eq!(pending(&store, None, 0), ids[..2]);
+
+        let options = ClaimOptions {
+            retry_count: Some(7),
+            ..Default::default()
+        };
+        store
+            .xclaim(b"s", &claim_as("bob", 0), &[ids[0]], &options)
+            .unwrap();
+        assert_eq!(entry(&store, ids[0]).consumer, "bob");
+        assert_eq!(entry(&store, ids[0]).delivery_count, 7);
+
+        // JUSTID leaves the delivery count alone
+        let just_id = Claim {
+            just_id: true,
+            ..claim_as("alice", 0)
+        };
+        store
+            .xclaim(b"s", &just_id, &[ids[0]], &ClaimOptions::default())
+            .unwrap();
+        assert_eq!(entry(&store, ids[0]).consumer, "alice");
+        assert_eq!(entry(&store, ids[0]).delivery_count, 7);
+
+        // LASTID only ever moves the group forward
+        for (last_id, expected) in [(ids[2], ids[2]), (ids[1], ids[2])] {
+            let options = ClaimOptions {
+                last_id: Some(last_id),
+                ..Default::default()
+            };
+            store
+                .xclaim(b"s", &claim_as("bob", 0), &[], &options)
+                .unwrap();
+            assert_eq!(group_info(&store).last_delivered, expected);
+        }
+
+        assert_eq!(read_new(&mut store, "alice", 10), [ids[3]]);
+    }
+
+    #[test]
+    fn auto_claim_cursor() {
+        let (mut store, ids) = stream_with_group(12);
+        assert_eq!(read_new(&mut store, "alice", 12), ids);
+
+        // Nothing is idle enough, so the search gives up after ten times
+        // COUNT entries, and continues from the next one
+        let busy = claim_as("bob", 60_000);
+        let (cursor, claimed, _) = store.xautoclaim(b"s", &busy, StreamId::MIN, 1).unwrap();
+        assert_eq!((cursor, claimed.len()), (ids[10], 0));
+        let (cursor, claimed, _) = store.xautoclaim(b"s", &busy, cursor, 1).unwrap();
+        assert_eq!((cursor, claimed.len()), (StreamId::MIN, 0));
+
+        let (cursor, claimed, _) = store
+            .xautoclaim(b"s", &claim_as("bob", 0), StreamId::MIN, 2)
+            .unwrap();
+        assert_eq!(cursor, ids[2]);
+        assert_eq!(
+            claimed.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            ids[..2]
+        );
+
+        // Deleted entries are dropped, and count as attempts
+        store.xdel(b"s", &ids[2..4]).unwrap();
+        let (cursor, claimed, deleted) = store
+            .xautoclaim(b"s", &claim_as("bob", 0), cursor, 1)
+            .unwrap();
+        assert_eq!((cursor, claimed[0].0), (ids[5], ids[4]));
+        assert_eq!(deleted, ids[2..4]);
+        assert_eq!(pending(&store, Some("bob"), 0), [ids[0], ids[1], ids[4]]);
+    }
+
+    #[test]
+    fn lag_with_tombstones() {
+        let (mut store, ids) = stream_with_group(5);
+
+        assert_eq!(read_new(&mut store, "alice", 2), ids[..2]);
+        assert_eq!(group_info(&store).lag, Some(3));
+
+        // Deleting an entry that was read already changes nothing
+        store.xdel(b"s", &[ids[0]]).unwrap();
+        assert_eq!(group_info(&store).lag, Some(3));
+
+        // But one that wasn't makes the lag unknown
+        store.xdel(b"s", &[ids[3]]).unwrap();
+        assert_eq!(group_info(&store).lag, None);
+
+        // Until the group catches up with the end of the stream
+        assert_eq!(read_new(&mut store, "alice", 10), [ids[2], ids[4]]);
+        let info = group_info(&store);
+        assert_eq!((info.entries_read, info.lag), (Some(5), Some(0)));
+    }
+}
@@ -0,0 +1,54 @@
+use bytes::Bytes;
+use std::collections::VecDeque;
+
+use super::{hash::Hash, sampled::SampledSet, stream::Stream, zset::SortedSet, StoreError};
+
+/// A value in the store, which can be any of the Redis data types
+#[derive(Debug)]
+pub enum Value {
+    String(Bytes),
+    List(VecDeque<Bytes>),
+    Hash(Hash),
+    Set(SampledSet),
+    ZSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// The name of the type, as reported by `TYPE`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Whether the value is an empty collection. Strings are never empty.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Hash(hash) => hash.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::ZSet(zset) => zset.is_empty(),
+            Value::Stream(_) => false,
+        }
+    }
+
+    pub fn as_string(&self) -> Result<&Bytes, StoreError> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(StoreError::WrongType),
+        }
+    }
+}
+
+impl From<Bytes> for Value {
+    fn from(value: Bytes) -> Self {
+        Value::String(value)
+    }
+}
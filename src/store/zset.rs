@@ -0,0 +1,1365 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+
+use super::{db::Db, list::normalize_range, Store, StoreError, StoreItem, Value};
+
+/// The most levels a skiplist node can have, enough for 2^64 elements
+const MAX_LEVEL: usize = 32;
+
+/// The chance of a node having each level above the first, like Redis'
+/// `ZSKIPLIST_P` of 0.25
+const LEVEL_PROBABILITY: u64 = u64::MAX / 4;
+
+/// The index of the head node, which holds no element
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    forward: Option<usize>,
+    /// The number of elements the forward link skips over, counting the one
+    /// it points to. This is what makes ranks O(log n).
+    span: usize,
+}
+
+#[derive(Debug)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+impl Node {
+    /// Whether the node sorts before the element `(score, member)`
+    fn is_before(&self, score: f64, member: &[u8]) -> bool {
+        self.score < score || (self.score == score && self.member.as_ref() < member)
+    }
+}
+
+/// A range of elements, by score or lexicographically
+pub trait ZRange {
+    fn is_empty(&self) -> bool;
+    /// Whether the element is above the lower end of the range
+    fn above_min(&self, score: f64, member: &[u8]) -> bool;
+    /// Whether the element is below the upper end of the range
+    fn below_max(&self, score: f64, member: &[u8]) -> bool;
+}
+
+/// One end of a score range, e.g. `(1.5` or `-inf`
+#[derive(Clone, Copy)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct ScoreRange {
+    pub min: ScoreBound,
+    pub max: ScoreBound,
+}
+
+impl ZRange for ScoreRange {
+    fn is_empty(&self) -> bool {
+        self.min.score > self.max.score
+            || (self.min.score == self.max.score && (self.min.exclusive || self.max.exclusive))
+    }
+
+    fn above_min(&self, score: f64, _: &[u8]) -> bool {
+        if self.min.exclusive {
+            score > self.min.score
+        } else {
+            score >= self.min.score
+        }
+    }
+
+    fn below_max(&self, score: f64, _: &[u8]) -> bool {
+        if self.max.exclusive {
+            score < self.max.score
+        } else {
+            score <= self.max.score
+        }
+    }
+}
+
+/// One end of a lexicographical range, e.g. `[a`, `(a`, `-` or `+`
+#[derive(Clone)]
+pub enum LexBound {
+    NegInf,
+    PosInf,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+#[derive(Clone)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl ZRange for LexRange {
+    fn is_empty(&self) -> bool {
+        match (&self.min, &self.max) {
+            (LexBound::PosInf, _) | (_, LexBound::NegInf) => true,
+            (LexBound::NegInf, _) | (_, LexBound::PosInf) => false,
+            (LexBound::Inclusive(min), LexBound::Inclusive(max)) => min > max,
+            (LexBound::Inclusive(min) | LexBound::Exclusive(min), LexBound::Exclusive(max))
+            | (LexBound::Exclusive(min), LexBound::Inclusive(max)) => min >= max,
+        }
+    }
+
+    fn above_min(&self, _: f64, member: &[u8]) -> bool {
+        match &self.min {
+            LexBound::NegInf => true,
+            LexBound::PosInf => false,
+            LexBound::Inclusive(min) => member >= min.as_ref(),
+            LexBound::Exclusive(min) => member > min.as_ref(),
+        }
+    }
+
+    fn below_max(&self, _: f64, member: &[u8]) -> bool {
+        match &self.max {
+            LexBound::NegInf => false,
+            LexBound::PosInf => true,
+            LexBound::Inclusive(max) => member <= max.as_ref(),
+            LexBound::Exclusive(max) => member < max.as_ref(),
+        }
+    }
+}
+
+/// A skiplist ordered by score and then member, as in Redis. Nodes live in
+/// an arena and link to each other by index.
+#[derive(Debug)]
+struct SkipList {
+    nodes: Vec<Node>,
+    /// The indexes of deleted nodes, for reuse
+    free: Vec<usize>,
+    tail: Option<usize>,
+    level: usize,
+    len: usize,
+    rng_state: u64,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                MAX_LEVEL
+            ],
+        };
+
+        Self {
+            nodes: vec![head],
+            free: vec![],
+            tail: None,
+            level: 1,
+            len: 0,
+            rng_state: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+}
+
+impl SkipList {
+    fn forward(&self, node: usize, level: usize) -> Option<usize> {
+        self.nodes[node].levels[level].forward
+    }
+
+    fn span(&self, node: usize, level: usize) -> usize {
+        self.nodes[node].levels[level].span
+    }
+
+    /// A random level between 1 and `MAX_LEVEL`, where each level is a
+    /// quarter as likely as the one below
+    fn random_level(&mut self) -> usize {
+        let mut level = 1;
+
+        while level < MAX_LEVEL {
+            let mut x = self.rng_state;
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            self.rng_state = x;
+
+            if x >= LEVEL_PROBABILITY {
+                break;
+            }
+
+            level += 1;
+        }
+
+        level
+    }
+
+    /// The last node on each level that sorts before `(score, member)`,
+    /// along with its rank
+    fn find_predecessors(
+        &self,
+        score: f64,
+        member: &[u8],
+    ) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+
+            while let Some(next) = self.forward(x, i) {
+                if !self.nodes[next].is_before(score, member) {
+                    break;
+                }
+
+                rank[i] += self.span(x, i);
+                x = next;
+            }
+
+            update[i] = x;
+        }
+
+        (update, rank)
+    }
+
+    /// Inserts an element, which mustn't be in the list already
+    fn insert(&mut self, score: f64, member: Bytes) {
+        let (mut update, mut rank) = self.find_predecessors(score, &member);
+        let level = self.random_level();
+
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                level
+            ],
+        };
+
+        let new = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let prev = update[i];
+
+            self.nodes[new].levels[i] = Level {
+                forward: self.forward(prev, i),
+                span: self.span(prev, i) - (rank[0] - rank[i]),
+            };
+            self.nodes[prev].levels[i] = Level {
+                forward: Some(new),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+
+        // The levels above the new node now skip over one more element
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].levels[i].span += 1;
+        }
+
+        self.nodes[new].backward = (update[0] != HEAD).then_some(update[0]);
+
+        match self.forward(new, 0) {
+            Some(next) => self.nodes[next].backward = Some(new),
+            None => self.tail = Some(new),
+        }
+
+        self.len += 1;
+    }
+
+    /// Deletes an element. Returns whether it was found.
+    fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let (update, _) = self.find_predecessors(score, member);
+
+        let Some(node) = self.forward(update[0], 0) else {
+            return false;
+        };
+
+        if self.nodes[node].score != score || self.nodes[node].member != member {
+            return false;
+        }
+
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(prev, i) == Some(node) {
+                self.nodes[prev].levels[i] = Level {
+                    forward: self.forward(node, i),
+                    span: self.span(prev, i) + self.span(node, i) - 1,
+                };
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+
+        match self.forward(node, 0) {
+            Some(next) => self.nodes[next].backward = self.nodes[node].backward,
+            None => self.tail = self.nodes[node].backward,
+        }
+
+        while self.level > 1 && self.forward(HEAD, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+
+        self.nodes[node].member = Bytes::new();
+        self.nodes[node].levels.clear();
+        self.free.push(node);
+        self.len -= 1;
+
+        true
+    }
+
+    /// The 0-based rank of an element in the list
+    fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let node = &self.nodes[next];
+
+                if !(node.is_before(score, member)
+                    || (node.score == score && node.member == member))
+                {
+                    break;
+                }
+
+                rank += self.span(x, i);
+                x = next;
+            }
+
+            if x != HEAD && self.nodes[x].member == member {
+                return Some(rank - 1);
+            }
+        }
+
+        None
+    }
+
+    /// The node at a 0-based rank
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if traversed + self.span(x, i) > target {
+                    break;
+                }
+
+                traversed += self.span(x, i);
+                x = next;
+            }
+
+            if traversed == target {
+                return Some(x);
+            }
+        }
+
+        None
+    }
+
+    /// The first node in `range`, along with its rank
+    fn first_in(&self, range: &impl ZRange) -> Option<(usize, usize)> {
+        if range.is_empty() {
+            return None;
+        }
+
+        let mut rank = 0;
+        let mut x = HEAD;
+
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let node = &self.nodes[next];
+
+                if range.above_min(node.score, &node.member) {
+                    break;
+                }
+
+                rank += self.span(x, i);
+                x = next;
+            }
+        }
+
+        let first = self.forward(x, 0)?;
+        let node = &self.nodes[first];
+
+        range
+            .below_max(node.score, &node.member)
+            .then_some((first, rank))
+    }
+
+    /// The last node in `range`, along with its rank
+    fn last_in(&self, range: &impl ZRange) -> Option<(usize, usize)> {
+        if range.is_empty() {
+            return None;
+        }
+
+        let mut rank = 0;
+        let mut x = HEAD;
+
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let node = &self.nodes[next];
+
+                if !range.below_max(node.score, &node.member) {
+                    break;
+                }
+
+                rank += self.span(x, i);
+                x = next;
+            }
+        }
+
+        let node = &self.nodes[x];
+
+        (x != HEAD && range.above_min(node.score, &node.member)).then(|| (x, rank - 1))
+    }
+
+    /// The node after `node`, or before it if `rev`
+    fn step(&self, node: usize, rev: bool) -> Option<usize> {
+        if rev {
+            self.nodes[node].backward
+        } else {
+            self.forward(node, 0)
+        }
+    }
+
+    /// Up to `count` elements starting at `node`, walking backwards if `rev`
+    fn collect(&self, mut node: Option<usize>, rev: bool, count: usize) -> Vec<(Bytes, f64)> {
+        let mut elements = vec![];
+
+        while let Some(x) = node {
+            if elements.len() >= count {
+                break;
+            }
+
+            elements.push((self.nodes[x].member.clone(), self.nodes[x].score));
+            node = self.step(x, rev);
+        }
+
+        elements
+    }
+}
+
+/// A sorted set, ordering its members by score and then lexicographically.
+/// The scores are kept in a map as well, for O(1) lookups by member.
+#[derive(Debug, Default)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    list: SkipList,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Sets the score of `member`. Returns whether it was added.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old) if old == score => false,
+            Some(old) => {
+                self.list.remove(old, &member);
+                self.list.insert(score, member);
+                false
+            }
+            None => {
+                self.list.insert(score, member);
+                true
+            }
+        }
+    }
+
+    /// Removes `member`. Returns whether it was there.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        let Some(score) = self.scores.remove(member) else {
+            return false;
+        };
+
+        self.list.remove(score, member);
+
+        true
+    }
+
+    /// The 0-based rank of `member`, counting from the highest score if
+    /// `rev`
+    pub fn rank(&self, member: &[u8], rev: bool) -> Option<usize> {
+        let rank = self.list.rank(self.score(member)?, member)?;
+
+        Some(if rev { self.len() - 1 - rank } else { rank })
+    }
+
+    /// The element with a 0-based rank, in O(log n)
+    pub fn get_by_rank(&self, rank: usize) -> Option<(&Bytes, f64)> {
+        let node = &self.list.nodes[self.list.by_rank(rank)?];
+
+        Some((&node.member, node.score))
+    }
+
+    /// The elements with ranks from `start` to `stop`, counting from the
+    /// highest score if `rev`
+    pub fn range_by_rank(&self, start: usize, stop: usize, rev: bool) -> Vec<(Bytes, f64)> {
+        if start > stop || start >= self.len() {
+            return vec![];
+        }
+
+        let first = if rev {
+            self.list.by_rank(self.len() - 1 - start)
+        } else {
+            self.list.by_rank(start)
+        };
+
+        self.list.collect(first, rev, stop - start + 1)
+    }
+
+    /// Up to `count` elements in `range` after skipping `offset` of them,
+    /// from the highest score if `rev`
+    pub fn range_by(
+        &self,
+        range: &impl ZRange,
+        rev: bool,
+        offset: usize,
+        count: usize,
+    ) -> Vec<(Bytes, f64)> {
+        let first = if rev {
+            self.list.last_in(range)
+        } else {
+            self.list.first_in(range)
+        };
+
+        let Some((first, _)) = first else {
+            return vec![];
+        };
+
+        let mut node = Some(first);
+
+        for _ in 0..offset {
+            node = node.and_then(|x| self.list.step(x, rev));
+        }
+
+        let mut elements = vec![];
+
+        while let Some(x) = node {
+            let Node { member, score, .. } = &self.list.nodes[x];
+
+            if elements.len() >= count
+                || !range.above_min(*score, member)
+                || !range.below_max(*score, member)
+            {
+                break;
+            }
+
+            elements.push((member.clone(), *score));
+            node = self.list.step(x, rev);
+        }
+
+        elements
+    }
+
+    /// The number of elements in `range`, in O(log n)
+    pub fn count(&self, range: &impl ZRange) -> usize {
+        match (self.list.first_in(range), self.list.last_in(range)) {
+            (Some((_, first)), Some((_, last))) if first <= last => last - first + 1,
+            _ => 0,
+        }
+    }
+
+    /// Removes and returns up to `count` elements with the lowest scores, or
+    /// the highest if `max`
+    pub fn pop(&mut self, count: usize, max: bool) -> Vec<(Bytes, f64)> {
+        let popped = if max {
+            self.list.collect(self.list.tail, true, count)
+        } else {
+            self.list.collect(self.list.forward(HEAD, 0), false, count)
+        };
+
+        for (member, _) in &popped {
+            self.remove(member);
+        }
+
+        popped
+    }
+
+    /// Every element, ordered by score
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        let mut node = self.list.forward(HEAD, 0);
+
+        std::iter::from_fn(move || {
+            let x = node?;
+            node = self.list.forward(x, 0);
+            Some((&self.list.nodes[x].member, self.list.nodes[x].score))
+        })
+    }
+}
+
+/// What `ZRANGE` selects elements by
+pub enum ZRangeBy {
+    Rank(i64, i64),
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+/// The options of `ZADD`
+#[derive(Clone, Copy, Default)]
+pub struct ZAddFlags {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    /// Count the changed elements along with the added ones
+    pub ch: bool,
+    /// Add the score to the current one, like `ZINCRBY`
+    pub incr: bool,
+}
+
+/// How `ZUNIONSTORE` and `ZINTERSTORE` combine the scores of a member
+#[derive(Clone, Copy, Default)]
+pub enum Aggregate {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // Infinities of opposite signs add up to 0 rather than NaN
+            Aggregate::Sum => Some(a + b).filter(|x| !x.is_nan()).unwrap_or(0.0),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+impl Db {
+    /// The sorted set at `key`, failing if the key holds another type
+    pub fn get_zset(&mut self, key: &[u8]) -> Result<Option<&mut SortedSet>, StoreError> {
+        self.get(key)
+            .map(|item| match &mut item.value {
+                Value::ZSet(zset) => Ok(zset),
+                _ => Err(StoreError::WrongType),
+            })
+            .transpose()
+    }
+
+    /// The sorted set at `key`, which is created if needed
+    fn get_or_create_zset(&mut self, key: Bytes) -> Result<&mut SortedSet, StoreError> {
+        if self.get_zset(&key)?.is_none() {
+            self.insert(
+                key.clone(),
+                StoreItem::new(Value::ZSet(SortedSet::default()), None),
+            );
+        }
+
+        Ok(self
+            .get_zset(&key)?
+            .expect("The sorted set was just created"))
+    }
+
+    /// The elements of the sorted set at `key` selected by `by`, counting
+    /// from the highest score if `rev`. `limit` is an offset and a count,
+    /// where a negative count means all of them.
+    fn zrange(
+        &mut self,
+        key: &[u8],
+        by: &ZRangeBy,
+        rev: bool,
+        limit: Option<(i64, i64)>,
+    ) -> Result<Vec<(Bytes, f64)>, StoreError> {
+        let Some(zset) = self.get_zset(key)? else {
+            return Ok(vec![]);
+        };
+
+        let (offset, count) = match limit {
+            Some((offset, _)) if offset < 0 => return Ok(vec![]),
+            Some((offset, count)) => (
+                offset as usize,
+                usize::try_from(count).unwrap_or(usize::MAX),
+            ),
+            None => (0, usize::MAX),
+        };
+
+        Ok(match by {
+            ZRangeBy::Rank(start, stop) => match normalize_range(*start, *stop, zset.len()) {
+                Some((start, stop)) => zset.range_by_rank(start, stop, rev),
+                None => vec![],
+            },
+            ZRangeBy::Score(range) => zset.range_by(range, rev, offset, count),
+            ZRangeBy::Lex(range) => zset.range_by(range, rev, offset, count),
+        })
+    }
+
+    /// The elements of the sorted set at `key`, or of the set there with
+    /// every score being 1
+    fn zset_elements(&mut self, key: &[u8]) -> Result<Vec<(Bytes, f64)>, StoreError> {
+        Ok(match self.get(key).map(|item| &item.value) {
+            Some(Value::ZSet(zset)) => zset
+                .iter()
+                .map(|(member, score)| (member.clone(), score))
+                .collect(),
+            Some(Value::Set(set)) => set.keys().map(|member| (member.clone(), 1.0)).collect(),
+            Some(_) => return Err(StoreError::WrongType),
+            None => vec![],
+        })
+    }
+
+    /// Replaces whatever is at `destination` with a sorted set of
+    /// `elements`, or deletes it if there are none. Returns the size of the
+    /// new sorted set.
+    fn store_zset(&mut self, destination: Bytes, elements: Vec<(Bytes, f64)>) -> usize {
+        let mut zset = SortedSet::default();
+
+        for (member, score) in elements {
+            zset.insert(member, score);
+        }
+
+        let len = zset.len();
+
+        if zset.is_empty() {
+            self.remove(&destination);
+        } else {
+            self.insert(destination, StoreItem::new(Value::ZSet(zset), None));
+        }
+
+        len
+    }
+}
+
+impl Store {
+    /// Adds or updates the elements in `pairs` as `flags` allow. Returns
+    /// the number of elements added, or changed as well with `ch`, along
+    /// with the new score of the last element with `incr`. That score is
+    /// `None` if the flags didn't allow the update.
+    pub fn zadd(
+        &mut self,
+        key: Bytes,
+        pairs: Vec<(f64, Bytes)>,
+        flags: ZAddFlags,
+    ) -> Result<(usize, Option<f64>), StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let zset = db.get_or_create_zset(key.clone())?;
+
+        let mut added = 0;
+        let mut changed = 0;
+        let mut new_score = None;
+
+        for (score, member) in pairs {
+            new_score = None;
+
+            let Some(current) = zset.score(&member) else {
+                if !flags.xx {
+                    zset.insert(member, score);
+                    added += 1;
+                    new_score = Some(score);
+                }
+
+                continue;
+            };
+
+            if flags.nx {
+                continue;
+            }
+
+            let score = if flags.incr { current + score } else { score };
+
+            if score.is_nan() {
+                return Err(StoreError::ScoreNaN);
+            }
+
+            if (flags.gt && score <= current) || (flags.lt && score >= current) {
+                continue;
+            }
+
+            if score != current {
+                zset.insert(member, score);
+                changed += 1;
+            }
+
+            new_score = Some(score);
+        }
+
+        db.remove_if_empty(&key);
+
+        let count = if flags.ch { added + changed } else { added };
+
+        Ok((count, new_score))
+    }
+
+    /// Removes `members`, and the sorted set along with its last member.
+    /// Returns the number of members removed.
+    pub fn zrem(&mut self, key: &[u8], members: &[Bytes]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(zset) = db.get_zset(key)? else {
+            return Ok(0);
+        };
+
+        let removed = members.iter().filter(|member| zset.remove(member)).count();
+
+        db.remove_if_empty(key);
+
+        Ok(removed)
+    }
+
+    /// The score of each of `members`
+    pub fn zmscore(&self, key: &[u8], members: &[Bytes]) -> Result<Vec<Option<f64>>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(zset) = db.get_zset(key)? else {
+            return Ok(vec![None; members.len()]);
+        };
+
+        Ok(members.iter().map(|member| zset.score(member)).collect())
+    }
+
+    pub fn zcard(&self, key: &[u8]) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.get_zset(key)?.map_or(0, |zset| zset.len()))
+    }
+
+    /// The rank of `member` along with its score, counting from the highest
+    /// score if `rev`
+    pub fn zrank(
+        &self,
+        key: &[u8],
+        member: &[u8],
+        rev: bool,
+    ) -> Result<Option<(usize, f64)>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(zset) = db.get_zset(key)? else {
+            return Ok(None);
+        };
+
+        Ok(zset.rank(member, rev).zip(zset.score(member)))
+    }
+
+    pub fn zrange(
+        &self,
+        key: &[u8],
+        by: &ZRangeBy,
+        rev: bool,
+        limit: Option<(i64, i64)>,
+    ) -> Result<Vec<(Bytes, f64)>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        db.zrange(key, by, rev, limit)
+    }
+
+    /// Stores the elements `ZRANGE` would return at `destination`. Returns
+    /// the number of elements stored.
+    pub fn zrangestore(
+        &mut self,
+        destination: Bytes,
+        key: &[u8],
+        by: &ZRangeBy,
+        rev: bool,
+        limit: Option<(i64, i64)>,
+    ) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let elements = db.zrange(key, by, rev, limit)?;
+
+        Ok(db.store_zset(destination, elements))
+    }
+
+    /// The number of elements in `range`
+    pub fn zcount(&self, key: &[u8], range: &impl ZRange) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        Ok(db.get_zset(key)?.map_or(0, |zset| zset.count(range)))
+    }
+
+    /// Removes and returns up to `count` elements with the lowest scores, or
+    /// the highest if `max`
+    pub fn zpop(
+        &mut self,
+        key: &[u8],
+        count: usize,
+        max: bool,
+    ) -> Result<Vec<(Bytes, f64)>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let Some(zset) = db.get_zset(key)? else {
+            return Ok(vec![]);
+        };
+
+        let popped = zset.pop(count, max);
+
+        db.remove_if_empty(key);
+
+        Ok(popped)
+    }
+
+    /// Stores the union, or the intersection if `inter`, of the sorted sets
+    /// at `keys` at `destination`. Each set's scores are multiplied by its
+    /// weight and then combined with `aggregate`. Plain sets count as having
+    /// scores of 1. Returns the size of the result.
+    pub fn zcombine_store(
+        &mut self,
+        destination: Bytes,
+        keys: &[Bytes],
+        weights: &[f64],
+        aggregate: Aggregate,
+        inter: bool,
+    ) -> Result<usize, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let mut inputs = vec![];
+
+        for (i, key) in keys.iter().enumerate() {
+            let weight = weights.get(i).copied().unwrap_or(1.0);
+
+            let elements: HashMap<_, _> = db
+                .zset_elements(key)?
+                .into_iter()
+                .map(|(member, score)| {
+                    // 0 times infinity counts as 0
+                    let score = Some(score * weight).filter(|x| !x.is_nan()).unwrap_or(0.0);
+                    (member, score)
+                })
+                .collect();
+
+            inputs.push(elements);
+        }
+
+        let mut inputs = inputs.into_iter();
+        let mut result = inputs.next().unwrap_or_default();
+
+        for input in inputs {
+            if inter {
+                result = result
+                    .into_iter()
+                    .filter_map(|(member, score)| {
+                        let other = input.get(&member)?;
+                        Some((member, aggregate.apply(score, *other)))
+                    })
+                    .collect();
+            } else {
+                for (member, score) in input {
+                    result
+                        .entry(member)
+                        .and_modify(|current| *current = aggregate.apply(*current, score))
+                        .or_insert(score);
+                }
+            }
+        }
+
+        Ok(db.store_zset(destination, result.into_iter().collect()))
+    }
+
+    /// Picks `count` random elements. A negative `count` may pick the same
+    /// element more than once.
+    pub fn zrandmember(&mut self, key: &[u8], count: i64) -> Result<Vec<(Bytes, f64)>, StoreError> {
+        let mut db = self.0.lock().unwrap();
+
+        let len = db.get_zset(key)?.map_or(0, |zset| zset.len());
+        let ranks = db.random_positions(len, count);
+
+        let Some(zset) = db.get_zset(key)? else {
+            return Ok(vec![]);
+        };
+
+        Ok(ranks
+            .into_iter()
+            .filter_map(|rank| zset.get_by_rank(rank))
+            .map(|(member, score)| (member.clone(), score))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score_range(min: f64, max: f64) -> ScoreRange {
+        ScoreRange {
+            min: ScoreBound {
+                score: min,
+                exclusive: false,
+            },
+            max: ScoreBound {
+                score: max,
+                exclusive: false,
+            },
+        }
+    }
+
+    #[test]
+    fn ranks_follow_scores() {
+        let mut zset = SortedSet::default();
+
+        // Insert in a scrambled order, then move some members around
+        for i in 0..1000u64 {
+            let n = (i * 7919) % 1000;
+            zset.insert(format!("m{n}").into(), n as f64);
+        }
+
+        for n in (0..1000).step_by(3) {
+            zset.insert(format!("m{n}").into(), n as f64 + 0.5);
+        }
+
+        for n in (0..1000).step_by(10) {
+            zset.remove(format!("m{n}").as_bytes());
+        }
+
+        let members: Vec<_> = zset.iter().map(|(member, _)| member.clone()).collect();
+        assert_eq!(members.len(), zset.len());
+
+        for (rank, member) in members.iter().enumerate() {
+            assert_eq!(zset.rank(member, false), Some(rank));
+            assert_eq!(zset.range_by_rank(rank, rank, false)[0].0, member);
+        }
+
+        let range = score_range(100.0, 199.5);
+        let in_range = zset.range_by(&range, false, 0, usize::MAX);
+        assert_eq!(zset.count(&range), in_range.len());
+        assert_eq!(in_range.len(), 90);
+        assert_eq!(zset.range_by(&range, true, 5, 1), [in_range[84].clone()]);
+    }
+
+    #[test]
+    fn lex_ranges() {
+        let mut zset = SortedSet::default();
+
+        for member in ["a", "b", "c", "d"] {
+            zset.insert(member.into(), 0.0);
+        }
+
+        let range = LexRange {
+            min: LexBound::Exclusive("a".into()),
+            max: LexBound::Inclusive("c".into()),
+        };
+
+        let members: Vec<_> = zset
+            .range_by(&range, false, 0, usize::MAX)
+            .into_iter()
+            .map(|(member, _)| member)
+            .collect();
+
+        assert_eq!(members, ["b", "c"]);
+        assert_eq!(zset.count(&range), 2);
+    }
+
+    /// Checks the links and spans on every level of the list against its
+    /// elements, which should be `expected` in order
+    fn check_list(list: &SkipList, expected: &[(f64, Bytes)]) {
+        let mut ranks = HashMap::from([(HEAD, 0)]);
+        let mut node = list.forward(HEAD, 0);
+        let mut prev = None;
+
+        for (rank, (score, member)) in expected.iter().enumerate() {
+            let x = node.expect("The list is too short");
+            assert_eq!(
+                (list.nodes[x].score, &list.nodes[x].member),
+                (*score, member)
+            );
+            assert_eq!(list.nodes[x].backward, prev);
+
+            ranks.insert(x, rank + 1);
+            prev = Some(x);
+            node = list.forward(x, 0);
+        }
+
+        assert_eq!(node, None);
+        assert_eq!(list.tail, prev);
+        assert_eq!(list.len, expected.len());
+
+        for i in 0..list.level {
+            let mut x = HEAD;
+
+            while let Some(next) = list.forward(x, i) {
+                assert_eq!(list.span(x, i), ranks[&next] - ranks[&x], "level {i}");
+                x = next;
+            }
+        }
+
+        assert!(list.level == 1 || list.forward(HEAD, list.level - 1).is_some());
+    }
+
+    #[test]
+    fn spans_survive_random_updates() {
+        let mut zset = SortedSet::default();
+        let mut expected: Vec<(f64, Bytes)> = vec![];
+        let mut rng = 0x9e37_79b9_7f4a_7c15u64;
+
+        for step in 0..5000 {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+
+            // Few distinct scores, so that members break plenty of ties
+            let member = Bytes::from(format!("m{}", rng % 300));
+            let score = ((rng >> 20) % 20) as f64;
+
+            expected.retain(|(_, x)| *x != member);
+
+            if rng.is_multiple_of(3) {
+                zset.remove(&member);
+            } else {
+                zset.insert(member.clone(), score);
+                expected.push((score, member));
+            }
+
+            if step % 100 == 0 {
+                expected.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+                check_list(&zset.list, &expected);
+
+                for (rank, (_, member)) in expected.iter().enumerate() {
+                    assert_eq!(zset.rank(member, false), Some(rank));
+                    assert_eq!(zset.rank(member, true), Some(expected.len() - 1 - rank));
+                }
+            }
+        }
+    }
+
+    fn elements(pairs: &[(&'static str, f64)]) -> Vec<(Bytes, f64)> {
+        pairs
+            .iter()
+            .map(|&(member, score)| (member.into(), score))
+            .collect()
+    }
+
+    #[test]
+    fn zadd_flags() {
+        let mut store = Store::default();
+        let zadd = |store: &mut Store, score: f64, member: &'static str, flags: ZAddFlags| {
+            store.zadd("z".into(), vec![(score, member.into())], flags)
+        };
+        let all = |store: &Store| store.zrange(b"z", &ZRangeBy::Rank(0, -1), false, None);
+
+        let nx = ZAddFlags {
+            nx: true,
+            ..Default::default()
+        };
+        let xx = ZAddFlags {
+            xx: true,
+            ..Default::default()
+        };
+        let gt_ch = ZAddFlags {
+            gt: true,
+            ch: true,
+            ..Default::default()
+        };
+        let lt_ch = ZAddFlags {
+            lt: true,
+            ch: true,
+            ..Default::default()
+        };
+
+        // XX never adds, so the key isn't created either
+        assert_eq!(zadd(&mut store, 1.0, "a", xx).unwrap(), (0, None));
+        assert_eq!(store.key_type(b"z"), None);
+
+        assert_eq!(zadd(&mut store, 1.0, "a", nx).unwrap(), (1, Some(1.0)));
+        assert_eq!(zadd(&mut store, 5.0, "a", nx).unwrap(), (0, None));
+        assert_eq!(zadd(&mut store, 2.0, "a", xx).unwrap(), (0, Some(2.0)));
+
+        // GT and LT only update in their direction, but still add
+        assert_eq!(zadd(&mut store, 1.0, "a", gt_ch).unwrap(), (0, None));
+        assert_eq!(zadd(&mut store, 3.0, "a", gt_ch).unwrap(), (1, Some(3.0)));
+        assert_eq!(zadd(&mut store, 4.0, "a", lt_ch).unwrap(), (0, None));
+        assert_eq!(zadd(&mut store, 9.0, "b", lt_ch).unwrap(), (1, Some(9.0)));
+
+        // CH doesn't count an update to the same score
+        assert_eq!(zadd(&mut store, 3.0, "a", gt_ch).unwrap().0, 0);
+        assert_eq!(all(&store).unwrap(), elements(&[("a", 3.0), ("b", 9.0)]));
+
+        let incr = |flags: ZAddFlags| ZAddFlags {
+            incr: true,
+            ..flags
+        };
+
+        assert_eq!(
+            zadd(&mut store, 2.0, "a", incr(xx)).unwrap(),
+            (0, Some(5.0))
+        );
+        assert_eq!(zadd(&mut store, 2.0, "c", incr(xx)).unwrap(), (0, None));
+        assert_eq!(zadd(&mut store, 2.0, "a", incr(nx)).unwrap(), (0, None));
+        assert_eq!(
+            zadd(&mut store, 2.0, "c", incr(nx)).unwrap(),
+            (1, Some(2.0))
+        );
+        assert_eq!(zadd(&mut store, -1.0, "a", incr(gt_ch)).unwrap(), (0, None));
+        assert_eq!(
+            zadd(&mut store, -1.0, "a", incr(lt_ch)).unwrap(),
+            (1, Some(4.0))
+        );
+
+        // Adding -inf to +inf isn't a number
+        zadd(&mut store, f64::INFINITY, "a", xx).unwrap();
+        let err = zadd(&mut store, f64::NEG_INFINITY, "a", incr(xx)).unwrap_err();
+        assert!(matches!(err, StoreError::ScoreNaN));
+
+        assert_eq!(
+            all(&store).unwrap(),
+            elements(&[("c", 2.0), ("b", 9.0), ("a", f64::INFINITY)])
+        );
+    }
+
+    #[test]
+    fn ranges_with_limit_and_rev() {
+        let mut store = Store::default();
+        let pairs = (0..6)
+            .map(|i| (i as f64, Bytes::from(format!("m{i}"))))
+            .collect();
+        store.zadd("z".into(), pairs, ZAddFlags::default()).unwrap();
+
+        let members = |by: &ZRangeBy, rev: bool, limit: Option<(i64, i64)>| {
+            store
+                .zrange(b"z", by, rev, limit)
+                .unwrap()
+                .into_iter()
+                .map(|(member, _)| member)
+                .collect::<Vec<_>>()
+        };
+
+        let by_score = ZRangeBy::Score(ScoreRange {
+            min: ScoreBound {
+                score: 1.0,
+                exclusive: true,
+            },
+            max: ScoreBound {
+                score: f64::INFINITY,
+                exclusive: false,
+            },
+        });
+
+        assert_eq!(members(&by_score, false, None), ["m2", "m3", "m4", "m5"]);
+        assert_eq!(members(&by_score, false, Some((1, 2))), ["m3", "m4"]);
+        assert_eq!(members(&by_score, true, Some((1, 2))), ["m4", "m3"]);
+        assert_eq!(members(&by_score, true, Some((3, -1))), ["m2"]);
+        assert_eq!(members(&by_score, false, Some((4, 1))), Vec::<Bytes>::new());
+        assert_eq!(
+            members(&by_score, false, Some((-1, 1))),
+            Vec::<Bytes>::new()
+        );
+        assert_eq!(members(&by_score, false, Some((0, 0))), Vec::<Bytes>::new());
+
+        // Equal scores leave the members in lexicographical order
+        let mut store = Store::default();
+        let pairs = ["a", "b", "c", "d", "e"]
+            .into_iter()
+            .map(|member| (0.0, member.into()))
+            .collect();
+        store.zadd("z".into(), pairs, ZAddFlags::default()).unwrap();
+
+        let members = |by: &ZRangeBy, rev: bool, limit: Option<(i64, i64)>| {
+            store
+                .zrange(b"z", by, rev, limit)
+                .unwrap()
+                .into_iter()
+                .map(|(member, _)| member)
+                .collect::<Vec<_>>()
+        };
+
+        let by_lex = ZRangeBy::Lex(LexRange {
+            min: LexBound::Inclusive("b".into()),
+            max: LexBound::PosInf,
+        });
+
+        assert_eq!(members(&by_lex, false, Some((1, 2))), ["c", "d"]);
+        assert_eq!(members(&by_lex, true, Some((0, 2))), ["e", "d"]);
+        assert_eq!(members(&by_lex, true, Some((1, -5))), ["d", "c", "b"]);
+
+        let empty = ZRangeBy::Lex(LexRange {
+            min: LexBound::Exclusive("c".into()),
+            max: LexBound::Inclusive("c".into()),
+        });
+        assert_eq!(members(&empty, false, None), Vec::<Bytes>::new());
+    }
+
+    #[test]
+    fn combine_with_infinite_weights() {
+        let mut store = Store::default();
+        let zadd = |store: &mut Store, key: &'static str, pairs: &[(&'static str, f64)]| {
+            let pairs = pairs
+                .iter()
+                .map(|&(member, score)| (score, member.into()))
+                .collect();
+            store.zadd(key.into(), pairs, ZAddFlags::default()).unwrap();
+        };
+        let all = |store: &Store| {
+            store
+                .zrange(b"out", &ZRangeBy::Rank(0, -1), false, None)
+                .unwrap()
+        };
+
+        zadd(
+            &mut store,
+            "x",
+            &[("a", 1.0), ("b", 0.0), ("c", f64::INFINITY)],
+        );
+        zadd(&mut store, "y", &[("a", 2.0), ("c", f64::NEG_INFINITY)]);
+        let keys: Vec<Bytes> = vec!["x".into(), "y".into()];
+
+        // Zero times infinity is 0, and so is +inf plus -inf
+        let weights = [f64::INFINITY, 1.0];
+        let len = store
+            .zcombine_store("out".into(), &keys, &weights, Aggregate::Sum, false)
+            .unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(
+            all(&store),
+            elements(&[("b", 0.0), ("c", 0.0), ("a", f64::INFINITY)])
+        );
+
+        let weights = [1.0, f64::NEG_INFINITY];
+        store
+            .zcombine_store("out".into(), &keys, &weights, Aggregate::Max, true)
+            .unwrap();
+        assert_eq!(all(&store), elements(&[("a", 1.0), ("c", f64::INFINITY)]));
+
+        store
+            .zcombine_store("out".into(), &keys, &weights, Aggregate::Min, true)
+            .unwrap();
+        assert_eq!(
+            all(&store),
+            elements(&[("a", f64::NEG_INFINITY), ("c", f64::INFINITY)])
+        );
+
+        // An empty result deletes the destination
+        let keys: Vec<Bytes> = vec!["x".into(), "missing".into()];
+        let len = store
+            .zcombine_store("out".into(), &keys, &[], Aggregate::Sum, true)
+            .unwrap();
+        assert_eq!(len, 0);
+        assert_eq!(store.key_type(b"out"), None);
+    }
+}